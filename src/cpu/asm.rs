@@ -0,0 +1,414 @@
+// Two-pass assembler for the official 6502 instructions, in the syntax of
+// Easy6502 (https://skilldrick.github.io/easy6502/):
+//
+//         .org $0600          ; default origin
+//     start:
+//         LDA #<table         ; low byte of an address
+//         STA $10
+//         LDX table+1,Y
+//         BNE start
+//     table:
+//         .byte $01, 2, %11
+//         .word start
+//
+// Numbers are decimal, $hex or %binary. Operands known in the first pass
+// and below $100 use zero page addressing when the instruction has it,
+// forward references use absolute addressing.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::opcodes::{Op, OpCode, CPU_OPS_CODES};
+use crate::cpu::AddressingMode;
+
+pub const DEFAULT_ORIGIN: u16 = 0x0600;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AsmError {
+    /// 1-based line number in the source.
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+/// Bytes assembled from an `.org` onwards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Segment {
+    pub origin: u16,
+    pub bytes: Vec<u8>,
+    /// Line of its `.org`, 1 for the code before any.
+    pub line: usize,
+}
+
+/// Assembles `source`, returning one segment per `.org`.
+pub fn assemble(source: &str) -> Result<Vec<Segment>, AsmError> {
+    let mut labels = HashMap::new();
+    let mut items = vec![];
+
+    // First pass: parse, define labels and fix every instruction's size
+    let mut addr = DEFAULT_ORIGIN;
+    for (number, line) in source.lines().enumerate() {
+        let error = |message: String| AsmError {
+            line: number + 1,
+            message,
+        };
+        let mut text = line.split(';').next().unwrap().trim();
+        if let Some((label, rest)) = text.split_once(':') {
+            let label = label.trim();
+            if !is_identifier(label) {
+                return Err(error(format!("invalid label {}", label)));
+            }
+            if labels.insert(label.to_string(), addr).is_some() {
+                return Err(error(format!("label {} is already defined", label)));
+            }
+            text = rest.trim();
+        }
+        if text.is_empty() {
+            continue;
+        }
+        let item = parse(text, &labels).map_err(error)?;
+        if let Item::Org(origin) = item {
+            addr = origin;
+        } else {
+            addr = addr.wrapping_add(item.len());
+        }
+        items.push((number + 1, addr, item));
+    }
+
+    // Second pass: encode with every label known
+    let mut segments = vec![];
+    let mut current = Segment {
+        origin: DEFAULT_ORIGIN,
+        bytes: vec![],
+        line: 1,
+    };
+    for (line, end, item) in items {
+        let error = |message: String| AsmError { line, message };
+        let start = end.wrapping_sub(item.len());
+        match item {
+            Item::Org(origin) => {
+                let previous = std::mem::replace(
+                    &mut current,
+                    Segment {
+                        origin,
+                        bytes: vec![],
+                        line,
+                    },
+                );
+                if !previous.bytes.is_empty() {
+                    segments.push(previous);
+                }
+            }
+            Item::Bytes(exprs) => {
+                for expr in exprs {
+                    current
+                        .bytes
+                        .push(byte(expr.eval(&labels).map_err(error)?).map_err(error)?);
+                }
+            }
+            Item::Words(exprs) => {
+                for expr in exprs {
+                    current
+                        .bytes
+                        .extend(expr.eval(&labels).map_err(error)?.to_le_bytes());
+                }
+            }
+            Item::Instruction(opcode, operand) => {
+                current.bytes.push(opcode.code);
+                let value = match operand {
+                    Some(expr) => expr.eval(&labels).map_err(error)?,
+                    None => 0,
+                };
+                match (opcode.mode, opcode.len) {
+                    // relative branches
+                    (AddressingMode::NoneAddressing, 2) => {
+                        let offset = value.wrapping_sub(start.wrapping_add(2)) as i16;
+                        let offset = i8::try_from(offset).map_err(|_| {
+                            error(format!("branch target ${:04x} is out of range", value))
+                        })?;
+                        current.bytes.push(offset as u8);
+                    }
+                    (_, 2) => current.bytes.push(byte(value).map_err(error)?),
+                    (_, 3) => current.bytes.extend(value.to_le_bytes()),
+                    _ => {}
+                }
+            }
+        }
+    }
+    if !current.bytes.is_empty() || segments.is_empty() {
+        segments.push(current);
+    }
+    Ok(segments)
+}
+
+enum Item {
+    Org(u16),
+    Bytes(Vec<Expr>),
+    Words(Vec<Expr>),
+    Instruction(&'static OpCode, Option<Expr>),
+}
+
+impl Item {
+    fn len(&self) -> u16 {
+        match self {
+            Item::Org(_) => 0,
+            Item::Bytes(exprs) => exprs.len() as u16,
+            Item::Words(exprs) => 2 * exprs.len() as u16,
+            Item::Instruction(opcode, _) => opcode.len as u16,
+        }
+    }
+}
+
+fn parse(text: &str, labels: &HashMap<String, u16>) -> Result<Item, String> {
+    let (keyword, operand) = match text.split_once(char::is_whitespace) {
+        Some((keyword, operand)) => (keyword, operand.trim()),
+        None => (text, ""),
+    };
+    let list = || -> Result<Vec<Expr>, String> { operand.split(',').map(Expr::parse).collect() };
+    match keyword.to_ascii_lowercase().as_str() {
+        ".org" => Ok(Item::Org(Expr::parse(operand)?.eval(labels)?)),
+        ".byte" => Ok(Item::Bytes(list()?)),
+        ".word" => Ok(Item::Words(list()?)),
+        _ if keyword.starts_with('.') => Err(format!("unknown directive {}", keyword)),
+        mnemonic => {
+            let mnemonic = mnemonic.to_ascii_uppercase();
+            let (opcode, expr) = instruction(&mnemonic, operand, labels)?;
+            Ok(Item::Instruction(opcode, expr))
+        }
+    }
+}
+
+// Picks the opcode from the mnemonic and the operand syntax.
+fn instruction(
+    mnemonic: &str,
+    operand: &str,
+    labels: &HashMap<String, u16>,
+) -> Result<(&'static OpCode, Option<Expr>), String> {
+    use AddressingMode::*;
+    let candidates: Vec<&'static OpCode> = CPU_OPS_CODES
+        .iter()
+        .filter(|o| o.mnemonic == mnemonic)
+        .collect();
+    if candidates.is_empty() {
+        return Err(format!("unknown instruction {}", mnemonic));
+    }
+    let find = |mode: AddressingMode, len: u8| {
+        candidates
+            .iter()
+            .copied()
+            .find(|o| o.mode == mode && o.len == len && o.op != Op::JmpIndirect)
+    };
+    let upper = operand.to_ascii_uppercase();
+    let missing = || format!("{} does not support the operand {}", mnemonic, operand);
+
+    // implied and accumulator
+    if operand.is_empty() || upper == "A" {
+        return find(NoneAddressing, 1)
+            .map(|o| (o, None))
+            .ok_or_else(missing);
+    }
+    if let Some(expr) = operand.strip_prefix('#') {
+        let opcode = find(Immediate, 2).ok_or_else(missing)?;
+        return Ok((opcode, Some(Expr::parse(expr)?)));
+    }
+    if let Some(inner) = operand.strip_prefix('(') {
+        let (mode, expr) = if let Some(expr) = upper.strip_suffix(",X)") {
+            (IndirectX, &inner[..expr.len() - 1])
+        } else if let Some(expr) = upper.strip_suffix("),Y") {
+            (IndirectY, &inner[..expr.len() - 1])
+        } else if let Some(expr) = inner.strip_suffix(')') {
+            let jmp = candidates.iter().copied().find(|o| o.op == Op::JmpIndirect);
+            return Ok((jmp.ok_or_else(missing)?, Some(Expr::parse(expr)?)));
+        } else {
+            return Err(format!("invalid operand {}", operand));
+        };
+        return Ok((find(mode, 2).ok_or_else(missing)?, Some(Expr::parse(expr)?)));
+    }
+
+    let (expr, zero_page, absolute) = if let Some(expr) = upper.strip_suffix(",X") {
+        (&operand[..expr.len()], ZeroPageX, AbsoluteX)
+    } else if let Some(expr) = upper.strip_suffix(",Y") {
+        (&operand[..expr.len()], ZeroPageY, AbsoluteY)
+    } else {
+        // branches and JMP take an address, but have no addressing mode
+        if let Some(opcode) = find(NoneAddressing, 2).or_else(|| find(NoneAddressing, 3)) {
+            return Ok((opcode, Some(Expr::parse(operand)?)));
+        }
+        (operand, ZeroPage, Absolute)
+    };
+    let expr = Expr::parse(expr.trim())?;
+    let small = matches!(expr.eval(labels), Ok(value) if value < 0x100);
+    let opcode = match (find(zero_page, 2), find(absolute, 3)) {
+        (Some(opcode), _) if small => Some(opcode),
+        (zero_page, None) => zero_page,
+        (_, absolute) => absolute,
+    };
+    Ok((opcode.ok_or_else(missing)?, Some(expr)))
+}
+
+fn byte(value: u16) -> Result<u8, String> {
+    u8::try_from(value).map_err(|_| format!("${:04x} does not fit in a byte", value))
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// `<` or `>` for the low or high byte, then terms added or subtracted.
+struct Expr {
+    part: Option<char>,
+    terms: Vec<(bool, String)>,
+}
+
+impl Expr {
+    fn parse(text: &str) -> Result<Expr, String> {
+        let text = text.trim();
+        let (part, text) = match text.chars().next() {
+            Some(c @ ('<' | '>')) => (Some(c), &text[1..]),
+            _ => (None, text),
+        };
+        let mut terms = vec![];
+        let mut negative = false;
+        let mut start = 0;
+        for (i, c) in text.char_indices().chain([(text.len(), '+')]) {
+            if (c == '+' || c == '-') && i > start {
+                terms.push((negative, text[start..i].trim().to_string()));
+                negative = c == '-';
+                start = i + 1;
+            }
+        }
+        if terms.is_empty() || terms.iter().any(|(_, term)| term.is_empty()) {
+            return Err(format!("invalid expression {}", text));
+        }
+        Ok(Expr { part, terms })
+    }
+
+    fn eval(&self, labels: &HashMap<String, u16>) -> Result<u16, String> {
+        let mut value: u16 = 0;
+        for (negative, term) in &self.terms {
+            let term = number(term)
+                .or_else(|| labels.get(term).copied())
+                .ok_or_else(|| format!("undefined label {}", term))?;
+            value = if *negative {
+                value.wrapping_sub(term)
+            } else {
+                value.wrapping_add(term)
+            };
+        }
+        Ok(match self.part {
+            Some('<') => value & 0xff,
+            Some('>') => value >> 8,
+            _ => value,
+        })
+    }
+}
+
+fn number(text: &str) -> Option<u16> {
+    if let Some(hex) = text.strip_prefix('$') {
+        u16::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = text.strip_prefix('%') {
+        u16::from_str_radix(bin, 2).ok()
+    } else {
+        text.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn bytes(source: &str) -> Vec<u8> {
+        let segments = assemble(source).unwrap();
+        assert_eq!(segments.len(), 1);
+        segments[0].bytes.clone()
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        let source = "
+            LDA #$01      ; immediate
+            LDA $10       ; zero page
+            LDA $10,X
+            LDX $10,Y
+            LDA $1234
+            LDA $10,Y     ; no zero page,Y for LDA
+            STA $1234,X
+            LDA ($10,X)
+            LDA ($10),y
+            JMP ($1234)
+            ASL A
+            ASL
+            INX
+        ";
+        #[rustfmt::skip]
+        assert_eq!(
+            bytes(source),
+            vec![
+                0xa9, 0x01, 0xa5, 0x10, 0xb5, 0x10, 0xb6, 0x10, 0xad, 0x34, 0x12,
+                0xb9, 0x10, 0x00, 0x9d, 0x34, 0x12, 0xa1, 0x10, 0xb1, 0x10,
+                0x6c, 0x34, 0x12, 0x0a, 0x0a, 0xe8,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_labels_and_directives() {
+        let source = "
+            start: LDX #<table
+                   LDA table+1
+                   BNE start
+                   JSR end
+            end:   JMP start
+            table: .byte 1, $02, %11
+                   .word start, table-1
+                   .org $0700
+                   LDA >table
+        ";
+        let segments = assemble(source).unwrap();
+        #[rustfmt::skip]
+        assert_eq!(
+            segments,
+            vec![
+                Segment {
+                    origin: 0x0600,
+                    bytes: vec![
+                        0xa2, 0x0d, 0xad, 0x0e, 0x06, 0xd0, 0xf9, 0x20, 0x0a, 0x06,
+                        0x4c, 0x00, 0x06, 0x01, 0x02, 0x03, 0x00, 0x06, 0x0c, 0x06,
+                    ],
+                    line: 1,
+                },
+                Segment {
+                    origin: 0x0700,
+                    bytes: vec![0xa5, 0x06],
+                    line: 9,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors() {
+        let error = |source: &str| assemble(source).unwrap_err().to_string();
+        assert_eq!(error("NOP\nFOO"), "line 2: unknown instruction FOO");
+        assert_eq!(error("JMP nowhere"), "line 1: undefined label nowhere");
+        assert_eq!(
+            error("STX $1234,X"),
+            "line 1: STX does not support the operand $1234,X"
+        );
+        assert_eq!(error(".byte 256"), "line 1: $0100 does not fit in a byte");
+        assert_eq!(
+            error("far: .org $0700\nBNE far"),
+            "line 2: branch target $0600 is out of range"
+        );
+        assert_eq!(error("a:\na:"), "line 2: label a is already defined");
+    }
+}
@@ -1,25 +1,172 @@
 use crate::cpu::cartridge::*;
+use crate::cpu::cdl::CodeDataLog;
+use crate::cpu::cheats::Cheats;
+use crate::cpu::hooks::{Access, AccessKind, HookId, Hooks};
+use crate::cpu::CpuBus;
+use crate::cpu::EmuError;
 use crate::cpu::Mem;
+use crate::cpu::NesBus;
+use crate::joypad::Joypad;
+use crate::ppu::NesPPU;
+
+// https://www.nesdev.org/wiki/NROM
+const SUPPORTED_MAPPERS: [u8; 1] = [0];
 
 pub struct Bus {
     cpu_vram: [u8; 2048],
     rom: Rom,
+    ppu: NesPPU,
+    joypad1: Joypad,
+    nmi_line: bool,
+    nmi_external: bool,
+    nmi_pending: bool,
+    irq_lines: u8,
+    fault: Option<EmuError>,
+    cdl: Option<CodeDataLog>,
+    hooks: Hooks,
+    cheats: Cheats,
 }
 
 impl Bus {
-    pub fn new(rom: Rom) -> Self {
-        Bus {
+    pub fn new(rom: Rom) -> Result<Self, EmuError> {
+        if !SUPPORTED_MAPPERS.contains(&rom.mapper) {
+            return Err(EmuError::UnsupportedMapper(rom.mapper));
+        }
+        if !matches!(rom.prg_rom.len(), 0x4000 | 0x8000) {
+            return Err(EmuError::MalformedRom("NROM has 16k or 32k of PRG ROM"));
+        }
+        let ppu = NesPPU::new(rom.chr_rom.clone(), rom.screen_mirroring);
+        Ok(Bus {
             cpu_vram: [0; 2048],
             rom,
+            ppu,
+            joypad1: Joypad::new(),
+            nmi_line: false,
+            nmi_external: false,
+            nmi_pending: false,
+            irq_lines: 0,
+            fault: None,
+            cdl: None,
+            hooks: Hooks::default(),
+            cheats: Cheats::default(),
+        })
+    }
+
+    // `Mem` accesses cannot fail, so faults are recorded here and reported
+    // by the CPU once the current instruction is done. The first one wins.
+    fn fault(&mut self, addr: u16, write: bool, reason: &'static str) {
+        self.fault.get_or_insert(EmuError::BusFault {
+            addr,
+            write,
+            reason,
+        });
+    }
+
+    #[allow(dead_code)]
+    pub fn joypad1(&mut self) -> &mut Joypad {
+        &mut self.joypad1
+    }
+
+    // Interrupt lines
+    // https://www.nesdev.org/wiki/CPU_interrupts
+
+    /// Drives the NMI input from outside the console. The line is shared
+    /// with the PPU and is asserted while either holds it. NMI is
+    /// edge-triggered: only the transition from released to asserted
+    /// raises an interrupt, keeping it asserted does not trigger a new one.
+    #[allow(dead_code)]
+    pub fn set_nmi(&mut self, asserted: bool) {
+        self.nmi_external = asserted;
+        self.update_nmi();
+    }
+
+    fn update_nmi(&mut self) {
+        let asserted = self.nmi_external || self.ppu.nmi_line();
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+    }
+
+    /// Drives the IRQ input for the devices in `source` (one bit per
+    /// device). IRQ is level-triggered and shared: the line stays asserted
+    /// as long as at least one device holds it.
+    #[allow(dead_code)]
+    pub fn set_irq(&mut self, source: u8, asserted: bool) {
+        if asserted {
+            self.irq_lines |= source;
+        } else {
+            self.irq_lines &= !source;
         }
     }
 
-    fn read_prg_rom(&self, addr: u16) -> u8 {
+    // Code/Data Logger
+
+    /// Starts logging which ROM bytes are code or data, unless it already is.
+    pub fn enable_cdl(&mut self) {
+        if self.cdl.is_none() {
+            self.cdl = Some(CodeDataLog::for_rom(&self.rom));
+        }
+    }
+
+    /// Goes on logging into a previously saved log.
+    pub fn load_cdl(&mut self, bytes: &[u8]) -> std::io::Result<()> {
+        self.cdl = Some(CodeDataLog::from_bytes(&self.rom, bytes)?);
+        Ok(())
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLog> {
+        self.cdl.as_ref()
+    }
+
+    // Memory access hooks
+
+    /// Calls `callback` on each access of `kind` to an address in `range`,
+    /// with the CPU address used (mirrors are not folded). Reads with
+    /// [`Mem::peek`] are not reported.
+    #[allow(dead_code)]
+    pub fn add_hook(
+        &mut self,
+        kind: AccessKind,
+        range: std::ops::RangeInclusive<u16>,
+        callback: impl FnMut(&Access) + 'static,
+    ) -> HookId {
+        self.hooks.add(kind, range, callback)
+    }
+
+    /// Returns false if there is no such hook.
+    #[allow(dead_code)]
+    pub fn remove_hook(&mut self, id: HookId) -> bool {
+        self.hooks.remove(id)
+    }
+
+    fn prg_rom_offset(&self, addr: u16) -> usize {
         let mut pr_addr = addr - 0x8000;
         if self.rom.prg_rom.len() == 0x4000 && pr_addr >= 0x4000 {
             pr_addr %= 0x4000;
         }
-        self.rom.prg_rom[pr_addr as usize]
+        pr_addr as usize
+    }
+
+    fn read_prg_rom(&mut self, addr: u16) -> u8 {
+        let offset = self.prg_rom_offset(addr);
+        if let Some(cdl) = &mut self.cdl {
+            cdl.log_prg_read(addr, offset);
+        }
+        self.peek_prg_rom(addr)
+    }
+
+    // PRG ROM as the CPU sees it, with the Game Genie codes applied
+    fn peek_prg_rom(&self, addr: u16) -> u8 {
+        let value = self.rom.prg_rom[self.prg_rom_offset(addr)];
+        self.cheats.patch(addr, value)
+    }
+
+    // Writes the RAM freezes back, at the end of a frame
+    fn apply_freezes(&mut self) {
+        for (addr, value) in self.cheats.freezes() {
+            self.cpu_vram[(addr & 0x7ff) as usize] = value;
+        }
     }
 }
 
@@ -27,42 +174,146 @@ const RAM: u16 = 0x0000;
 const RAM_MIRRORS_END: u16 = 0x1FFF;
 const PPU_REGISTERS: u16 = 0x2000;
 const PPU_REGISTERS_MIRRORS_END: u16 = 0x3FFF;
+const JOYPAD1: u16 = 0x4016;
 
-impl Mem for Bus {
-    fn mem_read(&self, addr: u16) -> u8 {
+impl Bus {
+    fn read(&mut self, addr: u16) -> u8 {
         match addr {
-            RAM..=RAM_MIRRORS_END => {
-                let mirror_down_addr = addr & 0x7ff;
-                self.cpu_vram[mirror_down_addr as usize]
-            }
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_down_addr = addr & 0x2007;
-                todo!("PPU is not supported yet")
-            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => match addr & 0x2007 {
+                0x2002 => {
+                    let data = self.ppu.read_status();
+                    self.update_nmi();
+                    data
+                }
+                0x2004 => self.ppu.read_oam_data(),
+                0x2007 => {
+                    let vram_addr = self.ppu.vram_addr();
+                    if let Some(cdl) = self.cdl.as_mut().filter(|_| vram_addr < 0x2000) {
+                        cdl.log_chr_read(vram_addr as usize);
+                    }
+                    self.ppu.read_data()
+                }
+                _ => self.ppu.open_bus(),
+            },
+            JOYPAD1 => self.joypad1.read(),
             0x8000..=0xFFFF => self.read_prg_rom(addr),
-            _ => {
-                println!("Ignoring mem access at {:#04x}", addr);
-                0
-            }
+            _ => self.peek(addr),
         }
     }
 
-    fn mem_write(&mut self, addr: u16, data: u8) {
+    fn write(&mut self, addr: u16, data: u8) {
         match addr {
             RAM..=RAM_MIRRORS_END => {
                 let mirror_down_addr = addr & 0x7ff;
                 self.cpu_vram[mirror_down_addr as usize] = data;
             }
-            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => {
-                let _mirror_down_addr = addr & 0x2007;
-                todo!("PPU is not supported yet");
-            }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => match addr & 0x2007 {
+                0x2000 => {
+                    self.ppu.write_to_ctrl(data);
+                    self.update_nmi();
+                }
+                0x2001 => self.ppu.write_to_mask(data),
+                0x2002 => self.ppu.write_to_status(data),
+                0x2003 => self.ppu.write_to_oam_addr(data),
+                0x2004 => self.ppu.write_to_oam_data(data),
+                0x2005 => self.ppu.write_to_scroll(data),
+                0x2006 => self.ppu.write_to_ppu_addr(data),
+                _ => self.ppu.write_to_data(data),
+            },
+            JOYPAD1 => self.joypad1.write(data),
             0x8000..=0xFFFF => {
-                panic!("Attempt to write to Cartidge ROM space: {:#04x}", addr)
+                self.fault(addr, true, "attempt to write to cartridge ROM space");
             }
-            _ => {
-                println!("Ignoring mem write-access at {:#04x}", addr);
+            // APU, I/O and cartridge RAM are not emulated
+            _ => {}
+        }
+    }
+}
+
+impl Mem for Bus {
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        let data = self.read(addr);
+        self.hooks.on_access(AccessKind::Read, addr, data);
+        data
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            RAM..=RAM_MIRRORS_END => {
+                let mirror_down_addr = addr & 0x7ff;
+                self.cpu_vram[mirror_down_addr as usize]
             }
+            PPU_REGISTERS..=PPU_REGISTERS_MIRRORS_END => match addr & 0x2007 {
+                0x2002 => self.ppu.peek_status(),
+                0x2004 => self.ppu.read_oam_data(),
+                0x2007 => self.ppu.peek_data(),
+                _ => self.ppu.open_bus(),
+            },
+            JOYPAD1 => self.joypad1.peek(),
+            0x8000..=0xFFFF => self.peek_prg_rom(addr),
+            // unmapped, reads as 0
+            _ => 0,
         }
     }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.write(addr, data);
+        self.hooks.on_access(AccessKind::Write, addr, data);
+    }
+}
+
+impl CpuBus for Bus {
+    fn nmi_pending(&self) -> bool {
+        self.nmi_pending
+    }
+
+    fn poll_nmi(&mut self) -> bool {
+        std::mem::take(&mut self.nmi_pending)
+    }
+
+    fn irq(&self) -> bool {
+        self.irq_lines != 0
+    }
+
+    fn take_fault(&mut self) -> Option<EmuError> {
+        self.fault.take()
+    }
+
+    fn fetch_instruction(&mut self, pc: u16, len: u8) {
+        if let Some(cdl) = &mut self.cdl {
+            cdl.fetch_instruction(pc, len);
+        }
+        if self.hooks.watches(AccessKind::Exec, pc) {
+            let opcode = self.peek(pc);
+            self.hooks.on_access(AccessKind::Exec, pc, opcode);
+        }
+    }
+
+    fn as_nes(&self) -> Option<&dyn NesBus> {
+        Some(self)
+    }
+
+    fn as_nes_mut(&mut self) -> Option<&mut dyn NesBus> {
+        Some(self)
+    }
+
+    // the PPU runs three cycles per CPU cycle on NTSC
+    fn tick(&mut self, cycles: u8) {
+        for _ in 0..cycles {
+            if self.ppu.tick(3) {
+                self.apply_freezes();
+            }
+        }
+        self.update_nmi();
+    }
+}
+
+impl NesBus for Bus {
+    fn prg_bank(&self, addr: u16) -> Option<usize> {
+        (addr >= 0x8000).then(|| self.prg_rom_offset(addr) / 0x4000)
+    }
+
+    fn cheats(&mut self) -> &mut Cheats {
+        &mut self.cheats
+    }
 }
@@ -0,0 +1,452 @@
+// Export of a cartridge as ca65 source, with the ld65 configuration that
+// links it back into the same iNES file.
+// https://cc65.github.io/doc/ca65.html
+
+use std::fmt::Write;
+
+use crate::cpu::cartridge::Rom;
+use crate::cpu::disasm::{self, Disassembly, Line};
+use crate::cpu::opcodes::{self, OpCode};
+use crate::cpu::{AddressingMode, CpuVariant, EmuError};
+
+const HEADER_SIZE: usize = 16;
+const TRAINER_SIZE: usize = 512;
+const BANK_SIZE: usize = 0x4000;
+const VECTORS: u16 = 0xFFFA;
+// Data bytes per `.byte` line outside of the disassembled PRG ROM
+const DATA_PER_LINE: usize = 16;
+
+pub struct Ca65Export {
+    /// Assembly source, for `ca65`.
+    pub source: String,
+    /// Linker configuration, for `ld65 -C`.
+    pub config: String,
+}
+
+/// Exports the iNES file `raw` as ca65 source. The mapped PRG ROM is
+/// disassembled, other banks, the trainer and CHR ROM are kept as data, so
+/// that assembling and linking the result gives back `raw` byte for byte.
+pub fn export(raw: &[u8], variant: CpuVariant) -> Result<Ca65Export, EmuError> {
+    let rom = Rom::new(&raw.to_vec())?;
+    let trainer = raw[6] & 0x4 != 0;
+    let disassembly = disasm::disassemble_rom(&rom, variant);
+    let mapped = rom.prg_rom.len().min(2 * BANK_SIZE);
+    let base = (0x10000 - mapped) as u16;
+
+    // The vectors get their own segment unless an instruction or label
+    // would straddle it.
+    let split_vectors = disassembly.lines.iter().all(|line| match line {
+        Line::Code { addr, bytes, .. } => *addr as usize + bytes.len() <= VECTORS as usize,
+        Line::Data { .. } => true,
+    }) && disassembly.labels.range(VECTORS..).next().is_none();
+
+    let mut source = String::new();
+    let mut config = String::from("MEMORY {\n");
+    let mut segments = String::from("SEGMENTS {\n");
+    let mut memory = |name: &str, start: u16, size: usize| {
+        writeln!(
+            config,
+            "    {}: start = ${:04X}, size = ${:04X}, file = %O, fill = yes;",
+            name, start, size
+        )
+        .unwrap();
+    };
+    let mut segment = |name: &str, load: &str, start: Option<u16>| {
+        let start = start.map_or(String::new(), |s| format!(", start = ${:04X}", s));
+        writeln!(
+            segments,
+            "    {}: load = {}, type = ro{};",
+            name, load, start
+        )
+        .unwrap();
+    };
+
+    if variant == CpuVariant::Cmos65C02 {
+        writeln!(source, ".setcpu \"65C02\"\n").unwrap();
+    }
+
+    memory("HEADER", 0, HEADER_SIZE);
+    segment("HEADER", "HEADER", None);
+    write_header(&mut source, &raw[..HEADER_SIZE]);
+
+    if trainer {
+        memory("TRAINER", 0x7000, TRAINER_SIZE);
+        segment("TRAINER", "TRAINER", None);
+        writeln!(source, "\n.segment \"TRAINER\"").unwrap();
+        write_data(&mut source, &raw[HEADER_SIZE..HEADER_SIZE + TRAINER_SIZE]);
+    }
+
+    memory("PRG", base, mapped);
+    segment("CODE", "PRG", None);
+    writeln!(source, "\n.segment \"CODE\"").unwrap();
+    for line in &disassembly.lines {
+        let addr = line.addr();
+        if split_vectors && addr >= VECTORS {
+            break;
+        }
+        if let Some(label) = disassembly.labels.get(&addr) {
+            writeln!(source, "{}:", label).unwrap();
+        }
+        match line {
+            Line::Data { addr, bytes } if split_vectors => {
+                let len = bytes.len().min((VECTORS - addr) as usize);
+                write_data(&mut source, &bytes[..len]);
+            }
+            Line::Data { bytes, .. } => write_data(&mut source, bytes),
+            Line::Code { opcode, bytes, .. } => writeln!(
+                source,
+                "    {}",
+                instruction(&disassembly, line, opcode, bytes, variant)
+            )
+            .unwrap(),
+        }
+    }
+    if split_vectors {
+        segment("VECTORS", "PRG", Some(VECTORS));
+        writeln!(source, "\n.segment \"VECTORS\"").unwrap();
+        let vectors: Vec<String> = (0..3)
+            .map(|i| {
+                let at = VECTORS as usize + 2 * i - base as usize;
+                let target = u16::from_le_bytes([rom.prg_rom[at], rom.prg_rom[at + 1]]);
+                match disassembly.labels.get(&target) {
+                    Some(label) => label.clone(),
+                    None => format!("${:04x}", target),
+                }
+            })
+            .collect();
+        writeln!(source, "    .word {}", vectors.join(", ")).unwrap();
+    }
+
+    // banks the CPU does not see without a mapper
+    for (i, bank) in rom.prg_rom[mapped..].chunks(BANK_SIZE).enumerate() {
+        let name = format!("BANK{}", i + mapped / BANK_SIZE);
+        memory(&name, 0x8000, bank.len());
+        segment(&name, &name, None);
+        writeln!(source, "\n.segment \"{}\"", name).unwrap();
+        write_data(&mut source, bank);
+    }
+
+    if !rom.chr_rom.is_empty() {
+        memory("CHR", 0, rom.chr_rom.len());
+        segment("CHARS", "CHR", None);
+        writeln!(source, "\n.segment \"CHARS\"").unwrap();
+        write_data(&mut source, &rom.chr_rom);
+    }
+
+    config.push_str("}\n\n");
+    config.push_str(&segments);
+    config.push_str("}\n");
+    Ok(Ca65Export { source, config })
+}
+
+fn write_header(source: &mut String, header: &[u8]) {
+    let flags6 = header[6];
+    writeln!(source, "; iNES header").unwrap();
+    writeln!(source, ".segment \"HEADER\"").unwrap();
+    writeln!(source, "    .byte \"NES\", $1a").unwrap();
+    writeln!(source, "    .byte ${:02x} ; 16k PRG ROM banks", header[4]).unwrap();
+    writeln!(source, "    .byte ${:02x} ; 8k CHR ROM banks", header[5]).unwrap();
+    writeln!(
+        source,
+        "    .byte ${:02x} ; mapper {}, {} mirroring{}{}{}",
+        flags6,
+        (header[7] & 0xf0) | (flags6 >> 4),
+        if flags6 & 0x1 != 0 {
+            "vertical"
+        } else {
+            "horizontal"
+        },
+        if flags6 & 0x2 != 0 { ", battery" } else { "" },
+        if flags6 & 0x4 != 0 { ", trainer" } else { "" },
+        if flags6 & 0x8 != 0 {
+            ", four screen"
+        } else {
+            ""
+        },
+    )
+    .unwrap();
+    writeln!(source, "    .byte ${:02x}", header[7]).unwrap();
+    write_data(source, &header[8..]);
+}
+
+fn write_data(source: &mut String, bytes: &[u8]) {
+    for chunk in bytes.chunks(DATA_PER_LINE) {
+        let bytes: Vec<String> = chunk.iter().map(|b| format!("${:02x}", b)).collect();
+        writeln!(source, "    .byte {}", bytes.join(", ")).unwrap();
+    }
+}
+
+// ca65 picks its own encoding for an instruction, so only opcodes that are
+// the single encoding of their mnemonic and addressing mode are written as
+// instructions, the others as bytes.
+fn instruction(
+    disassembly: &Disassembly,
+    line: &Line,
+    opcode: &OpCode,
+    bytes: &[u8],
+    variant: CpuVariant,
+) -> String {
+    let text = disassembly.text(line);
+    let encodings = (0..=255)
+        .filter_map(|code| opcodes::decode(variant, code))
+        .filter(|other| {
+            other.mnemonic == opcode.mnemonic && other.op == opcode.op && other.mode == opcode.mode
+        })
+        .count();
+    if opcode.mnemonic.starts_with('*') || encodings != 1 {
+        let bytes: Vec<String> = bytes.iter().map(|b| format!("${:02x}", b)).collect();
+        return format!(".byte {} ; {}", bytes.join(", "), text);
+    }
+
+    // ca65 would use zero page addressing for these
+    let absolute = matches!(
+        opcode.mode,
+        AddressingMode::Absolute | AddressingMode::AbsoluteX | AddressingMode::AbsoluteY
+    );
+    match text.split_once(" $00") {
+        Some((mnemonic, operand)) if absolute => format!("{} a:$00{}", mnemonic, operand),
+        _ => text,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+    use std::fs;
+    use std::process::Command;
+
+    // Assembles and links the subset of ca65 that `export` writes, placing
+    // segments in the order of the linker configuration. A smoke test of
+    // the output, `test_export_round_trips_ca65` checks it with the real
+    // tools.
+    fn reassemble(export: &Ca65Export, variant: CpuVariant) -> Vec<u8> {
+        let hex = |s: &str| u16::from_str_radix(&s[..4], 16).unwrap();
+        let mut starts = HashMap::new();
+        let mut order = vec![];
+        for line in export.config.lines() {
+            let Some((name, attributes)) = line.trim().split_once(": load = ") else {
+                continue;
+            };
+            let start = match attributes.split_once("start = $") {
+                Some((_, start)) => hex(start),
+                None => {
+                    let load = attributes.split(',').next().unwrap();
+                    let memory = format!("    {}: start = $", load);
+                    let line = export.config.lines().find(|l| l.starts_with(&memory));
+                    hex(&line.unwrap()[memory.len()..])
+                }
+            };
+            starts.insert(name.to_string(), start);
+            order.push(name.to_string());
+        }
+
+        // labels are only known after the first pass
+        let mut labels = HashMap::new();
+        let mut output: HashMap<String, Vec<u8>> = HashMap::new();
+        for _ in 0..2 {
+            let mut segment = String::new();
+            let mut addr = 0;
+            output.clear();
+            for line in export.source.lines() {
+                let line = line.split(';').next().unwrap().trim();
+                let mut emitted = vec![];
+                if let Some(name) = line.strip_prefix(".segment ") {
+                    segment = name.trim_matches('"').to_string();
+                    addr = starts[&segment];
+                } else if line.is_empty() || line.starts_with(".setcpu") {
+                    continue;
+                } else if let Some(label) = line.strip_suffix(':') {
+                    labels.insert(label.to_string(), addr);
+                } else if let Some(args) = line.strip_prefix(".byte ") {
+                    for arg in args.split(", ") {
+                        match arg.strip_prefix('"') {
+                            Some(s) => emitted.extend(s.trim_end_matches('"').bytes()),
+                            None => emitted.push(value(&labels, arg) as u8),
+                        }
+                    }
+                } else if let Some(args) = line.strip_prefix(".word ") {
+                    for arg in args.split(", ") {
+                        emitted.extend(value(&labels, arg).to_le_bytes());
+                    }
+                } else {
+                    emitted = instruction(variant, &labels, addr, line);
+                }
+                addr = addr.wrapping_add(emitted.len() as u16);
+                output.entry(segment.clone()).or_default().extend(emitted);
+            }
+        }
+        order.iter().flat_map(|name| output[name].clone()).collect()
+    }
+
+    fn value(labels: &HashMap<String, u16>, operand: &str) -> u16 {
+        match operand.strip_prefix('$') {
+            Some(hex) => u16::from_str_radix(hex, 16).unwrap(),
+            // forward references in the first pass
+            None => labels.get(operand).copied().unwrap_or(0x8000),
+        }
+    }
+
+    fn instruction(
+        variant: CpuVariant,
+        labels: &HashMap<String, u16>,
+        addr: u16,
+        line: &str,
+    ) -> Vec<u8> {
+        use crate::cpu::opcodes::Op;
+        use AddressingMode::*;
+        let (mnemonic, operand) = line.split_once(' ').unwrap_or((line, ""));
+        let forced = operand.starts_with("a:");
+        let operand = operand.trim_start_matches("a:");
+        let (arg, index) = match operand.split_once(',') {
+            Some((arg, index)) => (arg, index),
+            None => (operand, ""),
+        };
+        let zero_page = !forced && arg.len() == 3 && arg.starts_with('$');
+        let indirect = arg.trim_matches(|c| c == '(' || c == ')');
+        let (modes, arg): (&[AddressingMode], &str) = match (arg.chars().next(), index) {
+            (None, _) | (Some('A'), "") if arg.len() <= 1 => (&[NoneAddressing], ""),
+            (Some('#'), _) => (&[Immediate], &arg[1..]),
+            (Some('('), "X)") if indirect.len() == 3 => (&[IndirectX], indirect),
+            (Some('('), "Y") => (&[IndirectY], indirect),
+            (Some('('), "") if indirect.len() == 3 => (&[ZeroPageIndirect], indirect),
+            (Some('('), _) => (&[NoneAddressing], indirect),
+            (_, "X") if zero_page => (&[ZeroPageX], arg),
+            (_, "Y") if zero_page => (&[ZeroPageY], arg),
+            (_, "X") => (&[AbsoluteX], arg),
+            (_, "Y") => (&[AbsoluteY], arg),
+            _ if zero_page => (&[ZeroPage], arg),
+            // absolute, jumps and branches
+            _ => (&[Absolute, NoneAddressing], arg),
+        };
+        let jump = match (operand.starts_with('('), index) {
+            (false, _) => None,
+            (true, "X)") => Some(Op::JmpIndexedIndirect),
+            (true, _) => Some(Op::JmpIndirect),
+        };
+        let opcode = (0..=255)
+            .filter_map(|code| opcodes::decode(variant, code))
+            .find(|o| {
+                o.mnemonic == mnemonic
+                    && modes.contains(&o.mode)
+                    && (o.len > 1) != arg.is_empty()
+                    && match o.op {
+                        Op::JmpIndirect | Op::JmpIndexedIndirect => jump == Some(o.op),
+                        _ => jump.is_none() || o.mode != NoneAddressing,
+                    }
+            })
+            .unwrap_or_else(|| panic!("cannot assemble {}", line));
+
+        let mut bytes = vec![opcode.code];
+        let arg = if arg.is_empty() {
+            0
+        } else {
+            value(labels, arg)
+        };
+        match (opcode.mode, opcode.len) {
+            // relative branches
+            (NoneAddressing, 2) => bytes.push(arg.wrapping_sub(addr.wrapping_add(2)) as u8),
+            (_, 2) => bytes.push(arg as u8),
+            (_, 3) => bytes.extend(arg.to_le_bytes()),
+            _ => {}
+        }
+        bytes
+    }
+
+    fn ines(flags6: u8, prg_banks: u8, prg: &[u8]) -> Vec<u8> {
+        let mut raw = vec![
+            0x4e, 0x45, 0x53, 0x1a, prg_banks, 1, flags6, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        ];
+        if flags6 & 0x4 != 0 {
+            raw.extend((0..TRAINER_SIZE).map(|i| i as u8));
+        }
+        let mut prg_rom = vec![0; prg_banks as usize * BANK_SIZE];
+        let len = (2 * BANK_SIZE).min(prg_rom.len());
+        // program at the start of the last mapped bank
+        prg_rom[len - BANK_SIZE..len - BANK_SIZE + prg.len()].copy_from_slice(prg);
+        // NMI: $C00E, reset: $C000, IRQ: $C012
+        prg_rom[len - 6..len].copy_from_slice(&[0x0e, 0xc0, 0x00, 0xc0, 0x12, 0xc0]);
+        raw.extend(prg_rom);
+        raw.extend((0..0x2000).map(|i| (i * 7) as u8));
+        raw
+    }
+
+    #[rustfmt::skip]
+    const PROGRAM: [u8; 21] = [
+        // $C000: LDA $0010,X; *NOP $44; BNE $C000; JSR $C00E; JMP $C000
+        0xbd, 0x10, 0x00, 0x04, 0x44, 0xd0, 0xf9, 0x20, 0x0e, 0xc0, 0x4c, 0x00, 0xc0,
+        // $C00D: data
+        0xff,
+        // $C00E: INC $44; RTI; $C012: JMP ($0300)
+        0xe6, 0x44, 0x40, 0xea, 0x6c, 0x00, 0x03,
+    ];
+
+    #[test]
+    fn test_export_source() {
+        let raw = ines(0x01, 1, &PROGRAM);
+        let export = export(&raw, CpuVariant::default()).unwrap();
+        let source = export.source;
+        assert!(source.contains(".byte $01 ; mapper 0, vertical mirroring\n"));
+        assert!(source.contains("reset:\n    LDA a:$0010,X\n    .byte $04, $44 ; *NOP $44\n"));
+        assert!(source.contains("    BNE reset\n    JSR nmi\n    JMP reset\n    .byte $ff\nnmi:\n"));
+        assert!(source.contains(".segment \"VECTORS\"\n    .word nmi, reset, irq\n"));
+        assert!(export
+            .config
+            .contains("PRG: start = $C000, size = $4000, file = %O, fill = yes;"));
+        assert!(export
+            .config
+            .contains("VECTORS: load = PRG, type = ro, start = $FFFA;"));
+    }
+
+    #[test]
+    fn test_export_round_trips() {
+        // NROM-128, NROM-256 with a trainer, and more banks than are mapped
+        for (flags6, banks) in [(0x00, 1), (0x05, 2), (0x10, 4)] {
+            let raw = ines(flags6, banks, &PROGRAM);
+            let export = export(&raw, CpuVariant::default()).unwrap();
+            assert!(reassemble(&export, CpuVariant::default()) == raw);
+        }
+    }
+
+    // Runs `program` in `dir`, failing with its output.
+    fn cc65_tool(dir: &std::path::Path, program: &str, args: &[&str]) {
+        let output = Command::new(program)
+            .args(args)
+            .current_dir(dir)
+            .output()
+            .unwrap_or_else(|err| panic!("failure to run {}: {}", program, err));
+        assert!(
+            output.status.success(),
+            "{} failed: {}",
+            program,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    #[test]
+    #[ignore = "needs ca65 and ld65 from cc65 on the PATH"]
+    fn test_export_round_trips_ca65() {
+        let dir = std::env::temp_dir().join(format!("yane-ca65-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (flags6, banks) in [(0x00, 1), (0x05, 2), (0x10, 4)] {
+            let raw = ines(flags6, banks, &PROGRAM);
+            let export = export(&raw, CpuVariant::default()).unwrap();
+            fs::write(dir.join("game.s"), &export.source).unwrap();
+            fs::write(dir.join("game.cfg"), &export.config).unwrap();
+            cc65_tool(&dir, "ca65", &["game.s", "-o", "game.o"]);
+            cc65_tool(
+                &dir,
+                "ld65",
+                &["-C", "game.cfg", "game.o", "-o", "game.nes"],
+            );
+            let linked = fs::read(dir.join("game.nes")).unwrap();
+            assert!(
+                linked == raw,
+                "mapper flags {:#04x}, {} banks",
+                flags6,
+                banks
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
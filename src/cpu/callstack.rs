@@ -0,0 +1,371 @@
+// Shadow call stack: the subroutine calls and interrupts the CPU is in,
+// tracked apart from the 6502 stack, which mixes return addresses with
+// pushed data.
+
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::cpu::Interrupt;
+
+// More frames than the 6502 stack can hold mean returns were missed
+const MAX_FRAMES: usize = 256;
+const MAX_MISMATCHES: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Jsr,
+    Interrupt(Interrupt),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    /// The JSR or BRK instruction, or the instruction an NMI or IRQ
+    /// interrupted.
+    pub call_site: u16,
+    /// The subroutine or the interrupt handler.
+    pub target: u16,
+    /// Where RTS or RTI should go back to.
+    pub return_addr: u16,
+    /// SP once returned.
+    pub return_sp: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchKind {
+    /// A return with no frame on the call stack.
+    NoCaller,
+    /// A return to an address the code pushed itself, like RTS used as an
+    /// indirect jump. No frame is popped.
+    ReturnAsJump,
+    /// A return that went up more than one frame, the stack pointer being
+    /// moved past return addresses.
+    Unwound(usize),
+    /// A return to the right stack level, but not to the caller.
+    WrongReturnAddress { expected: u16 },
+    /// RTS leaving an interrupt handler, or RTI leaving a subroutine.
+    WrongReturn,
+}
+
+/// A return that does not match the call stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mismatch {
+    /// The RTS or RTI instruction.
+    pub pc: u16,
+    /// Where it went.
+    pub to: u16,
+    pub kind: MismatchKind,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "return at ${:04X} to ${:04X} ", self.pc, self.to)?;
+        match self.kind {
+            MismatchKind::NoCaller => write!(f, "without a caller"),
+            MismatchKind::ReturnAsJump => write!(f, "used as a jump"),
+            MismatchKind::Unwound(frames) => write!(f, "unwound {} frames", frames),
+            MismatchKind::WrongReturnAddress { expected } => {
+                write!(f, "instead of ${:04X}", expected)
+            }
+            MismatchKind::WrongReturn => write!(f, "with the wrong instruction"),
+        }
+    }
+}
+
+/// How a step changed the call stack.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CallEvent {
+    Call(Frame),
+    /// The number of frames popped.
+    Return(usize),
+}
+
+#[derive(Debug, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+    mismatches: VecDeque<Mismatch>,
+    // the frames the last return popped, innermost first, for the
+    // execution history
+    popped: Vec<Frame>,
+}
+
+#[allow(dead_code)]
+impl CallStack {
+    /// From the outermost frame to the innermost one.
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.mismatches.clear();
+    }
+
+    /// The mismatches seen since the last call, the most recent ones only.
+    pub fn take_mismatches(&mut self) -> Vec<Mismatch> {
+        self.mismatches.drain(..).collect()
+    }
+
+    /// gdb style backtrace, `pc` being where the innermost frame is and
+    /// `label` naming the routines.
+    pub fn backtrace(&self, pc: u16, label: impl Fn(u16) -> Option<String>) -> Vec<String> {
+        let mut lines = vec![];
+        let mut location = pc;
+        for frame in self.frames.iter().rev() {
+            let routine = label(frame.target).unwrap_or_else(|| format!("${:04X}", frame.target));
+            lines.push(format!(
+                "#{:<2} ${:04X} in {}",
+                lines.len(),
+                location,
+                routine
+            ));
+            if let FrameKind::Interrupt(interrupt) = frame.kind {
+                lines.push(format!(
+                    "#{:<2} <{}>",
+                    lines.len(),
+                    format!("{:?}", interrupt).to_ascii_uppercase()
+                ));
+            }
+            location = frame.call_site;
+        }
+        lines.push(format!("#{:<2} ${:04X}", lines.len(), location));
+        lines
+    }
+
+    pub(crate) fn call(&mut self, frame: Frame) -> CallEvent {
+        if self.frames.len() == MAX_FRAMES {
+            self.frames.remove(0);
+        }
+        self.frames.push(frame);
+        CallEvent::Call(frame)
+    }
+
+    // RTS or RTI at `pc` went to `to`, leaving SP at `sp`.
+    pub(crate) fn ret(&mut self, rti: bool, pc: u16, to: u16, sp: u8) -> Option<CallEvent> {
+        let kind = match self.frames.last() {
+            None => Some(MismatchKind::NoCaller),
+            Some(frame) if sp < frame.return_sp => Some(MismatchKind::ReturnAsJump),
+            Some(_) => None,
+        };
+        if let Some(kind) = kind {
+            self.flag(Mismatch { pc, to, kind });
+            return None;
+        }
+
+        self.popped.clear();
+        while let Some(top) = self.frames.last() {
+            if top.return_sp > sp {
+                break;
+            }
+            self.popped.extend(self.frames.pop());
+        }
+        let popped = self.popped.len();
+        let frame = self.popped[popped - 1];
+        let kind = if popped > 1 {
+            Some(MismatchKind::Unwound(popped))
+        } else if frame.return_addr != to {
+            Some(MismatchKind::WrongReturnAddress {
+                expected: frame.return_addr,
+            })
+        } else if rti != matches!(frame.kind, FrameKind::Interrupt(_)) {
+            Some(MismatchKind::WrongReturn)
+        } else {
+            None
+        };
+        if let Some(kind) = kind {
+            self.flag(Mismatch { pc, to, kind });
+        }
+        Some(CallEvent::Return(popped))
+    }
+
+    pub(crate) fn popped(&self) -> &[Frame] {
+        &self.popped
+    }
+
+    // Undoes a step: drops the frame it pushed, or pushes back the ones it
+    // popped.
+    pub(crate) fn undo(&mut self, called: bool, returned: &[Frame]) {
+        if called {
+            self.frames.pop();
+        }
+        self.frames.extend(returned.iter().rev());
+    }
+
+    fn flag(&mut self, mismatch: Mismatch) {
+        if self.mismatches.len() == MAX_MISMATCHES {
+            self.mismatches.pop_front();
+        }
+        self.mismatches.push_back(mismatch);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::ram::FlatRam;
+    use crate::cpu::{Cpu, StopReason};
+
+    fn run(source: &str) -> Cpu<FlatRam> {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.load_assembly(source).unwrap();
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+        cpu
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let mut cpu = run("
+                     JSR outer      ; $0600
+                     .byte $02
+            outer:   JSR inner      ; $0604
+                     RTS
+            inner:   NOP            ; $0608
+                     BRK
+                     NOP
+                     RTS
+            handler: .byte $02      ; $060C, JAM
+
+                     .org $fffe
+                     .word handler
+        ");
+        assert_eq!(
+            cpu.call_stack.backtrace(cpu.pc, |_| None),
+            [
+                "#0  $060C in $060C",
+                "#1  <BRK>",
+                "#2  $0609 in $0608",
+                "#3  $0604 in $0604",
+                "#4  $0600",
+            ]
+        );
+        cpu.symbols.insert(0x0608, None, "inner");
+        let backtrace = cpu
+            .call_stack
+            .backtrace(cpu.pc, |addr| cpu.label(addr).map(String::from));
+        assert_eq!(backtrace[2], "#2  $0609 in inner");
+        let frames = cpu.call_stack.frames();
+        assert_eq!(frames[2].kind, FrameKind::Interrupt(Interrupt::Brk));
+        assert_eq!(frames[2].return_addr, 0x060b);
+        assert_eq!(frames[1].return_addr, 0x0607);
+        assert!(cpu.call_stack.take_mismatches().is_empty());
+    }
+
+    #[test]
+    fn test_step_back() {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.load_assembly(
+            "
+                     JSR outer      ; $0600
+                     .byte $02
+            outer:   JSR inner      ; $0604
+                     RTS
+            inner:   RTS            ; $0608
+            ",
+        )
+        .unwrap();
+        cpu.history.set_capacity(10);
+        cpu.profiler.start();
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+        assert!(cpu.call_stack.frames().is_empty());
+
+        // back over the JAM and both returns
+        for _ in 0..3 {
+            cpu.step_back().unwrap();
+        }
+        assert_eq!(
+            cpu.call_stack.backtrace(cpu.pc, |_| None),
+            ["#0  $0608 in $0608", "#1  $0604 in $0604", "#2  $0600"]
+        );
+        // and over the call of inner
+        cpu.step_back().unwrap();
+        assert_eq!(cpu.call_stack.frames().len(), 1);
+
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+        assert!(cpu.call_stack.frames().is_empty());
+        assert!(cpu.call_stack.take_mismatches().is_empty());
+        let calls: Vec<(u16, u64)> = cpu
+            .profiler
+            .routines()
+            .iter()
+            .map(|routine| (routine.addr, routine.calls))
+            .collect();
+        assert_eq!(calls, [(0x0604, 1), (0x0608, 2)]);
+    }
+
+    #[test]
+    fn test_mismatches() {
+        let mut cpu = run("
+                     JSR jump       ; $0600
+                     .byte $02
+            jump:    LDA #>target   ; $0604
+                     PHA
+                     LDA #<target-1
+                     PHA
+                     RTS            ; $060A
+            target:  RTS
+        ");
+        assert!(cpu.call_stack.frames().is_empty());
+        let mismatches = cpu.call_stack.take_mismatches();
+        assert_eq!(
+            mismatches,
+            [Mismatch {
+                pc: 0x060a,
+                to: 0x060b,
+                kind: MismatchKind::ReturnAsJump,
+            }]
+        );
+        assert_eq!(
+            mismatches[0].to_string(),
+            "return at $060A to $060B used as a jump"
+        );
+
+        // data after the JSR, skipped by moving the return address
+        let mut cpu = run("
+                     JSR skip       ; $0600
+                     .byte $ff
+                     .byte $02
+            skip:    PLA            ; $0605
+                     CLC
+                     ADC #1
+                     PHA
+                     RTS            ; $060A
+        ");
+        assert_eq!(
+            cpu.call_stack.take_mismatches()[0].kind,
+            MismatchKind::WrongReturnAddress { expected: 0x0603 }
+        );
+    }
+
+    #[test]
+    fn test_unwinding() {
+        let frame = |kind, return_sp| Frame {
+            kind,
+            call_site: 0,
+            target: 0,
+            return_addr: 0x1234,
+            return_sp,
+        };
+        let mut stack = CallStack::default();
+        stack.call(frame(FrameKind::Interrupt(Interrupt::Nmi), 0xfd));
+        stack.call(frame(FrameKind::Jsr, 0xfa));
+        stack.call(frame(FrameKind::Jsr, 0xf8));
+        assert_eq!(
+            stack.ret(false, 0, 0x1234, 0xfa),
+            Some(CallEvent::Return(2))
+        );
+        assert_eq!(
+            stack.ret(false, 0, 0x1234, 0xfd),
+            Some(CallEvent::Return(1))
+        );
+        assert_eq!(stack.ret(true, 0, 0x1234, 0xfd), None);
+        let kinds: Vec<MismatchKind> = stack.take_mismatches().iter().map(|m| m.kind).collect();
+        assert_eq!(
+            kinds,
+            [
+                MismatchKind::Unwound(2),
+                MismatchKind::WrongReturn,
+                MismatchKind::NoCaller
+            ]
+        );
+    }
+}
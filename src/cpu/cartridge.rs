@@ -1,4 +1,6 @@
-#[derive(Debug, PartialEq, Eq)]
+use crate::cpu::EmuError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mirroring {
     Vertical,
     Horizontal,
@@ -18,16 +20,16 @@ const PRG_ROM_PAGE_SIZE: usize = 0x4000; // 16k
 const CHR_ROM_PAGE_SIZE: usize = 0x2000; // 8k
 
 impl Rom {
-    pub fn new(raw: &Vec<u8>) -> Result<Rom, String> {
-        if &raw[0..4] != NES_TAG {
-            return Err("File is not in iNES file format".to_string());
+    pub fn new(raw: &Vec<u8>) -> Result<Rom, EmuError> {
+        if raw.len() < 16 || raw[0..4] != NES_TAG {
+            return Err(EmuError::MalformedRom("File is not in iNES file format"));
         }
 
         let mapper = (raw[7] & 0xf0) | (raw[6] >> 4);
 
         let ines_ver = (raw[7] >> 2) & 0x3;
         if ines_ver != 0 {
-            return Err("NES2.0 format is not supported".to_string());
+            return Err(EmuError::MalformedRom("NES2.0 format is not supported"));
         }
 
         let four_screen = raw[6] & 0x8 != 0;
@@ -39,12 +41,18 @@ impl Rom {
         };
 
         let prg_rom_size = raw[4] as usize * PRG_ROM_PAGE_SIZE;
+        if prg_rom_size == 0 {
+            return Err(EmuError::MalformedRom("File has no PRG ROM"));
+        }
         let chr_rom_size = raw[5] as usize * CHR_ROM_PAGE_SIZE;
 
         let skip_trainer = raw[6] & 0x4 != 0;
 
         let prg_rom_start = 16 + if skip_trainer { 512 } else { 0 };
         let chr_rom_start = prg_rom_start + prg_rom_size;
+        if raw.len() < chr_rom_start + chr_rom_size {
+            return Err(EmuError::MalformedRom("File is truncated"));
+        }
 
         Ok(Rom {
             prg_rom: raw[prg_rom_start..(prg_rom_start + prg_rom_size)].to_vec(),
@@ -87,7 +95,7 @@ pub mod test {
     pub fn test_rom() -> Rom {
         let test_rom = create_rom(TestRom {
             header: vec![
-                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x01, 00, 00, 00, 00, 00, 00, 00, 00, 00,
             ],
             trainer: None,
             pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
@@ -163,7 +171,55 @@ pub mod test {
         let rom = Rom::new(&test_rom);
         match rom {
             Result::Ok(_) => assert!(false, "should not load rom"),
-            Result::Err(str) => assert_eq!(str, "NES2.0 format is not supported"),
+            Result::Err(err) => assert_eq!(
+                err,
+                EmuError::MalformedRom("NES2.0 format is not supported")
+            ),
         }
     }
+
+    #[test]
+    fn test_truncated_rom() {
+        let mut test_rom = create_rom(TestRom {
+            header: vec![
+                0x4E, 0x45, 0x53, 0x1A, 0x02, 0x01, 0x31, 00, 00, 00, 00, 00, 00, 00, 00, 00,
+            ],
+            trainer: None,
+            pgp_rom: vec![1; 2 * PRG_ROM_PAGE_SIZE],
+            chr_rom: vec![2; 1 * CHR_ROM_PAGE_SIZE],
+        });
+        test_rom.truncate(PRG_ROM_PAGE_SIZE);
+        assert_eq!(
+            Rom::new(&test_rom).err(),
+            Some(EmuError::MalformedRom("File is truncated"))
+        );
+        assert_eq!(
+            Rom::new(&vec![0x4E, 0x45]).err(),
+            Some(EmuError::MalformedRom("File is not in iNES file format"))
+        );
+    }
+
+    #[test]
+    fn test_prg_rom_size() {
+        let rom = |prg_pages: u8| {
+            create_rom(TestRom {
+                header: vec![
+                    0x4E, 0x45, 0x53, 0x1A, prg_pages, 0x01, 0x00, 00, 00, 00, 00, 00, 00, 00, 00,
+                    00,
+                ],
+                trainer: None,
+                pgp_rom: vec![1; prg_pages as usize * PRG_ROM_PAGE_SIZE],
+                chr_rom: vec![2; CHR_ROM_PAGE_SIZE],
+            })
+        };
+        assert_eq!(
+            Rom::new(&rom(0)).err(),
+            Some(EmuError::MalformedRom("File has no PRG ROM"))
+        );
+        assert!(crate::cpu::Cpu::new(Rom::new(&rom(1)).unwrap()).is_ok());
+        assert_eq!(
+            crate::cpu::Cpu::new(Rom::new(&rom(3)).unwrap()).err(),
+            Some(EmuError::MalformedRom("NROM has 16k or 32k of PRG ROM"))
+        );
+    }
 }
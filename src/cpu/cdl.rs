@@ -0,0 +1,165 @@
+// Code/Data Logger, in the .cdl format of FCEUX: one flag byte per PRG ROM
+// byte, followed by one per CHR ROM byte.
+// https://fceux.com/web/help/CodeDataLogger.html
+
+use std::io;
+use std::ops::Range;
+
+use crate::cpu::cartridge::Rom;
+
+// PRG ROM flags
+pub const PRG_CODE: u8 = 0x01;
+pub const PRG_DATA: u8 = 0x02;
+// bits 2-3: the 8k window ($8000, $A000, $C000 or $E000) the byte was
+// mapped in when last accessed
+const PRG_WINDOW_SHIFT: u8 = 2;
+const PRG_WINDOW_MASK: u8 = 0b0000_1100;
+
+// CHR ROM flags. Nothing is rendered yet, so only reads through PPUDATA
+// are logged.
+#[allow(dead_code)]
+pub const CHR_RENDERED: u8 = 0x01;
+pub const CHR_READ: u8 = 0x02;
+
+pub struct CodeDataLog {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+    // the instruction the CPU is fetching: reads there are code
+    fetch_start: u16,
+    fetch_len: u8,
+}
+
+#[allow(dead_code)]
+impl CodeDataLog {
+    /// An empty log for `rom`.
+    pub fn for_rom(rom: &Rom) -> Self {
+        CodeDataLog {
+            prg: vec![0; rom.prg_rom.len()],
+            chr: vec![0; rom.chr_rom.len()],
+            fetch_start: 0,
+            fetch_len: 0,
+        }
+    }
+
+    /// Reads a .cdl file made for `rom`.
+    pub fn from_bytes(rom: &Rom, bytes: &[u8]) -> io::Result<Self> {
+        let mut log = Self::for_rom(rom);
+        if bytes.len() != log.prg.len() + log.chr.len() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "CDL file size does not match the ROM",
+            ));
+        }
+        let (prg, chr) = bytes.split_at(log.prg.len());
+        log.prg.copy_from_slice(prg);
+        log.chr.copy_from_slice(chr);
+        Ok(log)
+    }
+
+    /// The content of a .cdl file.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        [self.prg.as_slice(), self.chr.as_slice()].concat()
+    }
+
+    /// Flags of each PRG ROM byte.
+    pub fn prg(&self) -> &[u8] {
+        &self.prg
+    }
+
+    /// Flags of each CHR ROM byte.
+    pub fn chr(&self) -> &[u8] {
+        &self.chr
+    }
+
+    /// PRG ROM ranges that were never accessed.
+    pub fn unused_prg(&self) -> Vec<Range<usize>> {
+        let mut unused = vec![];
+        let mut start = None;
+        for (offset, flags) in self.prg.iter().chain([&PRG_CODE]).enumerate() {
+            match (start, *flags & (PRG_CODE | PRG_DATA)) {
+                (None, 0) => start = Some(offset),
+                (Some(begin), flags) if flags != 0 => {
+                    unused.push(begin..offset);
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        unused
+    }
+
+    pub(crate) fn fetch_instruction(&mut self, pc: u16, len: u8) {
+        self.fetch_start = pc;
+        self.fetch_len = len;
+    }
+
+    // `offset` is the PRG ROM byte `addr` maps to.
+    pub(crate) fn log_prg_read(&mut self, addr: u16, offset: usize) {
+        let kind = if addr.wrapping_sub(self.fetch_start) < self.fetch_len as u16 {
+            PRG_CODE
+        } else {
+            PRG_DATA
+        };
+        let window = (((addr - 0x8000) >> 13) as u8) << PRG_WINDOW_SHIFT;
+        if let Some(flags) = self.prg.get_mut(offset) {
+            *flags = (*flags & !PRG_WINDOW_MASK) | kind | window;
+        }
+    }
+
+    pub(crate) fn log_chr_read(&mut self, offset: usize) {
+        if let Some(flags) = self.chr.get_mut(offset) {
+            *flags |= CHR_READ;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::cartridge::Mirroring;
+    use crate::cpu::{Cpu, StopReason};
+
+    fn rom(program: &[u8]) -> Rom {
+        let mut prg_rom = vec![0; 0x4000];
+        prg_rom[..program.len()].copy_from_slice(program);
+        Rom {
+            prg_rom,
+            chr_rom: vec![0; 0x2000],
+            mapper: 0,
+            screen_mirroring: Mirroring::Horizontal,
+        }
+    }
+
+    #[test]
+    fn test_log_code_and_data() {
+        #[rustfmt::skip]
+        let program = [
+            // $C000: LDA $C010; LDX #$00; LDY #$05; STX $2006; STY $2006
+            0xad, 0x10, 0xc0, 0xa2, 0x00, 0xa0, 0x05, 0x8e, 0x06, 0x20, 0x8c, 0x06, 0x20,
+            // LDA $2007; JAM
+            0xad, 0x07, 0x20, 0x02,
+        ];
+        let mut cpu = Cpu::new(rom(&program)).unwrap();
+        cpu.bus.enable_cdl();
+        cpu.pc = 0xc000;
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+
+        let log = cpu.bus.cdl().unwrap();
+        let window = 2 << PRG_WINDOW_SHIFT;
+        assert!(log.prg()[..0x10].iter().all(|f| *f == PRG_CODE | window));
+        assert_eq!(log.prg()[0x11], 0);
+        // $C010, the JAM opcode, was also read as data
+        assert_eq!(log.prg()[0x10], PRG_CODE | PRG_DATA | window);
+        assert_eq!(log.unused_prg(), vec![0x11..0x4000]);
+        assert_eq!(log.chr()[5], CHR_READ);
+        assert_eq!(log.chr().iter().filter(|f| **f != 0).count(), 1);
+
+        // the file is the PRG flags then the CHR flags
+        let bytes = log.to_bytes();
+        assert_eq!(bytes.len(), 0x6000);
+        assert_eq!(bytes[0x4005], CHR_READ);
+        let loaded = CodeDataLog::from_bytes(&rom(&program), &bytes).unwrap();
+        assert_eq!(loaded.to_bytes(), bytes);
+        assert!(CodeDataLog::from_bytes(&rom(&program), &bytes[1..]).is_err());
+    }
+}
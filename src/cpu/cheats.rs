@@ -0,0 +1,287 @@
+// Cheat codes: Game Genie codes, patching what the CPU reads from PRG ROM,
+// and Pro Action Replay style RAM freezes, written back every frame.
+// https://www.nesdev.org/wiki/Game_Genie
+
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+// Letters of the Game Genie, by value
+const LETTERS: &str = "APZLGITYEOXUKSVN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatKind {
+    /// Reads of `addr` give `value`, when the ROM byte there is `compare`
+    /// for 8 letter codes.
+    GameGenie {
+        addr: u16,
+        value: u8,
+        compare: Option<u8>,
+    },
+    /// `addr` is set to `value` at the end of every frame.
+    Freeze { addr: u16, value: u8 },
+}
+
+impl CheatKind {
+    /// Decodes a 6 or 8 letter Game Genie code, or an `ADDR:VAL` RAM freeze
+    /// in hex.
+    pub fn decode(code: &str) -> Result<Self, String> {
+        match code.split_once(':') {
+            Some((addr, value)) => {
+                let addr = u16::from_str_radix(addr, 16)
+                    .map_err(|_| format!("invalid address in {}", code))?;
+                let value = u8::from_str_radix(value, 16)
+                    .map_err(|_| format!("invalid value in {}", code))?;
+                if addr > 0x1fff {
+                    return Err(format!("{} does not freeze RAM", code));
+                }
+                Ok(CheatKind::Freeze { addr, value })
+            }
+            None => decode_game_genie(code),
+        }
+    }
+}
+
+fn decode_game_genie(code: &str) -> Result<CheatKind, String> {
+    let n: Vec<u16> = code
+        .chars()
+        .map(|letter| LETTERS.find(letter.to_ascii_uppercase()).map(|n| n as u16))
+        .collect::<Option<_>>()
+        .ok_or(format!("invalid Game Genie code {}", code))?;
+    if n.len() != 6 && n.len() != 8 {
+        return Err(format!("Game Genie codes have 6 or 8 letters: {}", code));
+    }
+    let addr = 0x8000
+        + (((n[3] & 7) << 12)
+            | ((n[5] & 7) << 8)
+            | ((n[4] & 8) << 8)
+            | ((n[2] & 7) << 4)
+            | ((n[1] & 8) << 4)
+            | (n[4] & 7)
+            | (n[3] & 8));
+    let value = ((n[1] & 7) << 4) | ((n[0] & 8) << 4) | (n[0] & 7);
+    let (value, compare) = match n.len() {
+        6 => (value | (n[5] & 8), None),
+        _ => {
+            let compare = ((n[7] & 7) << 4) | ((n[6] & 8) << 4) | (n[6] & 7) | (n[5] & 8);
+            (value | (n[7] & 8), Some(compare as u8))
+        }
+    };
+    Ok(CheatKind::GameGenie {
+        addr,
+        value: value as u8,
+        compare,
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cheat {
+    pub code: String,
+    pub name: String,
+    pub kind: CheatKind,
+    pub enabled: bool,
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = if self.enabled { "on " } else { "off" };
+        write!(f, "{} {:8}", state, self.code)?;
+        if !self.name.is_empty() {
+            write!(f, " {}", self.name)?;
+        }
+        match self.kind {
+            CheatKind::GameGenie {
+                addr,
+                value,
+                compare: Some(compare),
+            } => write!(f, " (${:04X} = ${:02X} if ${:02X})", addr, value, compare),
+            CheatKind::GameGenie { addr, value, .. } | CheatKind::Freeze { addr, value } => {
+                write!(f, " (${:04X} = ${:02X})", addr, value)
+            }
+        }
+    }
+}
+
+/// The cheats of a cartridge, applied by the bus.
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+#[allow(dead_code)]
+impl Cheats {
+    /// Adds the cheats of a cheat file: one code per line followed by an
+    /// optional name, `!` before the code for a cheat that starts disabled,
+    /// `#` for comments.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let text = std::fs::read_to_string(path)?;
+        self.parse(&text)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+    }
+
+    /// Adds the cheats of the text of a cheat file, see [`Cheats::load`].
+    pub fn parse(&mut self, text: &str) -> Result<(), String> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let (code, enabled) = match code.strip_prefix('!') {
+                Some(code) => (code, false),
+                None => (code, true),
+            };
+            let index = self
+                .add(code, name.trim())
+                .map_err(|err| format!("line {}: {}", number + 1, err))?;
+            self.cheats[index].enabled = enabled;
+        }
+        Ok(())
+    }
+
+    /// Adds an enabled cheat, returning its index.
+    pub fn add(&mut self, code: &str, name: &str) -> Result<usize, String> {
+        let kind = CheatKind::decode(code)?;
+        self.cheats.push(Cheat {
+            code: code.to_ascii_uppercase(),
+            name: name.to_string(),
+            kind,
+            enabled: true,
+        });
+        Ok(self.cheats.len() - 1)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Cheat> {
+        (index < self.cheats.len()).then(|| self.cheats.remove(index))
+    }
+
+    /// Returns false if there is no such cheat.
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> bool {
+        match self.cheats.get_mut(index) {
+            Some(cheat) => {
+                cheat.enabled = enabled;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn list(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cheats.is_empty()
+    }
+
+    fn enabled(&self) -> impl Iterator<Item = CheatKind> + '_ {
+        self.cheats
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .map(|cheat| cheat.kind)
+    }
+
+    // The byte the CPU reads at `addr` in PRG ROM, `value` being the one
+    // of the ROM.
+    pub(crate) fn patch(&self, addr: u16, value: u8) -> u8 {
+        for kind in self.enabled() {
+            if let CheatKind::GameGenie {
+                addr: target,
+                value: patched,
+                compare,
+            } = kind
+            {
+                if target == addr && compare.is_none_or(|compare| compare == value) {
+                    return patched;
+                }
+            }
+        }
+        value
+    }
+
+    // The RAM addresses to set at the end of a frame, and their values.
+    pub(crate) fn freezes(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.enabled().filter_map(|kind| match kind {
+            CheatKind::Freeze { addr, value } => Some((addr, value)),
+            CheatKind::GameGenie { .. } => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cpu::{Cpu, CpuBus, Mem, NesBus};
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            CheatKind::decode("SXIOPO"),
+            Ok(CheatKind::GameGenie {
+                addr: 0x91d9,
+                value: 0xad,
+                compare: None
+            })
+        );
+        assert_eq!(
+            CheatKind::decode("zgoaaapa"),
+            Ok(CheatKind::GameGenie {
+                addr: 0x8010,
+                value: 0x42,
+                compare: Some(0x01)
+            })
+        );
+        assert_eq!(
+            CheatKind::decode("0075:09"),
+            Ok(CheatKind::Freeze {
+                addr: 0x0075,
+                value: 0x09
+            })
+        );
+        assert!(CheatKind::decode("SXIOP").is_err());
+        assert!(CheatKind::decode("SXIOPB").is_err());
+        assert!(CheatKind::decode("8000:01").is_err());
+    }
+
+    #[test]
+    fn test_cheats_on_bus() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        let cheats = cpu.bus.cheats();
+        cheats
+            .parse(
+                "
+                # the test ROM is filled with $01
+                ZGOAAAPA   $8010 = $42 if $01
+                ZGOAAAZA   $8010 = $42 if $02
+                !OPZGLO    $C123 = $99
+                0075:09    lives
+                ",
+            )
+            .unwrap();
+        assert_eq!(cheats.list()[0].name, "$8010 = $42 if $01");
+        assert!(!cheats.list()[2].enabled);
+        assert_eq!(
+            cheats.list()[3].to_string(),
+            "on  0075:09  lives ($0075 = $09)"
+        );
+        assert_eq!(cpu.mem_read(0x8010), 0x42);
+        assert_eq!(cpu.peek(0x8010), 0x42);
+        assert_eq!(cpu.mem_read(0xc123), 0x01);
+
+        let cheats = cpu.bus.cheats();
+        cheats.set_enabled(0, false);
+        cheats.set_enabled(2, true);
+        assert_eq!(cpu.mem_read(0x8010), 0x01);
+        assert_eq!(cpu.mem_read(0xc123), 0x99);
+
+        // the freeze is written back at the end of the frame
+        cpu.mem_write(0x0075, 0);
+        cpu.bus.tick(255);
+        assert_eq!(cpu.peek(0x0075), 0);
+        for _ in 0..200 {
+            cpu.bus.tick(255);
+        }
+        assert_eq!(cpu.peek(0x0075), 0x09);
+    }
+}
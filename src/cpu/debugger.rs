@@ -0,0 +1,176 @@
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+use crate::cpu::Interrupt;
+
+/// The kind of access a watchpoint triggers on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    /// A data read: instruction fetches are not reported, even for the
+    /// operand of an immediate instruction.
+    Read,
+    Write,
+    /// An instruction is about to be executed from the range.
+    Execute,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+}
+
+/// Why the debugger stopped the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakReason {
+    /// PC reached a breakpoint. The instruction there is not executed yet.
+    Breakpoint(u16),
+    /// A watched access happened. Read and write watchpoints stop after the
+    /// instruction making the access, execute watchpoints before the
+    /// instruction runs.
+    Watchpoint {
+        kind: WatchKind,
+        addr: u16,
+        value: u8,
+    },
+    /// The next instruction has a watched opcode.
+    Opcode(u8),
+    /// An interrupt sequence ran, PC is at the start of the handler.
+    Interrupt(Interrupt),
+}
+
+/// Breakpoints and watchpoints of a [`Cpu`](crate::cpu::Cpu).
+///
+/// The CPU's `run_*` methods that return a [`StopReason`] check it after
+/// every instruction and stop with [`StopReason::Break`] when one is hit.
+/// Nothing is checked while it is empty.
+///
+/// [`StopReason`]: crate::cpu::StopReason
+/// [`StopReason::Break`]: crate::cpu::StopReason::Break
+#[derive(Debug, Default)]
+pub struct Debugger {
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    opcodes: BTreeSet<u8>,
+    interrupts: Vec<Interrupt>,
+    hit: Option<BreakReason>,
+}
+
+#[allow(dead_code)]
+impl Debugger {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns false if there already was a breakpoint at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.insert(addr)
+    }
+
+    /// Returns false if there was no breakpoint at `addr`.
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) {
+        self.watchpoints.push(Watchpoint { range, kind });
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.watchpoints.len() {
+            Some(self.watchpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn break_on_opcode(&mut self, code: u8, enabled: bool) {
+        if enabled {
+            self.opcodes.insert(code);
+        } else {
+            self.opcodes.remove(&code);
+        }
+    }
+
+    pub fn break_on_interrupt(&mut self, interrupt: Interrupt, enabled: bool) {
+        self.interrupts.retain(|i| *i != interrupt);
+        if enabled {
+            self.interrupts.push(interrupt);
+        }
+    }
+
+    /// Removes every breakpoint, watchpoint and break condition.
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.breakpoints.is_empty()
+            && self.watchpoints.is_empty()
+            && self.opcodes.is_empty()
+            && self.interrupts.is_empty()
+    }
+
+    pub(crate) fn watching(&self) -> bool {
+        !self.watchpoints.is_empty()
+    }
+
+    // Hits are only reported for the instruction that caused them.
+    pub(crate) fn start_step(&mut self) {
+        self.hit = None;
+    }
+
+    // Called for every read and write the CPU makes while watching. The
+    // first hit of an instruction wins.
+    pub(crate) fn on_access(&mut self, kind: WatchKind, addr: u16, value: u8) {
+        if self.hit.is_some() {
+            return;
+        }
+        if self.matches(kind, addr) {
+            self.hit = Some(BreakReason::Watchpoint { kind, addr, value });
+        }
+    }
+
+    // Called after each instruction with the state the next one starts from.
+    pub(crate) fn check(
+        &mut self,
+        pc: u16,
+        next_opcode: u8,
+        interrupt: Option<Interrupt>,
+    ) -> Option<BreakReason> {
+        if let Some(hit) = self.hit.take() {
+            return Some(hit);
+        }
+        if let Some(interrupt) = interrupt.filter(|i| self.interrupts.contains(i)) {
+            return Some(BreakReason::Interrupt(interrupt));
+        }
+        if self.breakpoints.contains(&pc) {
+            return Some(BreakReason::Breakpoint(pc));
+        }
+        if self.matches(WatchKind::Execute, pc) {
+            return Some(BreakReason::Watchpoint {
+                kind: WatchKind::Execute,
+                addr: pc,
+                value: next_opcode,
+            });
+        }
+        if self.opcodes.contains(&next_opcode) {
+            return Some(BreakReason::Opcode(next_opcode));
+        }
+        None
+    }
+
+    fn matches(&self, kind: WatchKind, addr: u16) -> bool {
+        self.watchpoints
+            .iter()
+            .any(|w| w.kind == kind && w.range.contains(&addr))
+    }
+}
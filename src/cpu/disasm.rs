@@ -0,0 +1,402 @@
+// Static disassembler: separates code from data by following the control
+// flow from the interrupt vectors and the given entry points.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::cpu::cartridge::Rom;
+use crate::cpu::opcodes::{self, Op, OpCode};
+use crate::cpu::symbols::SymbolTable;
+use crate::cpu::{AddressingMode, Cpu, CpuBus, CpuVariant, Mem};
+
+const VECTORS: [(u16, &str); 3] = [(0xFFFA, "nmi"), (0xFFFC, "reset"), (0xFFFE, "irq")];
+// Data bytes per `.byte` line
+const DATA_PER_LINE: usize = 8;
+
+#[derive(Debug, Clone)]
+pub enum Line {
+    Code {
+        addr: u16,
+        opcode: &'static OpCode,
+        bytes: Vec<u8>,
+    },
+    Data {
+        addr: u16,
+        bytes: Vec<u8>,
+    },
+}
+
+impl Line {
+    pub fn addr(&self) -> u16 {
+        match self {
+            Line::Code { addr, .. } | Line::Data { addr, .. } => *addr,
+        }
+    }
+}
+
+/// The disassembly of a memory image, in address order.
+#[derive(Debug, Clone)]
+pub struct Disassembly {
+    /// Names of the vectors, jump and call targets found in the image, and
+    /// of the symbols added with [`Disassembly::add_symbols`].
+    pub labels: BTreeMap<u16, String>,
+    pub lines: Vec<Line>,
+}
+
+impl Disassembly {
+    /// Names addresses after `symbols`, `bank` being the PRG bank of an
+    /// address of the image. Symbols of other banks are left out.
+    #[allow(dead_code)]
+    pub fn add_symbols(&mut self, symbols: &SymbolTable, bank: impl Fn(u16) -> Option<usize>) {
+        // symbols of any bank come first, the ones of a bank override them
+        for (addr, symbol_bank, name) in symbols.iter() {
+            if symbol_bank.is_none() || symbol_bank == bank(addr) {
+                self.labels.insert(addr, name.to_string());
+            }
+        }
+    }
+
+    /// Text of the instruction or data directive of `line`, with targets
+    /// in the image replaced by their label.
+    pub fn text(&self, line: &Line) -> String {
+        match line {
+            Line::Code {
+                addr,
+                opcode,
+                bytes,
+            } => format_instruction(
+                opcode,
+                *addr,
+                |i| bytes.get(i as usize).copied().unwrap_or(0),
+                |addr| self.labels.get(&addr).cloned(),
+            ),
+            Line::Data { bytes, .. } => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("${:02x}", b)).collect();
+                format!(".byte {}", bytes.join(", "))
+            }
+        }
+    }
+}
+
+impl fmt::Display for Disassembly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for line in &self.lines {
+            if let Some(label) = self.labels.get(&line.addr()) {
+                writeln!(f, "{}:", label)?;
+            }
+            let hex = match line {
+                Line::Code { bytes, .. } => {
+                    let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                    hex.join(" ")
+                }
+                Line::Data { .. } => String::new(),
+            };
+            writeln!(f, "    {:04X}  {:8}  {}", line.addr(), hex, self.text(line))?;
+        }
+        Ok(())
+    }
+}
+
+/// Disassembles the instruction at `addr` as the CPU sees it, returning its
+/// text and length. Bytes that are not an opcode show as `.byte`.
+pub fn disassemble<B: CpuBus>(cpu: &Cpu<B>, addr: u16) -> (String, u16) {
+    let code = cpu.peek(addr);
+    match opcodes::decode(cpu.variant, code) {
+        Some(opcode) => {
+            let operand = |i: u16| cpu.peek(addr.wrapping_add(i));
+            let text = format_instruction(opcode, addr, operand, |addr| {
+                cpu.label(addr).map(String::from)
+            });
+            (text, opcode.len as u16)
+        }
+        None => (format!(".byte ${:02x}", code), 1),
+    }
+}
+
+/// Disassembles the PRG ROM of a cartridge, mapped like the CPU bus does:
+/// at $8000, or mirrored up to $C000 for a single 16k bank.
+pub fn disassemble_rom(rom: &Rom, variant: CpuVariant) -> Disassembly {
+    let image = &rom.prg_rom[..rom.prg_rom.len().min(0x8000)];
+    let base = (0x10000 - image.len()) as u16;
+    disassemble_image(image, base, variant, &[])
+}
+
+/// The PRG bank of `addr` in [`disassemble_rom`], None outside of the ROM.
+pub fn rom_bank(rom: &Rom, addr: u16) -> Option<usize> {
+    let base = 0x10000 - rom.prg_rom.len().min(0x8000);
+    (addr as usize >= base).then(|| (addr as usize - base) / 0x4000)
+}
+
+/// Disassembles `image` loaded at `base`. Code is found by following the
+/// control flow from `entry_points` and from the NMI, reset and IRQ
+/// vectors when the image covers them; everything else is data.
+pub fn disassemble_image(
+    image: &[u8],
+    base: u16,
+    variant: CpuVariant,
+    entry_points: &[u16],
+) -> Disassembly {
+    assert!(
+        base as usize + image.len() <= 0x10000,
+        "image goes past $FFFF"
+    );
+    let offset = |addr: u16| {
+        let offset = addr.wrapping_sub(base) as usize;
+        (addr >= base && offset < image.len()).then_some(offset)
+    };
+
+    let mut names = BTreeMap::new();
+    let mut pending: Vec<u16> = entry_points.to_vec();
+    for (vector, name) in VECTORS {
+        if let (Some(lo), Some(hi)) = (offset(vector), offset(vector + 1)) {
+            let target = u16::from_le_bytes([image[lo], image[hi]]);
+            names.entry(target).or_insert_with(|| name.to_string());
+            pending.push(target);
+        }
+    }
+
+    let mut starts: BTreeMap<u16, &'static OpCode> = BTreeMap::new();
+    let mut covered = vec![false; image.len()];
+    while let Some(mut addr) = pending.pop() {
+        while let Some(start) = offset(addr) {
+            if covered[start] {
+                break;
+            }
+            let Some(opcode) = opcodes::decode(variant, image[start]) else {
+                break;
+            };
+            let end = start + opcode.len as usize;
+            if end > image.len() || covered[start..end].iter().any(|c| *c) {
+                break;
+            }
+            covered[start..end].fill(true);
+            starts.insert(addr, opcode);
+
+            let next = addr.wrapping_add(opcode.len as u16);
+            let operand = |i: usize| image.get(start + i).copied().unwrap_or(0);
+            let word = u16::from_le_bytes([operand(1), operand(2)]);
+            match opcode.op {
+                Op::Bcc | Op::Bcs | Op::Beq | Op::Bmi | Op::Bne | Op::Bpl | Op::Bvc | Op::Bvs => {
+                    pending.push(next.wrapping_add(operand(1) as i8 as u16));
+                }
+                Op::Bra => {
+                    pending.push(next.wrapping_add(operand(1) as i8 as u16));
+                    break;
+                }
+                Op::Jsr => {
+                    names
+                        .entry(word)
+                        .or_insert_with(|| format!("sub_{:04X}", word));
+                    pending.push(word);
+                }
+                Op::Jmp => {
+                    pending.push(word);
+                    break;
+                }
+                Op::JmpIndirect
+                | Op::JmpIndexedIndirect
+                | Op::Rts
+                | Op::Rti
+                | Op::Brk
+                | Op::Jam => break,
+                _ => {}
+            }
+            addr = next;
+        }
+    }
+
+    // Label every jump target that turned out to be an instruction
+    let mut labels = BTreeMap::new();
+    for (&addr, opcode) in &starts {
+        let start = offset(addr).unwrap();
+        let operand = |i: usize| image.get(start + i).copied().unwrap_or(0);
+        if let Some(target) = jump_target(opcode, addr, operand(1), operand(2)) {
+            if starts.contains_key(&target) {
+                labels.insert(target, format!("L_{:04X}", target));
+            }
+        }
+    }
+    for (addr, name) in names {
+        if starts.contains_key(&addr) {
+            labels.insert(addr, name);
+        }
+    }
+    for addr in entry_points.iter().filter(|addr| starts.contains_key(addr)) {
+        labels
+            .entry(*addr)
+            .or_insert_with(|| format!("L_{:04X}", addr));
+    }
+
+    let mut lines = vec![];
+    let mut start = 0;
+    while start < image.len() {
+        let addr = base.wrapping_add(start as u16);
+        if let Some(&opcode) = starts.get(&addr) {
+            let end = start + opcode.len as usize;
+            lines.push(Line::Code {
+                addr,
+                opcode,
+                bytes: image[start..end].to_vec(),
+            });
+            start = end;
+            continue;
+        }
+        // data runs until the next instruction or label
+        let mut end = start + 1;
+        while end < image.len() && end - start < DATA_PER_LINE && !covered[end] {
+            if labels.contains_key(&base.wrapping_add(end as u16)) {
+                break;
+            }
+            end += 1;
+        }
+        lines.push(Line::Data {
+            addr,
+            bytes: image[start..end].to_vec(),
+        });
+        start = end;
+    }
+
+    Disassembly { labels, lines }
+}
+
+// Where a branch, JMP or JSR goes.
+fn jump_target(opcode: &OpCode, addr: u16, lo: u8, hi: u8) -> Option<u16> {
+    match (opcode.op, opcode.mode, opcode.len) {
+        (Op::Jmp | Op::Jsr, _, _) => Some(u16::from_le_bytes([lo, hi])),
+        // relative branches
+        (_, AddressingMode::NoneAddressing, 2) => {
+            Some(addr.wrapping_add(2).wrapping_add(lo as i8 as u16))
+        }
+        _ => None,
+    }
+}
+
+// `operand(i)` is the i-th byte of the instruction, `label` names addresses
+// used by zero page and absolute operands and jumps.
+fn format_instruction(
+    opcode: &OpCode,
+    addr: u16,
+    operand: impl Fn(u16) -> u8,
+    label: impl Fn(u16) -> Option<String>,
+) -> String {
+    let byte = operand(1);
+    let word = u16::from_le_bytes([operand(1), operand(2)]);
+    let target = |addr: u16| label(addr).unwrap_or_else(|| format!("${:04x}", addr));
+    let zero_page = label(byte as u16).unwrap_or_else(|| format!("${:02x}", byte));
+
+    let operand = match opcode.mode {
+        AddressingMode::Immediate => format!("#${:02x}", byte),
+        AddressingMode::ZeroPage => zero_page,
+        AddressingMode::ZeroPageX => format!("{},X", zero_page),
+        AddressingMode::ZeroPageY => format!("{},Y", zero_page),
+        AddressingMode::Absolute => target(word),
+        AddressingMode::AbsoluteX => format!("{},X", target(word)),
+        AddressingMode::AbsoluteY => format!("{},Y", target(word)),
+        AddressingMode::IndirectX => format!("({},X)", zero_page),
+        AddressingMode::IndirectY => format!("({}),Y", zero_page),
+        AddressingMode::ZeroPageIndirect => format!("({})", zero_page),
+        AddressingMode::NoneAddressing => match opcode.op {
+            Op::AslA | Op::LsrA | Op::RolA | Op::RorA | Op::IncA | Op::DecA => String::from("A"),
+            Op::JmpIndirect => format!("(${:04x})", word),
+            Op::JmpIndexedIndirect => format!("(${:04x},X)", word),
+            _ => match jump_target(opcode, addr, byte, operand(2)) {
+                Some(addr) => target(addr),
+                None if opcode.len == 3 => format!("${:04x}", word),
+                None => String::new(),
+            },
+        },
+    };
+    let text = format!("{} {}", opcode.mnemonic, operand);
+    text.trim_end().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+
+    #[test]
+    fn test_disassemble() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        // LDA ($10),Y; ASL A; BNE -4; JMP ($0200)
+        let program = [0xb1, 0x10, 0x0a, 0xd0, 0xfc, 0x6c, 0x00, 0x02];
+        for (i, byte) in program.iter().enumerate() {
+            cpu.mem_write(0x0600 + i as u16, *byte);
+        }
+        let mut addr = 0x0600;
+        let mut listing = vec![];
+        while addr < 0x0600 + program.len() as u16 {
+            let (text, len) = disassemble(&cpu, addr);
+            listing.push(text);
+            addr += len;
+        }
+        assert_eq!(
+            listing,
+            vec!["LDA ($10),Y", "ASL A", "BNE $0601", "JMP ($0200)"]
+        );
+    }
+
+    #[test]
+    fn test_disassemble_image() {
+        let mut image = vec![0; 0x20];
+        #[rustfmt::skip]
+        let code = [
+            // $FFE0 reset: JSR sub; LDA $FFF0,X; BNE reset; JMP $FFE0
+            0x20, 0xed, 0xff, 0xbd, 0xf0, 0xff, 0xd0, 0xf8, 0x4c, 0xe0, 0xff,
+            // data
+            0x12, 0x34,
+            // $FFED sub: RTI
+            0x40,
+        ];
+        image[..code.len()].copy_from_slice(&code);
+        // NMI and IRQ share sub, reset is $FFE0
+        image[0x1a..].copy_from_slice(&[0xed, 0xff, 0xe0, 0xff, 0xed, 0xff]);
+
+        let disassembly = disassemble_image(&image, 0xffe0, CpuVariant::default(), &[]);
+        assert_eq!(disassembly.labels.len(), 2);
+        assert_eq!(disassembly.labels[&0xffe0], "reset");
+        assert_eq!(disassembly.labels[&0xffed], "nmi");
+
+        let listing = disassembly.to_string();
+        let lines: Vec<&str> = listing.lines().collect();
+        assert_eq!(lines[0], "reset:");
+        assert_eq!(lines[1], "    FFE0  20 ED FF  JSR nmi");
+        assert_eq!(lines[2], "    FFE3  BD F0 FF  LDA $fff0,X");
+        assert_eq!(lines[3], "    FFE6  D0 F8     BNE reset");
+        assert_eq!(lines[4], "    FFE8  4C E0 FF  JMP reset");
+        assert_eq!(lines[5], "    FFEB            .byte $12, $34");
+        assert_eq!(lines[6], "nmi:");
+        assert_eq!(lines[7], "    FFED  40        RTI");
+        assert_eq!(
+            lines[8],
+            "    FFEE            .byte $00, $00, $00, $00, $00, $00, $00, $00"
+        );
+        assert_eq!(lines.len(), 11);
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        // LDA $10; JSR $C000 in ROM bank 1
+        for (i, byte) in [0xa5, 0x10, 0x20, 0x00, 0xc0].iter().enumerate() {
+            cpu.mem_write(0x0600 + i as u16, *byte);
+        }
+        cpu.symbols.insert(0x0010, None, "frame");
+        cpu.symbols.insert(0xc000, Some(0), "Bank0Entry");
+        cpu.symbols.insert(0xc000, Some(1), "Bank1Entry");
+        assert_eq!(disassemble(&cpu, 0x0600).0, "LDA frame");
+        assert_eq!(disassemble(&cpu, 0x0602).0, "JSR Bank1Entry");
+
+        // $FFE0 reset: JMP $FFE0, in the second bank of a 32k image
+        let mut image = vec![0; 0x20];
+        image[..3].copy_from_slice(&[0x4c, 0xe0, 0xff]);
+        image[0x1c..0x1e].copy_from_slice(&[0xe0, 0xff]);
+        let mut disassembly = disassemble_image(&image, 0xffe0, CpuVariant::default(), &[]);
+        let mut symbols = SymbolTable::default();
+        symbols.insert(0xffe0, Some(0), "OtherBank");
+        symbols.insert(0xffe0, Some(1), "Main");
+        disassembly.add_symbols(&symbols, |_| Some(1));
+        assert_eq!(disassembly.labels[&0xffe0], "Main");
+        assert!(disassembly.to_string().contains("JMP Main"));
+    }
+}
@@ -0,0 +1,43 @@
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EmuError {
+    /// The CPU fetched an opcode it does not know how to decode.
+    IllegalOpcode {
+        code: u8,
+        pc: u16,
+    },
+    /// A bus access hit a region that cannot handle it.
+    BusFault {
+        addr: u16,
+        write: bool,
+        reason: &'static str,
+    },
+    UnsupportedMapper(u8),
+    MalformedRom(&'static str),
+}
+
+impl fmt::Display for EmuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EmuError::IllegalOpcode { code, pc } => {
+                write!(f, "illegal opcode {:#04x} at {:#06x}", code, pc)
+            }
+            EmuError::BusFault {
+                addr,
+                write,
+                reason,
+            } => write!(
+                f,
+                "bus fault on {} at {:#06x}: {}",
+                if *write { "write" } else { "read" },
+                addr,
+                reason
+            ),
+            EmuError::UnsupportedMapper(mapper) => write!(f, "mapper {} is not supported", mapper),
+            EmuError::MalformedRom(reason) => write!(f, "invalid ROM: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for EmuError {}
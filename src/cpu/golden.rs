@@ -0,0 +1,315 @@
+// Comparison of the trace of a run against a reference log in the
+// nestest.log layout, like the one of https://www.qmtpro.com/~nes/misc/nestest.log
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, BufRead};
+
+use crate::cpu::trace::trace;
+use crate::cpu::{Cpu, CpuBus, EmuError};
+
+/// Columns of the reference logs that are not emulated yet.
+pub const IGNORED_FIELDS: [&str; 2] = ["PPU", "CYC"];
+
+// Matching lines shown before a divergence
+const CONTEXT: usize = 5;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldDiff {
+    pub field: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+/// The first line of a run that does not match the reference log.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    /// Line number in the log, from 1.
+    pub line: usize,
+    /// The log lines before, that matched.
+    pub context: Vec<String>,
+    pub expected: String,
+    pub actual: String,
+    pub diffs: Vec<FieldDiff>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "divergence at line {} of the log", self.line)?;
+        for line in &self.context {
+            writeln!(f, "  {}", line)?;
+        }
+        writeln!(f, "- {}", self.expected)?;
+        writeln!(f, "+ {}", self.actual)?;
+        for diff in &self.diffs {
+            writeln!(
+                f,
+                "  {}: expected {}, got {}",
+                diff.field, diff.expected, diff.actual
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// The columns of a trace line
+struct Fields<'a> {
+    pc: &'a str,
+    bytes: &'a str,
+    // the instruction, then the parts of its memory operand: `@ 0305`, `= 77`
+    instruction: Vec<&'a str>,
+    registers: Vec<(&'a str, &'a str)>,
+}
+
+fn fields(line: &str) -> Fields<'_> {
+    let column = |range: std::ops::Range<usize>| line.get(range).unwrap_or("").trim();
+    let registers_at = line.find(" A:").unwrap_or(line.len());
+    let mut instruction = vec![];
+    let mut rest = column(15..registers_at);
+    while let Some(at) = rest.rfind(" = ").into_iter().chain(rest.rfind(" @ ")).max() {
+        instruction.push(rest[at..].trim());
+        rest = &rest[..at];
+    }
+    instruction.push(rest);
+    instruction.reverse();
+    Fields {
+        pc: column(0..4),
+        bytes: column(6..15),
+        instruction,
+        registers: line[registers_at..]
+            .split_whitespace()
+            .filter_map(|token| token.split_once(':'))
+            .collect(),
+    }
+}
+
+/// Field by field differences between a reference log line and a line of
+/// [`trace`]. Registers missing from either line are not compared, nor are
+/// the [`IGNORED_FIELDS`].
+pub fn compare(expected: &str, actual: &str) -> Vec<FieldDiff> {
+    let (expected, actual) = (fields(expected), fields(actual));
+    let mut diffs = vec![];
+    let mut diff = |field: &str, expected: &str, actual: &str| {
+        if expected != actual {
+            diffs.push(FieldDiff {
+                field: field.to_string(),
+                expected: expected.to_string(),
+                actual: actual.to_string(),
+            });
+        }
+    };
+    diff("PC", expected.pc, actual.pc);
+    diff("bytes", expected.bytes, actual.bytes);
+    if expected.instruction.len() != actual.instruction.len() {
+        diff(
+            "instruction",
+            &expected.instruction.join(" "),
+            &actual.instruction.join(" "),
+        );
+    } else {
+        let count = expected.instruction.len();
+        let parts = expected.instruction.iter().zip(&actual.instruction);
+        for (i, (expected, actual)) in parts.enumerate() {
+            // like `LDA ($80),Y = 0200 @ 0200 = 5A`
+            let field = if i == 0 {
+                "instruction"
+            } else if expected.starts_with('@') {
+                "address"
+            } else if i + 1 == count {
+                "value"
+            } else {
+                "pointer"
+            };
+            diff(field, expected, actual);
+        }
+    }
+    for (name, value) in &expected.registers {
+        if IGNORED_FIELDS.contains(name) {
+            continue;
+        }
+        let Some((_, actual)) = actual.registers.iter().find(|(n, _)| n == name) else {
+            continue;
+        };
+        diff(name, value, actual);
+        if *name == "P" {
+            let p = |value: &str| u8::from_str_radix(value, 16).ok();
+            if let (Some(expected), Some(actual)) = (p(value), p(actual)) {
+                for (bit, flag) in "NV-BDIZC".chars().enumerate() {
+                    let mask = 0x80 >> bit;
+                    if (expected ^ actual) & mask != 0 {
+                        let state = |p: u8| if p & mask != 0 { "set" } else { "clear" };
+                        diff(&format!("flag {}", flag), state(expected), state(actual));
+                    }
+                }
+            }
+        }
+    }
+    diffs
+}
+
+// The trace of the next instruction, once the pending interrupts ran.
+fn next_line<B: CpuBus>(cpu: &mut Cpu<B>) -> Result<String, String> {
+    while cpu.interrupt_pending() {
+        cpu.step().map_err(stopped_by)?;
+    }
+    trace(cpu).map_err(|err| err.to_string())
+}
+
+fn stopped_by(err: EmuError) -> String {
+    format!("emulation stopped: {}", err)
+}
+
+/// What [`run`] found.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    /// The log lines that matched.
+    pub matched: usize,
+    pub divergence: Option<Divergence>,
+}
+
+/// Runs `cpu` from the first PC of `log`, comparing each instruction with
+/// the next log line until the log ends or they diverge. NMIs and IRQs are
+/// not logged, so they are not compared.
+pub fn run<B: CpuBus>(cpu: &mut Cpu<B>, log: impl BufRead) -> io::Result<Outcome> {
+    let mut context = VecDeque::with_capacity(CONTEXT);
+    let mut matched = 0;
+    let mut stopped = None;
+    for (number, expected) in log.lines().enumerate() {
+        let expected = expected?;
+        if expected.trim().is_empty() {
+            continue;
+        }
+        if matched == 0 {
+            if let Ok(pc) = u16::from_str_radix(fields(&expected).pc, 16) {
+                cpu.pc = pc;
+            }
+        }
+
+        let actual = stopped.take().map_or_else(|| next_line(cpu), Err);
+        let diffs = match &actual {
+            Ok(actual) => compare(&expected, actual),
+            Err(err) => vec![FieldDiff {
+                field: String::from("emulation"),
+                expected: String::from("an instruction"),
+                actual: err.clone(),
+            }],
+        };
+        if !diffs.is_empty() {
+            return Ok(Outcome {
+                matched,
+                divergence: Some(Divergence {
+                    line: number + 1,
+                    context: context.into(),
+                    expected,
+                    actual: actual.unwrap_or_else(|err| err),
+                    diffs,
+                }),
+            });
+        }
+        matched += 1;
+        if context.len() == CONTEXT {
+            context.pop_front();
+        }
+        context.push_back(expected);
+        if let Err(err) = cpu.step() {
+            stopped = Some(stopped_by(err));
+        }
+    }
+    Ok(Outcome {
+        matched,
+        divergence: None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::ram::FlatRam;
+    use crate::cpu::Mem;
+
+    const PROGRAM: &str = "
+        LDX #$05
+        LDA $0300,X
+        SEC
+        .byte $02 ; *JAM
+    ";
+
+    fn golden() -> Vec<String> {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.load_assembly(PROGRAM).unwrap();
+        cpu.bus.mem_write(0x0305, 0x77);
+        let mut lines = vec![];
+        cpu.run_with_callback(|cpu| {
+            let line = trace(cpu).unwrap();
+            lines.push(format!("{} PPU:  0, 21 CYC:{}", line, cpu.cycles + 7));
+        })
+        .unwrap();
+        lines
+    }
+
+    fn run_against(log: &[String]) -> Outcome {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.load_assembly(PROGRAM).unwrap();
+        cpu.bus.mem_write(0x0305, 0x77);
+        cpu.pc = 0;
+        run(&mut cpu, log.join("\n").as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn test_compare_fields() {
+        let expected = "C72A  B1 80     LDA ($80),Y = 0200 @ 0200 = 5A  A:00 X:00 Y:00 P:27 SP:FB PPU: 16,138 CYC:1234";
+        let actual = "C72A  B1 80     LDA ($80),Y = 0200 @ 0200 = 5A  A:00 X:00 Y:00 P:27 SP:FB";
+        assert_eq!(compare(expected, actual), vec![]);
+
+        let actual = "C72A  B1 80     LDA ($80),Y = 0300 @ 0300 = 5B  A:00 X:00 Y:00 P:A6 SP:FB";
+        let diffs: Vec<(String, String, String)> = compare(expected, actual)
+            .into_iter()
+            .map(|d| (d.field, d.expected, d.actual))
+            .collect();
+        let diff = |field: &str, expected: &str, actual: &str| {
+            (field.to_string(), expected.to_string(), actual.to_string())
+        };
+        assert_eq!(
+            diffs,
+            [
+                diff("pointer", "= 0200", "= 0300"),
+                diff("address", "@ 0200", "@ 0300"),
+                diff("value", "= 5A", "= 5B"),
+                diff("P", "27", "A6"),
+                diff("flag N", "clear", "set"),
+                diff("flag C", "set", "clear"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_run_against_log() {
+        let log = golden();
+        assert_eq!(log.len(), 4);
+        assert_eq!(
+            run_against(&log),
+            Outcome {
+                matched: 4,
+                divergence: None
+            }
+        );
+
+        let mut log = golden();
+        log[2] = log[2].replace("P:24", "P:25");
+        let outcome = run_against(&log);
+        assert_eq!(outcome.matched, 2);
+        let divergence = outcome.divergence.unwrap();
+        assert_eq!(divergence.line, 3);
+        assert_eq!(divergence.context, log[..2]);
+        assert_eq!(divergence.diffs.len(), 2);
+        assert_eq!(divergence.diffs[1].field, "flag C");
+        let report = divergence.to_string();
+        assert!(report.contains("\n- 0605  38        SEC"));
+        assert!(report.contains("\n  flag C: expected set, got clear\n"));
+
+        // the log goes on after the CPU halted
+        let mut log = golden();
+        log.push(log[0].clone());
+        assert_eq!(run_against(&log).divergence.unwrap().line, 5);
+    }
+}
@@ -0,0 +1,203 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::cpu::callstack::{CallEvent, Frame};
+use crate::cpu::Interrupt;
+
+// Stepping back restores RAM and PRG RAM. The devices behind the I/O
+// registers cannot be rewound, writing the old values back would only
+// disturb them.
+const RAM: RangeInclusive<u16> = 0x0000..=0x1fff;
+const PRG_RAM: RangeInclusive<u16> = 0x6000..=0x7fff;
+const REGISTERS: RangeInclusive<u16> = 0x2000..=0x401f;
+
+/// A memory write made by an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemWrite {
+    pub addr: u16,
+    /// The value before the write, as `peek` saw it.
+    pub old: u8,
+    pub new: u8,
+}
+
+impl MemWrite {
+    pub(crate) fn writes_register(&self) -> bool {
+        REGISTERS.contains(&self.addr)
+    }
+
+    pub(crate) fn writes_memory(&self) -> bool {
+        RAM.contains(&self.addr) || PRG_RAM.contains(&self.addr)
+    }
+}
+
+/// Why [`Cpu::step_back`](crate::cpu::Cpu::step_back) did not undo an
+/// instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepBackError {
+    NoHistory,
+    /// The instruction at `pc` wrote to the I/O register at `addr`.
+    RegisterWrite {
+        pc: u16,
+        addr: u16,
+    },
+}
+
+impl fmt::Display for StepBackError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            StepBackError::NoHistory => write!(f, "no more history"),
+            StepBackError::RegisterWrite { pc, addr } => write!(
+                f,
+                "cannot step back over ${:04X}, it wrote to the I/O register ${:04X}",
+                pc, addr
+            ),
+        }
+    }
+}
+
+/// The state an instruction started from, and the writes it made.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Record {
+    pub pc: u16,
+    /// The instruction bytes, `len` of them are meaningful.
+    pub bytes: [u8; 3],
+    pub len: u8,
+    /// Set when an interrupt sequence ran instead of the instruction at PC.
+    pub interrupt: Option<Interrupt>,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub cycles: u64,
+    pub writes: Vec<MemWrite>,
+    /// Whether the instruction pushed a frame on the shadow call stack.
+    pub called: bool,
+    /// The frames it popped from the shadow call stack, innermost first.
+    pub returned: Vec<Frame>,
+}
+
+impl fmt::Display for Record {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let code = match self.interrupt {
+            Some(interrupt) => format!("{:?}", interrupt).to_ascii_uppercase(),
+            None => {
+                let bytes: Vec<String> = self.bytes[..self.len as usize]
+                    .iter()
+                    .map(|b| format!("{:02X}", b))
+                    .collect();
+                bytes.join(" ")
+            }
+        };
+        write!(
+            f,
+            "{:04X}  {:8}  A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            self.pc, code, self.a, self.x, self.y, self.p, self.sp, self.cycles
+        )?;
+        for write in &self.writes {
+            write!(
+                f,
+                " {:04X}:{:02X}->{:02X}",
+                write.addr, write.old, write.new
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Ring buffer of the last executed instructions of a
+/// [`Cpu`](crate::cpu::Cpu), for dumping and
+/// [`Cpu::step_back`](crate::cpu::Cpu::step_back). Nothing is recorded
+/// while the capacity is 0, the default.
+#[derive(Debug, Default)]
+pub struct History {
+    capacity: usize,
+    records: VecDeque<Record>,
+}
+
+#[allow(dead_code)]
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            capacity,
+            records: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Changes how many instructions are kept, dropping the oldest ones.
+    pub fn set_capacity(&mut self, capacity: usize) {
+        self.capacity = capacity;
+        while self.records.len() > capacity {
+            self.records.pop_front();
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Records from the oldest to the most recent.
+    pub fn records(&self) -> impl DoubleEndedIterator<Item = &Record> + ExactSizeIterator {
+        self.records.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.records.clear();
+    }
+
+    pub(crate) fn recording(&self) -> bool {
+        self.capacity > 0
+    }
+
+    // A record for the instruction about to run. Once the buffer is full
+    // the oldest record is reused, so that recording does not allocate.
+    pub(crate) fn start(&mut self) -> &mut Record {
+        let mut record = if self.records.len() >= self.capacity {
+            self.records.pop_front().unwrap_or_default()
+        } else {
+            Record::default()
+        };
+        record.interrupt = None;
+        record.writes.clear();
+        record.called = false;
+        record.returned.clear();
+        self.records.push_back(record);
+        self.records.back_mut().unwrap()
+    }
+
+    pub(crate) fn on_interrupt(&mut self, interrupt: Interrupt) {
+        if let Some(record) = self.records.back_mut() {
+            record.interrupt = Some(interrupt);
+        }
+    }
+
+    pub(crate) fn on_write(&mut self, addr: u16, old: u8, new: u8) {
+        if let Some(record) = self.records.back_mut() {
+            record.writes.push(MemWrite { addr, old, new });
+        }
+    }
+
+    // How the recorded step changed the call stack, `popped` being the
+    // frames a return popped.
+    pub(crate) fn on_call_event(&mut self, event: CallEvent, popped: &[Frame]) {
+        if let Some(record) = self.records.back_mut() {
+            match event {
+                CallEvent::Call(_) => record.called = true,
+                CallEvent::Return(_) => record.returned.extend_from_slice(popped),
+            }
+        }
+    }
+
+    pub(crate) fn pop(&mut self) -> Option<Record> {
+        self.records.pop_back()
+    }
+}
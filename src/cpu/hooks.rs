@@ -0,0 +1,186 @@
+// Callbacks on the memory accesses of the CPU bus, for tools watching the
+// memory traffic: RAM watches, cheat searches, achievement checks...
+
+use std::ops::RangeInclusive;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    /// The fetch of an instruction's opcode.
+    Exec,
+}
+
+impl AccessKind {
+    fn bit(self) -> u8 {
+        match self {
+            AccessKind::Read => 1,
+            AccessKind::Write => 2,
+            AccessKind::Exec => 4,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+    pub kind: AccessKind,
+    pub addr: u16,
+    /// The byte read or written, or the opcode.
+    pub value: u8,
+}
+
+/// Identifies a hook, to remove it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HookId(u64);
+
+struct Hook {
+    id: HookId,
+    kind: AccessKind,
+    range: RangeInclusive<u16>,
+    callback: Box<dyn FnMut(&Access)>,
+}
+
+/// The hooks of a bus. Which 256 byte pages have hooks of each kind is
+/// kept in a table, so that accesses elsewhere cost a lookup in it.
+pub struct Hooks {
+    hooks: Vec<Hook>,
+    pages: [u8; 256],
+    next_id: u64,
+}
+
+impl Default for Hooks {
+    fn default() -> Self {
+        Hooks {
+            hooks: vec![],
+            pages: [0; 256],
+            next_id: 0,
+        }
+    }
+}
+
+#[allow(dead_code)]
+impl Hooks {
+    /// Calls `callback` on each access of `kind` to an address in `range`.
+    pub fn add(
+        &mut self,
+        kind: AccessKind,
+        range: RangeInclusive<u16>,
+        callback: impl FnMut(&Access) + 'static,
+    ) -> HookId {
+        let id = HookId(self.next_id);
+        self.next_id += 1;
+        self.hooks.push(Hook {
+            id,
+            kind,
+            range,
+            callback: Box::new(callback),
+        });
+        self.update_pages();
+        id
+    }
+
+    /// Returns false if there is no such hook.
+    pub fn remove(&mut self, id: HookId) -> bool {
+        let count = self.hooks.len();
+        self.hooks.retain(|hook| hook.id != id);
+        self.update_pages();
+        self.hooks.len() != count
+    }
+
+    pub fn clear(&mut self) {
+        self.hooks.clear();
+        self.pages = [0; 256];
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    fn update_pages(&mut self) {
+        self.pages = [0; 256];
+        for hook in &self.hooks {
+            let (start, end) = (*hook.range.start() >> 8, *hook.range.end() >> 8);
+            for page in start..=end {
+                self.pages[page as usize] |= hook.kind.bit();
+            }
+        }
+    }
+
+    /// Whether accesses of `kind` to `addr` may have hooks.
+    #[inline]
+    pub(crate) fn watches(&self, kind: AccessKind, addr: u16) -> bool {
+        self.pages[(addr >> 8) as usize] & kind.bit() != 0
+    }
+
+    /// Runs the hooks of an access, if it is watched.
+    #[inline]
+    pub(crate) fn on_access(&mut self, kind: AccessKind, addr: u16, value: u8) {
+        if self.watches(kind, addr) {
+            self.run(Access { kind, addr, value });
+        }
+    }
+
+    fn run(&mut self, access: Access) {
+        for hook in &mut self.hooks {
+            if hook.kind == access.kind && hook.range.contains(&access.addr) {
+                (hook.callback)(&access);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use crate::cpu::{Cpu, Mem};
+
+    #[test]
+    fn test_hooks() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.load_assembly(
+            "
+            LDA #$2a        ; $0600
+            STA $10
+            LDA $0810       ; mirror of $0010
+            STA $11
+            .byte $02
+            ",
+        )
+        .unwrap();
+        let log = Rc::new(RefCell::new(vec![]));
+        let watch = |log: &Rc<RefCell<Vec<Access>>>| {
+            let log = Rc::clone(log);
+            move |access: &Access| log.borrow_mut().push(*access)
+        };
+        let reads = cpu
+            .bus
+            .add_hook(AccessKind::Read, 0x0800..=0x08ff, watch(&log));
+        cpu.bus
+            .add_hook(AccessKind::Write, 0x0010..=0x0010, watch(&log));
+        cpu.bus
+            .add_hook(AccessKind::Exec, 0x0602..=0x0604, watch(&log));
+        let _ = cpu.run();
+
+        let access = |kind, addr, value| Access { kind, addr, value };
+        assert_eq!(
+            *log.borrow(),
+            [
+                access(AccessKind::Exec, 0x0602, 0x85),
+                access(AccessKind::Write, 0x0010, 0x2a),
+                access(AccessKind::Exec, 0x0604, 0xad),
+                access(AccessKind::Read, 0x0810, 0x2a),
+            ]
+        );
+
+        assert!(cpu.bus.remove_hook(reads));
+        assert!(!cpu.bus.remove_hook(reads));
+        log.borrow_mut().clear();
+        cpu.mem_read(0x0810);
+        cpu.mem_write(0x0010, 1);
+        assert_eq!(*log.borrow(), [access(AccessKind::Write, 0x0010, 1)]);
+    }
+}
@@ -1,10 +1,9 @@
-use std::collections::HashMap;
-
 mod status;
 use status::StatusFlag::*;
 use status::*;
 
 mod opcodes;
+use opcodes::Op;
 
 pub mod cartridge;
 use cartridge::Rom;
@@ -12,23 +11,150 @@ use cartridge::Rom;
 mod bus;
 use bus::*;
 
+mod error;
+pub use error::EmuError;
+
+pub mod debugger;
+pub use debugger::{BreakReason, Debugger, WatchKind};
+
+pub mod history;
+pub use history::{History, StepBackError};
+
+pub mod ram;
+
 pub mod trace;
 
+pub mod disasm;
+
+pub mod ca65;
+
+pub mod asm;
+
+pub mod cdl;
+
+pub mod hooks;
+
+pub mod cheats;
+
+pub mod callstack;
+
+pub mod golden;
+use callstack::{CallEvent, CallStack, Frame, FrameKind};
+
+pub mod profiler;
+use asm::AsmError;
+pub use profiler::Profiler;
+
+pub mod symbols;
+use symbols::SymbolTable;
+
 const STACK: u16 = 0x0100;
 const STACK_RESET: u8 = 0xfd;
+const NMI_BASE: u16 = 0xFFFA;
 const BRK_IRQ_BASE: u16 = 0xFFFE;
 
-pub struct Cpu {
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+    Brk,
+}
+
+/// What happened during a [`Cpu::step`] besides executing an instruction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepEvent {
+    /// An interrupt sequence was executed: a hardware interrupt replaces the
+    /// instruction, BRK reports it after its own execution.
+    Interrupt(Interrupt),
+    /// The CPU executed a JAM opcode and is locked up until reset.
+    Halted,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    /// The executed opcode. Hardware interrupts force a BRK (0x00) into the
+    /// instruction register, so this is what they report.
+    pub opcode: u8,
+    pub cycles: u8,
+    pub event: Option<StepEvent>,
+}
+
+/// How the CPU drives the bus.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExecMode {
+    /// Instructions execute atomically and the bus is ticked once with the
+    /// total cycle count of each instruction. Dummy accesses are skipped.
+    #[default]
+    Instruction,
+    /// Every bus access happens on its own cycle, including the dummy reads
+    /// and writes of the real 6502, and the bus is ticked after each one.
+    Cycle,
+}
+
+/// The flavour of 6502 being emulated.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CpuVariant {
+    /// The NES CPU: an NMOS 6502 whose decimal mode is disconnected.
+    #[default]
+    Ricoh2A03,
+    /// The original NMOS 6502, with BCD arithmetic when D is set.
+    Nmos6502,
+    /// The CMOS 65C02: BCD with valid flags, fixed indirect JMP and the
+    /// extra instructions. Opcodes it does not define are NOPs.
+    Cmos65C02,
+}
+
+// The kind of access an instruction makes to its operand, which decides
+// the dummy reads done while indexing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+    ReadModifyWrite,
+}
+
+/// Why one of the `run_*` methods returned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    CyclesElapsed,
+    PcReached,
+    Predicate,
+    Halted,
+    Break(BreakReason),
+}
+
+impl Interrupt {
+    fn vector(&self) -> u16 {
+        match self {
+            Interrupt::Nmi => NMI_BASE,
+            Interrupt::Irq | Interrupt::Brk => BRK_IRQ_BASE,
+        }
+    }
+}
+
+pub struct Cpu<B = Bus> {
     pub a: u8,
     pub x: u8,
     pub y: u8,
     pub ps: Status,
     pub pc: u16,
     pub sp: u8,
-    pub bus: Bus,
+    pub bus: B,
+    pub cycles: u64,
+    pub exec_mode: ExecMode,
+    pub variant: CpuVariant,
+    pub debugger: Debugger,
+    pub history: History,
+    pub call_stack: CallStack,
+    pub profiler: Profiler,
+    pub symbols: SymbolTable,
+    jammed: bool,
+    // address and length of the instruction being executed
+    instruction: (u16, u8),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddressingMode {
     Immediate,
     ZeroPage,
@@ -39,17 +165,31 @@ pub enum AddressingMode {
     AbsoluteY,
     IndirectX,
     IndirectY,
+    ZeroPageIndirect,
     NoneAddressing,
 }
 
 pub trait Mem {
-    fn mem_read(&self, addr: u16) -> u8;
+    /// Reads like the CPU does, including any side effect on the device
+    /// (acknowledging a status flag, advancing a data port, ...).
+    fn mem_read(&mut self, addr: u16) -> u8 {
+        self.peek(addr)
+    }
+
+    /// Reads without side effects, for tracing and debugging.
+    fn peek(&self, addr: u16) -> u8;
 
     fn mem_write(&mut self, addr: u16, data: u8);
 
-    fn mem_read_u16(&self, pos: u16) -> u16 {
+    fn mem_read_u16(&mut self, pos: u16) -> u16 {
         let low = self.mem_read(pos) as u16;
-        let high = self.mem_read(pos + 1) as u16;
+        let high = self.mem_read(pos.wrapping_add(1)) as u16;
+        (high << 8) | (low as u16)
+    }
+
+    fn peek_u16(&self, pos: u16) -> u16 {
+        let low = self.peek(pos) as u16;
+        let high = self.peek(pos.wrapping_add(1)) as u16;
         (high << 8) | (low as u16)
     }
 
@@ -57,21 +197,79 @@ pub trait Mem {
         let high = (data >> 8) as u8;
         let low = (data & 0xff) as u8;
         self.mem_write(pos, low);
-        self.mem_write(pos + 1, high);
+        self.mem_write(pos.wrapping_add(1), high);
     }
 }
-impl Mem for Cpu {
-    fn mem_read(&self, addr: u16) -> u8 {
+
+/// The system the CPU is plugged into: memory, plus the interrupt lines and
+/// fault reporting, which default to a bus that has neither.
+pub trait CpuBus: Mem {
+    fn nmi_pending(&self) -> bool {
+        false
+    }
+
+    /// Returns true once per NMI edge.
+    fn poll_nmi(&mut self) -> bool {
+        false
+    }
+
+    fn irq(&self) -> bool {
+        false
+    }
+
+    fn take_fault(&mut self) -> Option<EmuError> {
+        None
+    }
+
+    /// Called as CPU cycles elapse, so devices can run alongside the CPU:
+    /// once per access in cycle mode, once per instruction otherwise.
+    fn tick(&mut self, _cycles: u8) {}
+
+    /// Called before the CPU fetches the `len` bytes of the instruction at
+    /// `pc`, so that reads there can be told apart from data reads.
+    fn fetch_instruction(&mut self, _pc: u16, _len: u8) {}
+
+    /// The bus as a NES bus, for the tools that need its cartridge.
+    fn as_nes(&self) -> Option<&dyn NesBus> {
+        None
+    }
+
+    fn as_nes_mut(&mut self) -> Option<&mut dyn NesBus> {
+        None
+    }
+}
+
+/// What the bus of a NES adds to [`CpuBus`].
+pub trait NesBus: CpuBus {
+    /// The 16k PRG ROM bank mapped at `addr`, if it is cartridge space.
+    fn prg_bank(&self, addr: u16) -> Option<usize>;
+
+    /// The cheats applied by the bus.
+    fn cheats(&mut self) -> &mut cheats::Cheats;
+}
+
+impl<B: CpuBus> Mem for Cpu<B> {
+    fn mem_read(&mut self, addr: u16) -> u8 {
         self.bus.mem_read(addr)
     }
 
+    fn peek(&self, addr: u16) -> u8 {
+        self.bus.peek(addr)
+    }
+
     fn mem_write(&mut self, addr: u16, data: u8) {
         self.bus.mem_write(addr, data)
     }
 }
 
-impl Cpu {
-    pub fn new(rom: Rom) -> Self {
+impl Cpu<Bus> {
+    pub fn new(rom: Rom) -> Result<Self, EmuError> {
+        Ok(Cpu::with_bus(Bus::new(rom)?))
+    }
+}
+
+impl<B: CpuBus> Cpu<B> {
+    pub fn with_bus(bus: B) -> Self {
         Cpu {
             a: 0,
             x: 0,
@@ -79,10 +277,47 @@ impl Cpu {
             ps: Status::new(),
             pc: 0,
             sp: STACK_RESET,
-            bus: Bus::new(rom),
+            bus,
+            cycles: 0,
+            exec_mode: ExecMode::Instruction,
+            variant: CpuVariant::Ricoh2A03,
+            debugger: Debugger::new(),
+            history: History::default(),
+            call_stack: CallStack::default(),
+            profiler: Profiler::default(),
+            symbols: SymbolTable::default(),
+            jammed: false,
+            instruction: (0, 0),
         }
     }
 
+    /// Assembles `source` (see [`asm`]), writes it to memory through the bus
+    /// and points PC at its first segment. A segment the bus cannot hold,
+    /// like one in cartridge ROM, is an error of its `.org` line.
+    pub fn load_assembly(&mut self, source: &str) -> Result<(), AsmError> {
+        let segments = asm::assemble(source)?;
+        for segment in &segments {
+            for (i, byte) in segment.bytes.iter().enumerate() {
+                self.mem_write(segment.origin.wrapping_add(i as u16), *byte);
+            }
+            if let Some(fault) = self.bus.take_fault() {
+                return Err(AsmError {
+                    line: segment.line,
+                    message: fault.to_string(),
+                });
+            }
+        }
+        self.pc = segments[0].origin;
+        Ok(())
+    }
+
+    /// The name of `addr` in [`Cpu::symbols`], for the PRG bank mapped
+    /// there.
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        let bank = self.bus.as_nes().and_then(|bus| bus.prg_bank(addr));
+        self.symbols.get(addr, bank)
+    }
+
     // Global actions & entry points
     pub fn reset(&mut self) {
         self.a = 0;
@@ -92,45 +327,130 @@ impl Cpu {
         self.sp = STACK_RESET;
 
         self.pc = self.mem_read_u16(0xFFFC);
+        // the reset sequence takes 7 cycles
+        self.cycles = 7;
+        self.jammed = false;
+        self.call_stack.clear();
+        self.profiler.unwind();
+    }
+
+    // Cycles accounted per instruction. In cycle mode they are already
+    // counted by the bus accesses themselves.
+    fn tick(&mut self, cycles: u8) {
+        if self.exec_mode == ExecMode::Instruction {
+            self.cycles += cycles as u64;
+            self.bus.tick(cycles);
+        }
+    }
+
+    // Bus accesses made while executing. `mem_read`/`mem_write` stay
+    // available to poke at memory from outside without spending cycles.
+    fn access_cycle(&mut self) {
+        if self.exec_mode == ExecMode::Cycle {
+            self.cycles += 1;
+            self.bus.tick(1);
+        }
+    }
+
+    fn read(&mut self, addr: u16) -> u8 {
+        let data = self.bus.mem_read(addr);
+        if self.debugger.watching() && !self.is_fetch(addr) {
+            self.debugger.on_access(WatchKind::Read, addr, data);
+        }
+        self.access_cycle();
+        data
+    }
+
+    // Whether reading `addr` fetches a byte of the current instruction
+    // rather than data. The 6502 always reads the byte after the opcode,
+    // even for single byte instructions and interrupts.
+    fn is_fetch(&self, addr: u16) -> bool {
+        let (start, len) = self.instruction;
+        addr.wrapping_sub(start) < len.max(2) as u16
+    }
+
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let low = self.read(addr) as u16;
+        let high = self.read(addr.wrapping_add(1)) as u16;
+        (high << 8) | low
+    }
+
+    fn write(&mut self, addr: u16, data: u8) {
+        if self.history.recording() {
+            let old = self.bus.peek(addr);
+            self.history.on_write(addr, old, data);
+        }
+        self.bus.mem_write(addr, data);
+        if self.debugger.watching() {
+            self.debugger.on_access(WatchKind::Write, addr, data);
+        }
+        self.access_cycle();
+    }
+
+    // Accesses the 6502 makes and throws away, only done in cycle mode.
+    // https://www.nesdev.org/6502_cpu.txt
+    fn dummy_read(&mut self, addr: u16) {
+        if self.exec_mode == ExecMode::Cycle {
+            self.read(addr);
+        }
+    }
+
+    fn dummy_write(&mut self, addr: u16, data: u8) {
+        if self.exec_mode == ExecMode::Cycle {
+            self.write(addr, data);
+        }
     }
 
     // Addressing Modes
-    fn get_absolute_address(&self, mode: &AddressingMode, addr: u16) -> u16 {
+    fn page_cross(addr1: u16, addr2: u16) -> bool {
+        addr1 & 0xFF00 != addr2 & 0xFF00
+    }
+
+    // Returns the effective address and whether indexing crossed a page,
+    // which costs an extra cycle to some instructions.
+    fn get_absolute_address(&self, mode: &AddressingMode, addr: u16) -> (u16, bool) {
         match mode {
-            AddressingMode::ZeroPage => self.mem_read(addr) as u16,
-            AddressingMode::Absolute => self.mem_read_u16(addr),
+            AddressingMode::ZeroPage => (self.peek(addr) as u16, false),
+            AddressingMode::Absolute => (self.peek_u16(addr), false),
             AddressingMode::ZeroPageX => {
-                let pos = self.mem_read(addr);
-                pos.wrapping_add(self.x) as u16
+                let pos = self.peek(addr);
+                (pos.wrapping_add(self.x) as u16, false)
             }
             AddressingMode::ZeroPageY => {
-                let pos = self.mem_read(addr);
-                pos.wrapping_add(self.y) as u16
+                let pos = self.peek(addr);
+                (pos.wrapping_add(self.y) as u16, false)
             }
             AddressingMode::AbsoluteX => {
-                let base = self.mem_read_u16(addr);
-                base.wrapping_add(self.x as u16)
+                let base = self.peek_u16(addr);
+                let addr = base.wrapping_add(self.x as u16);
+                (addr, Self::page_cross(base, addr))
             }
             AddressingMode::AbsoluteY => {
-                let base = self.mem_read_u16(addr);
-                base.wrapping_add(self.y as u16)
+                let base = self.peek_u16(addr);
+                let addr = base.wrapping_add(self.y as u16);
+                (addr, Self::page_cross(base, addr))
             }
             AddressingMode::IndirectX => {
-                let base = self.mem_read(addr);
+                let base = self.peek(addr);
 
                 let ptr: u8 = (base as u8).wrapping_add(self.x);
-                let low = self.mem_read(ptr as u16);
-                let high = self.mem_read(ptr.wrapping_add(1) as u16);
-                (high as u16) << 8 | (low as u16)
+                let low = self.peek(ptr as u16);
+                let high = self.peek(ptr.wrapping_add(1) as u16);
+                ((high as u16) << 8 | (low as u16), false)
             }
             AddressingMode::IndirectY => {
-                let base = self.mem_read(addr);
+                let base = self.peek(addr);
 
-                let low = self.mem_read(base as u16);
-                let high = self.mem_read((base as u8).wrapping_add(1) as u16);
+                let low = self.peek(base as u16);
+                let high = self.peek((base as u8).wrapping_add(1) as u16);
                 let deref_base = (high as u16) << 8 | (low as u16);
+                let deref = deref_base.wrapping_add(self.y as u16);
 
-                deref_base.wrapping_add(self.y as u16)
+                (deref, Self::page_cross(deref_base, deref))
+            }
+            AddressingMode::ZeroPageIndirect => {
+                let base = self.peek(addr);
+                (self.peek_zero_page_u16(base), false)
             }
             AddressingMode::Immediate | AddressingMode::NoneAddressing => {
                 panic!("mode {:?} is not supported", mode);
@@ -138,13 +458,82 @@ impl Cpu {
         }
     }
 
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
+    fn peek_zero_page_u16(&self, ptr: u8) -> u16 {
+        let low = self.peek(ptr as u16);
+        let high = self.peek(ptr.wrapping_add(1) as u16);
+        (high as u16) << 8 | (low as u16)
+    }
+
+    // Same as `get_absolute_address` for the operand at PC, but as real bus
+    // accesses, with the dummy reads done while indexing.
+    fn fetch_operand_address(&mut self, mode: &AddressingMode, access: Access) -> (u16, bool) {
+        let pc = self.pc;
         match mode {
-            AddressingMode::Immediate => self.pc,
-            _ => self.get_absolute_address(mode, self.pc),
+            AddressingMode::Immediate => (pc, false),
+            AddressingMode::ZeroPage => (self.read(pc) as u16, false),
+            AddressingMode::Absolute => (self.read_u16(pc), false),
+            AddressingMode::ZeroPageX => {
+                let pos = self.read(pc);
+                self.dummy_read(pos as u16);
+                (pos.wrapping_add(self.x) as u16, false)
+            }
+            AddressingMode::ZeroPageY => {
+                let pos = self.read(pc);
+                self.dummy_read(pos as u16);
+                (pos.wrapping_add(self.y) as u16, false)
+            }
+            AddressingMode::AbsoluteX => {
+                let base = self.read_u16(pc);
+                let addr = base.wrapping_add(self.x as u16);
+                self.indexed_dummy_read(base, addr, access)
+            }
+            AddressingMode::AbsoluteY => {
+                let base = self.read_u16(pc);
+                let addr = base.wrapping_add(self.y as u16);
+                self.indexed_dummy_read(base, addr, access)
+            }
+            AddressingMode::IndirectX => {
+                let base = self.read(pc);
+                self.dummy_read(base as u16);
+
+                let ptr: u8 = base.wrapping_add(self.x);
+                let low = self.read(ptr as u16);
+                let high = self.read(ptr.wrapping_add(1) as u16);
+                ((high as u16) << 8 | (low as u16), false)
+            }
+            AddressingMode::IndirectY => {
+                let base = self.read(pc);
+
+                let low = self.read(base as u16);
+                let high = self.read(base.wrapping_add(1) as u16);
+                let deref_base = (high as u16) << 8 | (low as u16);
+                let deref = deref_base.wrapping_add(self.y as u16);
+                self.indexed_dummy_read(deref_base, deref, access)
+            }
+            AddressingMode::ZeroPageIndirect => {
+                let base = self.read(pc);
+
+                let low = self.read(base as u16);
+                let high = self.read(base.wrapping_add(1) as u16);
+                ((high as u16) << 8 | (low as u16), false)
+            }
+            AddressingMode::NoneAddressing => {
+                panic!("mode {:?} is not supported", mode);
+            }
         }
     }
 
+    // While adding the index, the CPU first reads from the address with only
+    // the low byte fixed up. Reads keep that value when no page is crossed,
+    // writes and read-modify-writes always spend the extra cycle.
+    fn indexed_dummy_read(&mut self, base: u16, addr: u16, access: Access) -> (u16, bool) {
+        let page_cross = Self::page_cross(base, addr);
+        if page_cross || access != Access::Read {
+            self.dummy_read((base & 0xFF00) | (addr & 0x00FF));
+        }
+        (addr, page_cross)
+    }
+
     // Instructions
     fn update_zero_and_negative(&mut self, result: u8) {
         self.ps.set(Zero, result == 0);
@@ -152,39 +541,48 @@ impl Cpu {
     }
 
     fn lda(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let value = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
         self.a = value;
         self.update_zero_and_negative(self.a);
     }
 
     fn ldx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let value = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
         self.x = value;
         self.update_zero_and_negative(self.x);
     }
 
     fn ldy(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let value = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
         self.y = value;
         self.update_zero_and_negative(self.y);
     }
 
     fn sta(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        self.mem_write(addr, self.a);
+        let (addr, _) = self.fetch_operand_address(mode, Access::Write);
+        self.write(addr, self.a);
     }
 
     fn stx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        self.mem_write(addr, self.x);
+        let (addr, _) = self.fetch_operand_address(mode, Access::Write);
+        self.write(addr, self.x);
     }
 
     fn sty(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        self.mem_write(addr, self.y);
+        let (addr, _) = self.fetch_operand_address(mode, Access::Write);
+        self.write(addr, self.y);
     }
 
     fn tax(&mut self) {
@@ -217,22 +615,31 @@ impl Cpu {
     }
 
     fn and(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let value = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
         self.a &= value;
         self.update_zero_and_negative(self.a);
     }
 
     fn eor(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let value = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
         self.a ^= value;
         self.update_zero_and_negative(self.a);
     }
 
     fn ora(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let value = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
         self.a |= value;
         self.update_zero_and_negative(self.a);
     }
@@ -246,14 +653,29 @@ impl Cpu {
         self.update_zero_and_negative(self.a);
     }
 
-    fn asl(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
+    // The 65C02 only spends the extra indexing cycle of a shift when it
+    // crosses a page, like it does for reads.
+    fn fetch_shift_address(&mut self, mode: &AddressingMode) -> u16 {
+        if self.variant != CpuVariant::Cmos65C02 {
+            return self.fetch_operand_address(mode, Access::ReadModifyWrite).0;
+        }
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        if page_cross {
+            self.tick(1);
+        }
+        addr
+    }
+
+    fn asl(&mut self, mode: &AddressingMode) -> u8 {
+        let addr = self.fetch_shift_address(mode);
+        let mut data = self.read(addr);
+        self.dummy_write(addr, data);
         let bit7 = (data >> 7) & 0x1;
         self.ps.set(Carry, bit7 != 0);
         data <<= 1;
-        self.mem_write(addr, data);
+        self.write(addr, data);
         self.update_zero_and_negative(data);
+        data
     }
 
     fn lsr_a(&mut self) {
@@ -265,14 +687,16 @@ impl Cpu {
         self.update_zero_and_negative(self.a);
     }
 
-    fn lsr(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
+    fn lsr(&mut self, mode: &AddressingMode) -> u8 {
+        let addr = self.fetch_shift_address(mode);
+        let mut data = self.read(addr);
+        self.dummy_write(addr, data);
         let bit0 = data & 0x1;
         self.ps.set(Carry, bit0 != 0);
         data >>= 1;
-        self.mem_write(addr, data);
+        self.write(addr, data);
         self.update_zero_and_negative(data);
+        data
     }
 
     fn rol_a(&mut self) {
@@ -285,15 +709,17 @@ impl Cpu {
         self.update_zero_and_negative(self.a);
     }
 
-    fn rol(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
+    fn rol(&mut self, mode: &AddressingMode) -> u8 {
+        let addr = self.fetch_shift_address(mode);
+        let mut data = self.read(addr);
+        self.dummy_write(addr, data);
         let bit7 = (data >> 7) & 0x1;
         let carry = if self.ps.carry { 1 } else { 0 };
         self.ps.set(Carry, bit7 != 0);
         data = (data << 1) | (carry as u8);
-        self.mem_write(addr, data);
+        self.write(addr, data);
         self.update_zero_and_negative(data);
+        data
     }
 
     fn ror_a(&mut self) {
@@ -307,26 +733,35 @@ impl Cpu {
         self.update_zero_and_negative(self.a);
     }
 
-    fn ror(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
+    fn ror(&mut self, mode: &AddressingMode) -> u8 {
+        let addr = self.fetch_shift_address(mode);
+        let mut data = self.read(addr);
+        self.dummy_write(addr, data);
         let bit0 = data & 0x1;
         let carry = if self.ps.carry { 1 } else { 0 };
         let carry = (carry as u8) << 7;
         self.ps.set(Carry, bit0 != 0);
         data = (data >> 1) | carry;
-        self.mem_write(addr, data);
+        self.write(addr, data);
         self.update_zero_and_negative(data);
+        data
     }
 
     fn bit(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let data = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
         let bit6 = (data >> 6) & 0x1;
         let bit7 = (data >> 7) & 0x1;
 
         let res = self.a & data;
         self.ps.set(Zero, res == 0);
+        // BIT #imm (65C02) only affects Z
+        if *mode == AddressingMode::Immediate {
+            return;
+        }
         self.ps.set(Overflow, bit6 != 0);
         self.ps.set(Negative, bit7 != 0);
     }
@@ -337,10 +772,22 @@ impl Cpu {
 
     fn nop(&self) {}
 
+    // The 2A03 has the decimal flag but no BCD logic behind it
+    fn decimal_mode(&self) -> bool {
+        self.ps.decimal && self.variant != CpuVariant::Ricoh2A03
+    }
+
+    fn add_to_a(&mut self, data: u8) {
+        if self.decimal_mode() {
+            self.add_to_a_decimal(data);
+        } else {
+            self.add_to_a_binary(data);
+        }
+    }
+
     // Add to A with Carry
-    // Ignoring decimal mode
     /// http://www.righto.com/2012/12/the-6502-overflow-flag-explained.html
-    fn add_to_a(&mut self, data: u8) {
+    fn add_to_a_binary(&mut self, data: u8) {
         let sum = self.a as u16 + data as u16 + (if self.ps.carry { 1 } else { 0 }) as u16;
 
         let carry = sum > 0xff;
@@ -355,27 +802,115 @@ impl Cpu {
         self.update_zero_and_negative(self.a);
     }
 
-    fn sbc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
+    fn sub_from_a(&mut self, data: u8) {
+        if self.decimal_mode() {
+            self.sub_from_a_decimal(data);
+        } else {
+            self.sub_from_a_binary(data);
+        }
+    }
+
+    fn sub_from_a_binary(&mut self, data: u8) {
         // A - B = A + (-B) and -B = !B + 1
-        self.add_to_a(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
+        self.add_to_a_binary(((data as i8).wrapping_neg().wrapping_sub(1)) as u8);
+    }
+
+    // BCD arithmetic. On NMOS parts N, V and Z are left over from the
+    // intermediate binary results, the 65C02 sets N and Z from the result.
+    // http://www.6502.org/tutorials/decimal_mode.html#A
+    fn add_to_a_decimal(&mut self, data: u8) {
+        let (a, b) = (self.a as u16, data as u16);
+        let carry = self.ps.carry as u16;
+
+        let mut low = (a & 0x0F) + (b & 0x0F) + carry;
+        if low >= 0x0A {
+            low = ((low + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (a & 0xF0) + (b & 0xF0) + low;
+        let signed = (a & 0xF0) as u8 as i8 as i16 + (b & 0xF0) as u8 as i8 as i16 + low as i16;
+        self.ps.set(Overflow, !(-128..=127).contains(&signed));
+        self.ps.set(Negative, sum & 0x80 != 0);
+        self.ps.set(Zero, (a + b + carry) as u8 == 0);
+
+        if sum >= 0xA0 {
+            sum += 0x60;
+        }
+        self.ps.set(Carry, sum >= 0x100);
+        self.a = sum as u8;
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.update_zero_and_negative(self.a);
+        }
+    }
+
+    // http://www.6502.org/tutorials/decimal_mode.html#B
+    fn sub_from_a_decimal(&mut self, data: u8) {
+        let (a, b) = (self.a as i16, data as i16);
+        let borrow = !self.ps.carry as i16;
+        // C and V (and on NMOS N and Z) are the binary ones
+        self.sub_from_a_binary(data);
+
+        let mut low = (a & 0x0F) - (b & 0x0F) - borrow;
+        let result = if self.variant == CpuVariant::Cmos65C02 {
+            let mut result = a - b - borrow;
+            if result < 0 {
+                result -= 0x60;
+            }
+            if low < 0 {
+                result -= 0x06;
+            }
+            result
+        } else {
+            if low < 0 {
+                low = ((low - 0x06) & 0x0F) - 0x10;
+            }
+            let mut result = (a & 0xF0) - (b & 0xF0) + low;
+            if result < 0 {
+                result -= 0x60;
+            }
+            result
+        };
+        self.a = result as u8;
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.update_zero_and_negative(self.a);
+        }
+    }
+
+    // The 65C02 takes a cycle more to fix up the flags in decimal mode
+    fn decimal_penalty(&mut self, addr: u16) {
+        if self.variant == CpuVariant::Cmos65C02 && self.ps.decimal {
+            self.tick(1);
+            self.dummy_read(addr);
+        }
+    }
+
+    fn sbc(&mut self, mode: &AddressingMode) {
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let data = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
+        self.sub_from_a(data);
+        self.decimal_penalty(addr);
     }
 
     fn adc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let value = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
         self.add_to_a(value);
+        self.decimal_penalty(addr);
     }
 
     // Stack ops
     fn stack_pop(&mut self) -> u8 {
         self.sp = self.sp.wrapping_add(1);
-        self.mem_read((STACK as u16) + self.sp as u16)
+        self.read((STACK as u16) + self.sp as u16)
     }
 
     fn stack_push(&mut self, data: u8) {
-        self.mem_write((STACK as u16) + self.sp as u16, data);
+        self.write((STACK as u16) + self.sp as u16, data);
         self.sp = self.sp.wrapping_sub(1)
     }
 
@@ -406,11 +941,13 @@ impl Cpu {
     }
 
     fn pla(&mut self) {
+        self.dummy_read(STACK + self.sp as u16);
         self.a = self.stack_pop();
         self.update_zero_and_negative(self.a);
     }
 
     fn plp(&mut self) {
+        self.dummy_read(STACK + self.sp as u16);
         let data = self.stack_pop();
         self.ps = data.into();
         self.ps.break0 = false;
@@ -419,41 +956,63 @@ impl Cpu {
 
     // Jump/Branching
     fn branch(&mut self, condition: bool) {
+        let jump: i8 = self.read(self.pc) as i8;
         if condition {
-            let jump: i8 = self.mem_read(self.pc) as i8;
-            let jump_addr = self.pc.wrapping_add(1).wrapping_add(jump as u16);
+            // +1 cycle if the branch is taken, +1 more if it lands on another page
+            self.tick(1);
+            let next = self.pc.wrapping_add(1);
+            self.dummy_read(next);
+            let jump_addr = next.wrapping_add(jump as u16);
+            if Self::page_cross(next, jump_addr) {
+                self.tick(1);
+                self.dummy_read((next & 0xFF00) | (jump_addr & 0x00FF));
+            }
             self.pc = jump_addr;
         }
     }
 
     fn jmp_abs(&mut self) {
-        let addr = self.mem_read_u16(self.pc);
+        let addr = self.read_u16(self.pc);
         self.pc = addr;
     }
 
     fn jmp_indirect(&mut self) {
-        let addr = self.mem_read_u16(self.pc);
+        let addr = self.read_u16(self.pc);
         // https://www.nesdev.org/obelisk-6502-guide/reference.html#JMP
-        // let indirect_ref = self.mem_read_u16(addr);
-
-        let indirect_ref = if addr & 0x00FF == 0x00FF {
-            let low = self.mem_read(addr);
-            let high = self.mem_read(addr & 0xFF00);
+        // let indirect_ref = self.read_u16(addr);
+
+        let indirect_ref = if self.variant == CpuVariant::Cmos65C02 {
+            // fixed on the 65C02, at the cost of a cycle
+            self.dummy_read(self.pc.wrapping_add(1));
+            self.read_u16(addr)
+        } else if addr & 0x00FF == 0x00FF {
+            let low = self.read(addr);
+            let high = self.read(addr & 0xFF00);
             (high as u16) << 8 | (low as u16)
         } else {
-            self.mem_read_u16(addr)
+            self.read_u16(addr)
         };
 
         self.pc = indirect_ref;
     }
 
+    fn jmp_indexed_indirect(&mut self) {
+        let base = self.read_u16(self.pc);
+        self.dummy_read(self.pc.wrapping_add(1));
+        self.pc = self.read_u16(base.wrapping_add(self.x as u16));
+    }
+
     fn jsr(&mut self) {
-        self.stack_push_u16(self.pc + 2 - 1); // push return addr - 1
-        let addr = self.mem_read_u16(self.pc);
-        self.pc = addr;
+        // the high byte of the target is only fetched after the push
+        let low = self.read(self.pc) as u16;
+        self.dummy_read(STACK + self.sp as u16);
+        self.stack_push_u16(self.pc.wrapping_add(1)); // push return addr - 1
+        let high = self.read(self.pc.wrapping_add(1)) as u16;
+        self.pc = high << 8 | low;
     }
 
     fn rti(&mut self) {
+        self.dummy_read(STACK + self.sp as u16);
         self.ps = self.stack_pop().into();
         self.ps.set(Break0, false);
         self.ps.set(Break1, true);
@@ -462,10 +1021,70 @@ impl Cpu {
     }
 
     fn rts(&mut self) {
-        self.pc = self.stack_pop_u16() + 1;
+        self.dummy_read(STACK + self.sp as u16);
+        let addr = self.stack_pop_u16();
+        self.dummy_read(addr);
+        self.pc = addr.wrapping_add(1);
+    }
+
+    // 65C02 instructions
+    // http://6502.org/tutorials/65c02opcodes.html
+
+    fn pull(&mut self) -> u8 {
+        self.dummy_read(STACK + self.sp as u16);
+        let data = self.stack_pop();
+        self.update_zero_and_negative(data);
+        data
+    }
+
+    fn stz(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.fetch_operand_address(mode, Access::Write);
+        self.write(addr, 0);
+    }
+
+    // TSB/TRB set Z like BIT does, then set or clear the bits of A in memory
+    fn test_and_modify_bits(&mut self, mode: &AddressingMode, set: bool) {
+        let (addr, _) = self.fetch_operand_address(mode, Access::ReadModifyWrite);
+        let data = self.read(addr);
+        self.dummy_write(addr, data);
+        self.ps.set(Zero, self.a & data == 0);
+        let result = if set { data | self.a } else { data & !self.a };
+        self.write(addr, result);
+    }
+
+    // Pushes PC and P, then jumps through the interrupt vector.
+    // The B flag is only set in the pushed status for BRK:
+    // https://www.nesdev.org/wiki/Status_flags#The_B_flag
+    fn interrupt(&mut self, interrupt: Interrupt) {
+        self.stack_push_u16(self.pc);
+        let mut ps = self.ps.clone();
+        ps.break0 = interrupt == Interrupt::Brk;
+        ps.break1 = true;
+        let data: u8 = (&ps).into();
+        self.stack_push(data);
+        self.ps.set(StatusFlag::Interrupt, true);
+        if self.variant == CpuVariant::Cmos65C02 {
+            self.ps.set(Decimal, false);
+        }
+        self.pc = self.read_u16(interrupt.vector());
+    }
+
+    // Hardware interrupts are checked between instructions. NMI wins over
+    // IRQ, and IRQ is ignored while the I flag is set.
+    fn interrupt_pending(&self) -> bool {
+        self.bus.nmi_pending() || (self.bus.irq() && !self.ps.interrupt)
+    }
+
+    fn poll_interrupts(&mut self) -> Option<Interrupt> {
+        if self.bus.poll_nmi() {
+            Some(Interrupt::Nmi)
+        } else if self.bus.irq() && !self.ps.interrupt {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
     }
 
-    #[allow(dead_code)]
     fn brk(&mut self) {
         // #  address R/W description
         // --- ------- --- -----------------------------------------------
@@ -480,28 +1099,32 @@ impl Cpu {
         //  7   $FFFF   R  fetch PCH
         //
         // 1 is done by the `run` method.
-        // 2 we ignore
-        self.stack_push_u16(self.pc);
-        let mut ps = self.ps.clone();
-        ps.break0 = true;
-        let data: u8 = (&ps).into();
-        self.stack_push(data);
-        self.pc = self.mem_read_u16(BRK_IRQ_BASE)
+        self.pc = self.pc.wrapping_add(1);
+        self.interrupt(Interrupt::Brk);
     }
 
-    fn cmp(&mut self, mode: &AddressingMode, reference: u8) {
-        let addr = self.get_operand_address(mode);
-        let data = self.mem_read(addr);
+    fn compare(&mut self, reference: u8, data: u8) {
         self.ps.set(Carry, reference >= data);
         self.update_zero_and_negative(reference.wrapping_sub(data))
     }
 
-    fn dec(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
+    fn cmp(&mut self, mode: &AddressingMode, reference: u8) {
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let data = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
+        self.compare(reference, data);
+    }
+
+    fn dec(&mut self, mode: &AddressingMode) -> u8 {
+        let (addr, _) = self.fetch_operand_address(mode, Access::ReadModifyWrite);
+        let mut data = self.read(addr);
+        self.dummy_write(addr, data);
         data = data.wrapping_sub(1);
         self.update_zero_and_negative(data);
-        self.mem_write(addr, data);
+        self.write(addr, data);
+        data
     }
 
     fn dex(&mut self) {
@@ -514,12 +1137,14 @@ impl Cpu {
         self.update_zero_and_negative(self.y);
     }
 
-    fn inc(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let mut data = self.mem_read(addr);
+    fn inc(&mut self, mode: &AddressingMode) -> u8 {
+        let (addr, _) = self.fetch_operand_address(mode, Access::ReadModifyWrite);
+        let mut data = self.read(addr);
+        self.dummy_write(addr, data);
         data = data.wrapping_add(1);
         self.update_zero_and_negative(data);
-        self.mem_write(addr, data);
+        self.write(addr, data);
+        data
     }
 
     fn inx(&mut self) {
@@ -532,165 +1157,1365 @@ impl Cpu {
         self.update_zero_and_negative(self.y);
     }
 
+    // Unofficial instructions
+    // https://www.nesdev.org/wiki/Programming_with_unofficial_opcodes
+    // https://www.nesdev.org/undocumented_opcodes.txt
+    fn lax(&mut self, mode: &AddressingMode) {
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let value = self.read(addr);
+        if page_cross {
+            self.tick(1);
+        }
+        self.a = value;
+        self.x = value;
+        self.update_zero_and_negative(value);
+    }
+
+    fn sax(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.fetch_operand_address(mode, Access::Write);
+        self.write(addr, self.a & self.x);
+    }
+
+    fn dcp(&mut self, mode: &AddressingMode) {
+        let data = self.dec(mode);
+        self.compare(self.a, data);
+    }
+
+    fn isb(&mut self, mode: &AddressingMode) {
+        let data = self.inc(mode);
+        self.sub_from_a(data);
+    }
+
+    fn slo(&mut self, mode: &AddressingMode) {
+        let data = self.asl(mode);
+        self.a |= data;
+        self.update_zero_and_negative(self.a);
+    }
+
+    fn rla(&mut self, mode: &AddressingMode) {
+        let data = self.rol(mode);
+        self.a &= data;
+        self.update_zero_and_negative(self.a);
+    }
+
+    fn sre(&mut self, mode: &AddressingMode) {
+        let data = self.lsr(mode);
+        self.a ^= data;
+        self.update_zero_and_negative(self.a);
+    }
+
+    fn rra(&mut self, mode: &AddressingMode) {
+        let data = self.ror(mode);
+        self.add_to_a(data);
+    }
+
+    fn anc(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.ps.set(Carry, self.ps.negative);
+    }
+
+    fn alr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.lsr_a();
+    }
+
+    fn arr(&mut self, mode: &AddressingMode) {
+        self.and(mode);
+        self.ror_a();
+        let bit5 = (self.a >> 5) & 0x1;
+        let bit6 = (self.a >> 6) & 0x1;
+        self.ps.set(Carry, bit6 != 0);
+        self.ps.set(Overflow, (bit5 ^ bit6) != 0);
+    }
+
+    fn axs(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.fetch_operand_address(mode, Access::Read);
+        let data = self.read(addr);
+        let and = self.a & self.x;
+        self.ps.set(Carry, and >= data);
+        self.x = and.wrapping_sub(data);
+        self.update_zero_and_negative(self.x);
+    }
+
+    // XAA and LXA are "highly unstable": the result depends on analog
+    // effects. We use the magic constants matching the 2A03 behaviour
+    // observed by most test ROMs.
+    fn xaa(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.fetch_operand_address(mode, Access::Read);
+        let data = self.read(addr);
+        self.a = (self.a | 0xEE) & self.x & data;
+        self.update_zero_and_negative(self.a);
+    }
+
+    fn lxa(&mut self, mode: &AddressingMode) {
+        let (addr, _) = self.fetch_operand_address(mode, Access::Read);
+        let data = self.read(addr);
+        self.a = (self.a | 0xFF) & data;
+        self.x = self.a;
+        self.update_zero_and_negative(self.a);
+    }
+
+    fn las(&mut self, mode: &AddressingMode) {
+        let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+        let data = self.read(addr) & self.sp;
+        if page_cross {
+            self.tick(1);
+        }
+        self.a = data;
+        self.x = data;
+        self.sp = data;
+        self.update_zero_and_negative(data);
+    }
+
+    fn tas(&mut self, mode: &AddressingMode) {
+        self.sp = self.a & self.x;
+        self.unstable_store(mode, self.sp);
+    }
+
+    // Shared by SHA/SHX/SHY/TAS: the stored value is ANDed with the high
+    // byte of the base address + 1, and when the indexing crosses a page,
+    // that value also replaces the high byte of the target address.
+    fn unstable_store(&mut self, mode: &AddressingMode, data: u8) {
+        let (addr, _) = self.fetch_operand_address(mode, Access::Write);
+        let index = match mode {
+            AddressingMode::AbsoluteX => self.x,
+            _ => self.y,
+        };
+        let base = addr.wrapping_sub(index as u16);
+        let data = data & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if (base & 0xFF00) != (addr & 0xFF00) {
+            (data as u16) << 8 | (addr & 0x00FF)
+        } else {
+            addr
+        };
+        self.write(addr, data);
+    }
+
+    /// Executes a single instruction, or services a pending interrupt.
+    pub fn step(&mut self) -> Result<StepResult, EmuError> {
+        if self.jammed {
+            return Ok(StepResult {
+                opcode: self.peek(self.pc),
+                cycles: 0,
+                event: Some(StepEvent::Halted),
+            });
+        }
+
+        self.debugger.start_step();
+        if self.history.recording() {
+            self.start_record();
+        }
+        let start = self.cycles;
+        let start_pc = self.pc;
+        self.instruction = (self.pc, 0);
+        if let Some(interrupt) = self.poll_interrupts() {
+            if self.history.recording() {
+                self.history.on_interrupt(interrupt);
+            }
+            self.dummy_read(self.pc);
+            self.dummy_read(self.pc);
+            self.interrupt(interrupt);
+            self.tick(7);
+            return self.finish_step(
+                start_pc,
+                StepResult {
+                    opcode: 0x00,
+                    cycles: (self.cycles - start) as u8,
+                    event: Some(StepEvent::Interrupt(interrupt)),
+                },
+            );
+        }
+
+        let len = opcodes::decode(self.variant, self.peek(self.pc)).map_or(1, |o| o.len);
+        self.bus.fetch_instruction(self.pc, len);
+        self.instruction = (self.pc, len);
+        let code = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+
+        let pc = self.pc;
+
+        let opcode = opcodes::decode(self.variant, code).ok_or(EmuError::IllegalOpcode {
+            code,
+            pc: pc.wrapping_sub(1),
+        })?;
+        let mode = &opcode.mode;
+        // single byte instructions still read the next one, except for the
+        // one cycle NOPs of the 65C02
+        if opcode.len == 1 && opcode.cycles > 1 {
+            self.dummy_read(self.pc);
+        }
+
+        let mut event = None;
+        match opcode.op {
+            Op::Brk => {
+                self.brk();
+                event = Some(StepEvent::Interrupt(Interrupt::Brk));
+            }
+            Op::Lda => self.lda(mode),
+            Op::Ldx => self.ldx(mode),
+            Op::Ldy => self.ldy(mode),
+            Op::Sta => self.sta(mode),
+            Op::Stx => self.stx(mode),
+            Op::Sty => self.sty(mode),
+            Op::And => self.and(mode),
+            Op::Eor => self.eor(mode),
+            Op::Ora => self.ora(mode),
+            Op::AslA => self.asl_a(),
+            Op::Asl => {
+                self.asl(mode);
+            }
+            Op::RolA => self.rol_a(),
+            Op::Rol => {
+                self.rol(mode);
+            }
+            Op::LsrA => self.lsr_a(),
+            Op::Lsr => {
+                self.lsr(mode);
+            }
+            Op::RorA => self.ror_a(),
+            Op::Ror => {
+                self.ror(mode);
+            }
+            Op::Bit => self.bit(mode),
+            Op::Clc => self.set_flag(Carry, false),
+            Op::Cld => self.set_flag(Decimal, false),
+            Op::Cli => self.set_flag(Interrupt, false),
+            Op::Clv => self.set_flag(Overflow, false),
+            Op::Sec => self.set_flag(Carry, true),
+            Op::Sed => self.set_flag(Decimal, true),
+            Op::Sei => self.set_flag(Interrupt, true),
+            Op::Tax => self.tax(),
+            Op::Tay => self.tay(),
+            Op::Tsx => self.tsx(),
+            Op::Txa => self.txa(),
+            Op::Txs => self.txs(),
+            Op::Tya => self.tya(),
+            // Unofficial NOP / DOP / TOP also perform the operand read
+            Op::Nop => match mode {
+                AddressingMode::NoneAddressing => self.nop(),
+                _ => {
+                    let (addr, page_cross) = self.fetch_operand_address(mode, Access::Read);
+                    self.read(addr);
+                    if page_cross {
+                        self.tick(1);
+                    }
+                    // some 65C02 NOPs keep the bus busy for longer
+                    while self.exec_mode == ExecMode::Cycle
+                        && self.cycles - start < opcode.cycles as u64
+                    {
+                        self.dummy_read(addr);
+                    }
+                }
+            },
+            Op::Adc => self.adc(mode),
+            Op::Sbc => self.sbc(mode),
+            Op::Pha => self.pha(),
+            Op::Php => self.php(),
+            Op::Pla => self.pla(),
+            Op::Plp => self.plp(),
+            Op::Bcc => self.branch(!self.ps.carry),
+            Op::Bcs => self.branch(self.ps.carry),
+            Op::Beq => self.branch(self.ps.zero),
+            Op::Bmi => self.branch(self.ps.negative),
+            Op::Bne => self.branch(!self.ps.zero),
+            Op::Bpl => self.branch(!self.ps.negative),
+            Op::Bvc => self.branch(!self.ps.overflow),
+            Op::Bvs => self.branch(self.ps.overflow),
+            Op::Cmp => self.cmp(mode, self.a),
+            Op::Cpx => self.cmp(mode, self.x),
+            Op::Cpy => self.cmp(mode, self.y),
+            Op::Dec => {
+                self.dec(mode);
+            }
+            Op::Dex => self.dex(),
+            Op::Dey => self.dey(),
+            Op::Inc => {
+                self.inc(mode);
+            }
+            Op::Inx => self.inx(),
+            Op::Iny => self.iny(),
+            Op::Jmp => self.jmp_abs(),
+            Op::JmpIndirect => self.jmp_indirect(),
+            Op::Jsr => self.jsr(),
+            Op::Rti => self.rti(),
+            Op::Rts => self.rts(),
+
+            // Unofficial opcodes
+            Op::Lax => self.lax(mode),
+            Op::Sax => self.sax(mode),
+            Op::Dcp => self.dcp(mode),
+            Op::Isb => self.isb(mode),
+            Op::Slo => self.slo(mode),
+            Op::Rla => self.rla(mode),
+            Op::Sre => self.sre(mode),
+            Op::Rra => self.rra(mode),
+            Op::Anc => self.anc(mode),
+            Op::Alr => self.alr(mode),
+            Op::Arr => self.arr(mode),
+            Op::Axs => self.axs(mode),
+            Op::Xaa => self.xaa(mode),
+            Op::Lxa => self.lxa(mode),
+            Op::Las => self.las(mode),
+            Op::Tas => self.tas(mode),
+            Op::Sha => self.unstable_store(mode, self.a & self.x),
+            Op::Shx => self.unstable_store(mode, self.x),
+            Op::Shy => self.unstable_store(mode, self.y),
+            // 65C02
+            Op::Bra => self.branch(true),
+            Op::Phx => self.stack_push(self.x),
+            Op::Phy => self.stack_push(self.y),
+            Op::Plx => self.x = self.pull(),
+            Op::Ply => self.y = self.pull(),
+            Op::Stz => self.stz(mode),
+            Op::Trb => self.test_and_modify_bits(mode, false),
+            Op::Tsb => self.test_and_modify_bits(mode, true),
+            Op::IncA => {
+                self.a = self.a.wrapping_add(1);
+                self.update_zero_and_negative(self.a);
+            }
+            Op::DecA => {
+                self.a = self.a.wrapping_sub(1);
+                self.update_zero_and_negative(self.a);
+            }
+            Op::JmpIndexedIndirect => self.jmp_indexed_indirect(),
+            // JAM: the processor locks up until reset, keep PC on the opcode
+            Op::Jam => {
+                self.pc = pc.wrapping_sub(1);
+                self.jammed = true;
+                event = Some(StepEvent::Halted);
+            }
+        }
+
+        self.tick(opcode.cycles);
+
+        if pc == self.pc {
+            self.pc = self.pc.wrapping_add((opcode.len - 1) as u16);
+        }
+
+        self.finish_step(
+            start_pc,
+            StepResult {
+                opcode: code,
+                cycles: (self.cycles - start) as u8,
+                event,
+            },
+        )
+    }
+
+    fn start_record(&mut self) {
+        let bytes = [0, 1, 2].map(|i| self.peek(self.pc.wrapping_add(i)));
+        let len = opcodes::decode(self.variant, bytes[0]).map_or(1, |opcode| opcode.len);
+        let record = self.history.start();
+        record.pc = self.pc;
+        record.bytes = bytes;
+        record.len = len;
+        record.a = self.a;
+        record.x = self.x;
+        record.y = self.y;
+        record.p = (&self.ps).into();
+        record.sp = self.sp;
+        record.cycles = self.cycles;
+    }
+
+    /// Undoes the last instruction recorded in `history`: restores the
+    /// registers, cycle count, the RAM and PRG RAM it wrote and the call
+    /// stack. Devices behind the bus cannot be rewound, so an instruction
+    /// that wrote to an I/O register is not undone. The profiler keeps the
+    /// cycles it counted.
+    pub fn step_back(&mut self) -> Result<history::Record, StepBackError> {
+        let record = self.history.records().next_back();
+        let record = record.ok_or(StepBackError::NoHistory)?;
+        if let Some(write) = record.writes.iter().find(|write| write.writes_register()) {
+            return Err(StepBackError::RegisterWrite {
+                pc: record.pc,
+                addr: write.addr,
+            });
+        }
+        let record = self.history.pop().ok_or(StepBackError::NoHistory)?;
+        for write in record
+            .writes
+            .iter()
+            .rev()
+            .filter(|write| write.writes_memory())
+        {
+            self.bus.mem_write(write.addr, write.old);
+        }
+        self.call_stack.undo(record.called, &record.returned);
+        if self.profiler.is_enabled() {
+            self.profiler.step_back(record.called, &record.returned);
+        }
+        self.pc = record.pc;
+        self.a = record.a;
+        self.x = record.x;
+        self.y = record.y;
+        self.ps = record.p.into();
+        self.sp = record.sp;
+        self.cycles = record.cycles;
+        self.jammed = false;
+        Ok(record)
+    }
+
+    // `pc` is where the step started.
+    fn finish_step(&mut self, pc: u16, result: StepResult) -> Result<StepResult, EmuError> {
+        let call = self.track_calls(pc, &result);
+        if let Some(event) = call.filter(|_| self.history.recording()) {
+            self.history.on_call_event(event, self.call_stack.popped());
+        }
+        if self.profiler.is_enabled() {
+            self.profiler.on_step(&result, call);
+        }
+        match self.bus.take_fault() {
+            Some(fault) => Err(fault),
+            None => Ok(result),
+        }
+    }
+
+    fn track_calls(&mut self, pc: u16, result: &StepResult) -> Option<CallEvent> {
+        let (kind, return_addr, return_sp) = match result.event {
+            Some(StepEvent::Halted) => return None,
+            // BRK skips the byte after it
+            Some(StepEvent::Interrupt(Interrupt::Brk)) => (
+                FrameKind::Interrupt(Interrupt::Brk),
+                pc.wrapping_add(2),
+                self.sp.wrapping_add(3),
+            ),
+            Some(StepEvent::Interrupt(interrupt)) => {
+                (FrameKind::Interrupt(interrupt), pc, self.sp.wrapping_add(3))
+            }
+            None => match opcodes::decode(self.variant, result.opcode)?.op {
+                Op::Jsr => (FrameKind::Jsr, pc.wrapping_add(3), self.sp.wrapping_add(2)),
+                Op::Rts => return self.call_stack.ret(false, pc, self.pc, self.sp),
+                Op::Rti => return self.call_stack.ret(true, pc, self.pc, self.sp),
+                _ => return None,
+            },
+        };
+        Some(self.call_stack.call(Frame {
+            kind,
+            call_site: pc,
+            target: self.pc,
+            return_addr,
+            return_sp,
+        }))
+    }
+
+    /// Runs until at least `cycles` more cycles have elapsed. The last
+    /// instruction is always completed, so it can overshoot a little.
+    pub fn run_for_cycles(&mut self, cycles: u64) -> Result<StopReason, EmuError> {
+        let target = self.cycles + cycles;
+        while self.cycles < target {
+            if let Some(reason) = self.run_step()? {
+                return Ok(reason);
+            }
+        }
+        Ok(StopReason::CyclesElapsed)
+    }
+
+    /// Runs until PC reaches `addr`, checked after each step.
+    pub fn run_until_pc(&mut self, addr: u16) -> Result<StopReason, EmuError> {
+        match self.run_until(|cpu| cpu.pc == addr)? {
+            StopReason::Predicate => Ok(StopReason::PcReached),
+            reason => Ok(reason),
+        }
+    }
+
+    /// Runs until `predicate` holds, checked after each step.
+    pub fn run_until<P>(&mut self, mut predicate: P) -> Result<StopReason, EmuError>
+    where
+        P: FnMut(&Cpu<B>) -> bool,
+    {
+        loop {
+            if let Some(reason) = self.run_step()? {
+                return Ok(reason);
+            }
+            if predicate(self) {
+                return Ok(StopReason::Predicate);
+            }
+        }
+    }
+
+    /// Runs until the debugger breaks or the CPU halts.
+    pub fn run_until_break(&mut self) -> Result<StopReason, EmuError> {
+        self.run_until(|_| false)
+    }
+
+    // One step of the `run_*` methods, with the reason to stop after it.
+    fn run_step(&mut self) -> Result<Option<StopReason>, EmuError> {
+        let result = self.step()?;
+        if result.event == Some(StepEvent::Halted) {
+            return Ok(Some(StopReason::Halted));
+        }
+        if self.debugger.is_empty() {
+            return Ok(None);
+        }
+
+        let interrupt = match result.event {
+            Some(StepEvent::Interrupt(interrupt)) => Some(interrupt),
+            _ => None,
+        };
+        let next_opcode = self.peek(self.pc);
+        Ok(self
+            .debugger
+            .check(self.pc, next_opcode, interrupt)
+            .map(StopReason::Break))
+    }
+
     #[allow(dead_code)]
-    pub fn run(&mut self) {
-        self.run_with_callback(|_| {});
+    pub fn run(&mut self) -> Result<(), EmuError> {
+        self.run_with_callback(|_| {})
     }
 
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    /// Runs until the CPU halts, calling `callback` before each instruction.
+    #[allow(dead_code)]
+    pub fn run_with_callback<F>(&mut self, mut callback: F) -> Result<(), EmuError>
     where
-        F: FnMut(&mut Cpu),
+        F: FnMut(&mut Cpu<B>),
     {
-        let opcodes: &HashMap<u8, &'static opcodes::OpCode> = &(*opcodes::OPCODES_MAP);
         loop {
-            callback(self);
-            // eprintln!("PC = {:#04x}", self.pc);
-            let code = self.mem_read(self.pc);
-            self.pc += 1;
-
-            let pc = self.pc;
-
-            let opcode = opcodes
-                .get(&code)
-                .unwrap_or_else(|| panic!("OpCode {:x} is not supported", code));
-
-            // eprintln!("Decoding {:#?}", opcode);
-            match code {
-                // BRK : TODO
-                0x00 => return,
-                // LDA
-                0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
-                    self.lda(&opcode.mode);
-                }
-                // LDX
-                0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => self.ldx(&opcode.mode),
-                // LDY
-                0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => self.ldy(&opcode.mode),
-                // STA
-                0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
-                    self.sta(&opcode.mode);
-                }
-                // STX
-                0x86 | 0x96 | 0x8E => self.stx(&opcode.mode),
-                // STY
-                0x84 | 0x94 | 0x8C => self.sty(&opcode.mode),
-                // AND
-                0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
-                    self.and(&opcode.mode);
-                }
-                // EOR
-                0x49 | 0x45 | 0x55 | 0x4D | 0x5D | 0x59 | 0x41 | 0x51 => {
-                    self.eor(&opcode.mode);
-                }
-                // ORA
-                0x09 | 0x05 | 0x15 | 0x0D | 0x1D | 0x19 | 0x01 | 0x11 => {
-                    self.ora(&opcode.mode);
-                }
-                // ASL
-                0x0A => self.asl_a(),
-                0x06 | 0x16 | 0x0E | 0x1E => self.asl(&opcode.mode),
-                // ROL
-                0x2A => self.rol_a(),
-                0x26 | 0x36 | 0x2E | 0x3E => self.rol(&opcode.mode),
-                // LSR
-                0x4A => self.lsr_a(),
-                0x46 | 0x56 | 0x4E | 0x5E => self.lsr(&opcode.mode),
-                // ROR
-                0x6A => self.ror_a(),
-                0x66 | 0x76 | 0x6E | 0x7E => self.ror(&opcode.mode),
-                // BIT
-                0x24 | 0x2C => self.bit(&opcode.mode),
-                // CLC
-                0x18 => self.set_flag(Carry, false),
-                // CLD
-                0xD8 => self.set_flag(Decimal, false),
-                // CLI
-                0x58 => self.set_flag(Interrupt, false),
-                // CLV
-                0xB8 => self.set_flag(Overflow, false),
-                // SEC
-                0x38 => self.set_flag(Carry, true),
-                // SED
-                0xF8 => self.set_flag(Decimal, true),
-                // SEI
-                0x78 => self.set_flag(Interrupt, true),
-                // TAX
-                0xAA => self.tax(),
-                // TAY
-                0xA8 => self.tay(),
-                // TSX
-                0xBA => self.tsx(),
-                // TXA
-                0x8A => self.txa(),
-                // TXS
-                0x9A => self.txs(),
-                // TYA
-                0x98 => self.tya(),
-                // NOP
-                0xEA => self.nop(),
-                // ADC
-                0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => self.adc(&opcode.mode),
-                // SBC
-                0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => self.sbc(&opcode.mode),
-                // PHA
-                0x48 => self.pha(),
-                // PHP
-                0x08 => self.php(),
-                // PLA
-                0x68 => self.pla(),
-                // PLP
-                0x28 => self.plp(),
-                // BCC
-                0x90 => self.branch(!self.ps.carry),
-                // BCS
-                0xB0 => self.branch(self.ps.carry),
-                // BEQ
-                0xF0 => self.branch(self.ps.zero),
-                // BMI
-                0x30 => self.branch(self.ps.negative),
-                // BNE
-                0xD0 => self.branch(!self.ps.zero),
-                // BPL
-                0x10 => self.branch(!self.ps.negative),
-                // BVC
-                0x50 => self.branch(!self.ps.overflow),
-                // BVS
-                0x70 => self.branch(self.ps.overflow),
-                // CMP
-                0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
-                    self.cmp(&opcode.mode, self.a)
+            if !self.interrupt_pending() {
+                callback(self);
+            }
+            if self.step()?.event == Some(StepEvent::Halted) {
+                return Ok(());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cartridge::test::test_rom;
+    use debugger::Watchpoint;
+    use ram::FlatRam;
+
+    fn load_program<B: CpuBus>(cpu: &mut Cpu<B>, program: &[u8]) {
+        for (i, byte) in program.iter().enumerate() {
+            cpu.mem_write(0x0600 + i as u16, *byte);
+        }
+        cpu.pc = 0x0600;
+    }
+
+    fn run_program(program: &[u8]) -> Cpu<FlatRam> {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        load_program(&mut cpu, program);
+        cpu.run().unwrap();
+        cpu
+    }
+
+    #[test]
+    fn test_opcode_table_is_complete() {
+        assert!(opcodes::OPCODES.iter().all(|opcode| opcode.is_some()));
+        for (code, opcode) in opcodes::OPCODES.iter().enumerate() {
+            assert_eq!(opcode.unwrap().code as usize, code);
+        }
+    }
+
+    #[test]
+    fn test_cmos_opcode_table_is_complete() {
+        for (code, opcode) in opcodes::CMOS_OPCODES.iter().enumerate() {
+            let opcode = opcode.unwrap();
+            assert_eq!(opcode.code as usize, code);
+            assert_ne!(opcode.op, Op::Jam);
+            assert!(opcode.op == Op::Nop || !opcode.mnemonic.starts_with('*'));
+        }
+    }
+
+    #[test]
+    fn test_jam_halts() {
+        let cpu = run_program(&[0xe8, 0x02, 0xe8]);
+        assert_eq!(cpu.x, 1);
+        assert_eq!(cpu.pc, 0x0601);
+    }
+
+    #[test]
+    fn test_load_assembly_into_rom() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        let err = cpu.load_assembly("NOP\n.org $8000\nNOP").unwrap_err();
+        assert_eq!(err.line, 2);
+        assert!(err.message.contains("cartridge ROM"));
+        // nothing is left pending for the next instruction
+        cpu.pc = 0x0600;
+        assert!(cpu.step().is_ok());
+    }
+
+    #[test]
+    fn test_pc_wraps() {
+        // $FFFD: NOP; LDA $1234, its high byte at $0000; JAM
+        let mut ram = FlatRam::new();
+        ram.load(0xfffd, &[0xea, 0xad, 0x34]);
+        ram.load(0x0000, &[0x12, 0x02]);
+        ram.load(0x1234, &[0x77]);
+        let mut cpu = Cpu::with_bus(ram);
+        cpu.pc = 0xfffd;
+        let mut lines = vec![];
+        cpu.run_with_callback(|cpu| lines.push(trace::trace(cpu).unwrap()))
+            .unwrap();
+        assert!(lines[1].starts_with("FFFE  AD 34 12  LDA $1234 = 77"));
+        assert_eq!(cpu.a, 0x77);
+        assert_eq!(cpu.pc, 0x0001);
+    }
+
+    #[test]
+    fn test_lax_sax() {
+        // LAX $10; LDA #$0f; SAX $11
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.mem_write(0x10, 0x3c);
+        load_program(&mut cpu, &[0xa7, 0x10, 0xa9, 0x0f, 0x87, 0x11, 0x02]);
+        cpu.run().unwrap();
+        assert_eq!(cpu.x, 0x3c);
+        assert_eq!(cpu.mem_read(0x11), 0x0c);
+    }
+
+    #[test]
+    fn test_dcp_isb() {
+        // LDA #$05; DCP $10; ($10 = 6 -> 5, A == 5) ; ISB $11 ($11 = $ff -> 0, A - 0)
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.mem_write(0x10, 0x06);
+        cpu.mem_write(0x11, 0xff);
+        load_program(&mut cpu, &[0xa9, 0x05, 0xc7, 0x10, 0x38, 0xe7, 0x11, 0x02]);
+        cpu.run().unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x05);
+        assert_eq!(cpu.mem_read(0x11), 0x00);
+        assert_eq!(cpu.a, 0x05);
+        assert!(cpu.ps.carry);
+    }
+
+    #[test]
+    fn test_slo_rla_sre_rra() {
+        // SLO $10 ($10 = $81): carry set, $10 = $02, A = $02
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.mem_write(0x10, 0x81);
+        load_program(&mut cpu, &[0x07, 0x10, 0x02]);
+        cpu.run().unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x02);
+        assert_eq!(cpu.a, 0x02);
+        assert!(cpu.ps.carry);
+
+        // LDA #$ff; SEC; RLA $10 ($10 = $40 -> $81), A = $81
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.mem_write(0x10, 0x40);
+        load_program(&mut cpu, &[0xa9, 0xff, 0x38, 0x27, 0x10, 0x02]);
+        cpu.run().unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x81);
+        assert_eq!(cpu.a, 0x81);
+        assert!(!cpu.ps.carry);
+        assert!(cpu.ps.negative);
+
+        // LDA #$0f; SRE $10 ($10 = $03 -> $01), A = $0e, carry set
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.mem_write(0x10, 0x03);
+        load_program(&mut cpu, &[0xa9, 0x0f, 0x47, 0x10, 0x02]);
+        cpu.run().unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.a, 0x0e);
+        assert!(cpu.ps.carry);
+
+        // LDA #$10; RRA $10 ($10 = $03 -> $01, carry in), A = $12
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.mem_write(0x10, 0x03);
+        load_program(&mut cpu, &[0xa9, 0x10, 0x67, 0x10, 0x02]);
+        cpu.run().unwrap();
+        assert_eq!(cpu.mem_read(0x10), 0x01);
+        assert_eq!(cpu.a, 0x12);
+    }
+
+    #[test]
+    fn test_immediate_unofficial() {
+        // LDA #$ff; ANC #$80
+        let cpu = run_program(&[0xa9, 0xff, 0x0b, 0x80, 0x02]);
+        assert_eq!(cpu.a, 0x80);
+        assert!(cpu.ps.carry);
+
+        // LDA #$ff; ALR #$03
+        let cpu = run_program(&[0xa9, 0xff, 0x4b, 0x03, 0x02]);
+        assert_eq!(cpu.a, 0x01);
+        assert!(cpu.ps.carry);
+
+        // LDA #$ff; SEC; ARR #$ff
+        let cpu = run_program(&[0xa9, 0xff, 0x38, 0x6b, 0xff, 0x02]);
+        assert_eq!(cpu.a, 0xff);
+        assert!(cpu.ps.carry);
+        assert!(!cpu.ps.overflow);
+
+        // LDA #$0f; LDX #$fc; AXS #$02
+        let cpu = run_program(&[0xa9, 0x0f, 0xa2, 0xfc, 0xcb, 0x02, 0x02]);
+        assert_eq!(cpu.x, 0x0a);
+        assert!(cpu.ps.carry);
+    }
+
+    #[test]
+    fn test_multi_byte_nops() {
+        // DOP #$ff; TOP $1234,X; NOP; INX
+        let cpu = run_program(&[0x80, 0xff, 0x1c, 0x34, 0x12, 0x1a, 0xe8, 0x02]);
+        assert_eq!(cpu.x, 1);
+        assert_eq!(cpu.a, 0);
+        assert_eq!(cpu.pc, 0x0607);
+    }
+
+    #[test]
+    fn test_cycles() {
+        // LDA #$01; LDX #$20; LDA $10f0,X (page cross); STA $10f0,X
+        let cpu = run_program(&[
+            0xa9, 0x01, 0xa2, 0x20, 0xbd, 0xf0, 0x10, 0x9d, 0xf0, 0x10, 0x02,
+        ]);
+        assert_eq!(cpu.cycles, 2 + 2 + 5 + 5 + 2);
+
+        // LDY #$01; LDA ($10),Y with ($10) = $00ff (page cross)
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.mem_write(0x10, 0xff);
+        cpu.mem_write(0x11, 0x00);
+        load_program(&mut cpu, &[0xa0, 0x01, 0xb1, 0x10, 0x02]);
+        cpu.run().unwrap();
+        assert_eq!(cpu.cycles, 2 + 6 + 2);
+    }
+
+    #[test]
+    fn test_branch_cycles() {
+        // SEC; BCC +0 (not taken); BCS +0 (taken)
+        let cpu = run_program(&[0x38, 0x90, 0x00, 0xb0, 0x00, 0x02]);
+        assert_eq!(cpu.cycles, 2 + 2 + 3 + 2);
+
+        // branch taken across a page boundary
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.mem_write(0x06fc, 0x38); // SEC
+        cpu.mem_write(0x06fd, 0xb0); // BCS +1
+        cpu.mem_write(0x06fe, 0x01);
+        cpu.mem_write(0x06ff, 0x02);
+        cpu.mem_write(0x0700, 0x02); // JAM
+        cpu.pc = 0x06fc;
+        cpu.run().unwrap();
+        assert_eq!(cpu.pc, 0x0700);
+        assert_eq!(cpu.cycles, 2 + 4 + 2);
+    }
+
+    // The test ROM is filled with 0x01, so every vector points to $0101
+    fn install_handler(cpu: &mut Cpu) {
+        // INX; RTI
+        cpu.mem_write(0x0101, 0xe8);
+        cpu.mem_write(0x0102, 0x40);
+    }
+
+    #[test]
+    fn test_brk() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        install_handler(&mut cpu);
+        // BRK; padding; INY; JAM
+        load_program(&mut cpu, &[0x00, 0xff, 0xc8, 0x02]);
+        cpu.run().unwrap();
+        assert_eq!(cpu.x, 1);
+        assert_eq!(cpu.y, 1);
+        assert_eq!(cpu.pc, 0x0603);
+        assert_eq!(cpu.sp, STACK_RESET);
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x0602);
+        assert_eq!(cpu.mem_read(0x01fb) & 0x30, 0x30);
+        assert_eq!(cpu.cycles, 7 + 2 + 6 + 2 + 2);
+    }
+
+    #[test]
+    fn test_nmi_is_edge_triggered() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        install_handler(&mut cpu);
+        // NOP; NOP; INY; JAM
+        load_program(&mut cpu, &[0xea, 0xea, 0xc8, 0x02]);
+        cpu.run_with_callback(|cpu| {
+            if cpu.pc == 0x0601 {
+                cpu.bus.set_nmi(true);
+            }
+        })
+        .unwrap();
+        assert_eq!(cpu.x, 1);
+        assert_eq!(cpu.y, 1);
+        // B flag is clear for hardware interrupts
+        assert_eq!(cpu.mem_read(0x01fb) & 0x30, 0x20);
+        assert_eq!(cpu.mem_read_u16(0x01fc), 0x0601);
+    }
+
+    #[test]
+    fn test_irq_is_level_triggered() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        install_handler(&mut cpu);
+        // CLI; NOP; JAM
+        load_program(&mut cpu, &[0x58, 0xea, 0x02]);
+        cpu.bus.set_irq(1, true);
+        cpu.run_with_callback(|cpu| {
+            if cpu.x == 3 {
+                cpu.bus.set_irq(1, false);
+            }
+        })
+        .unwrap();
+        assert_eq!(cpu.x, 3);
+        assert_eq!(cpu.pc, 0x0602);
+    }
+
+    #[test]
+    fn test_irq_is_masked() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        install_handler(&mut cpu);
+        // SEI; NOP; JAM
+        load_program(&mut cpu, &[0x78, 0xea, 0x02]);
+        cpu.bus.set_irq(1, true);
+        cpu.run().unwrap();
+        assert_eq!(cpu.x, 0);
+    }
+
+    #[test]
+    fn test_step() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        install_handler(&mut cpu);
+        // LDA $10f0,X; BRK; padding; JAM
+        load_program(&mut cpu, &[0xbd, 0xf0, 0x10, 0x00, 0xff, 0x02]);
+        cpu.x = 0x20;
+        assert_eq!(
+            cpu.step().unwrap(),
+            StepResult {
+                opcode: 0xbd,
+                cycles: 5,
+                event: None
+            }
+        );
+        assert_eq!(
+            cpu.step().unwrap(),
+            StepResult {
+                opcode: 0x00,
+                cycles: 7,
+                event: Some(StepEvent::Interrupt(Interrupt::Brk))
+            }
+        );
+        assert_eq!(cpu.pc, 0x0101);
+
+        cpu.bus.set_nmi(true);
+        assert_eq!(
+            cpu.step().unwrap(),
+            StepResult {
+                opcode: 0x00,
+                cycles: 7,
+                event: Some(StepEvent::Interrupt(Interrupt::Nmi))
+            }
+        );
+        assert_eq!(cpu.pc, 0x0101);
+    }
+
+    #[test]
+    fn test_jam_ignores_interrupts() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        load_program(&mut cpu, &[0x02]);
+        assert_eq!(cpu.step().unwrap().event, Some(StepEvent::Halted));
+        cpu.bus.set_nmi(true);
+        assert_eq!(
+            cpu.step().unwrap(),
+            StepResult {
+                opcode: 0x02,
+                cycles: 0,
+                event: Some(StepEvent::Halted)
+            }
+        );
+        assert_eq!(cpu.pc, 0x0600);
+    }
+
+    #[test]
+    fn test_run_until() {
+        // loop: INX; JMP loop
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        load_program(&mut cpu, &[0xe8, 0x4c, 0x00, 0x06]);
+        assert_eq!(cpu.run_for_cycles(10).unwrap(), StopReason::CyclesElapsed);
+        assert_eq!(cpu.cycles, 10);
+        assert_eq!(cpu.x, 2);
+
+        assert_eq!(cpu.run_until_pc(0x0601).unwrap(), StopReason::PcReached);
+        assert_eq!(cpu.x, 3);
+
+        assert_eq!(
+            cpu.run_until(|cpu| cpu.x == 0x10).unwrap(),
+            StopReason::Predicate
+        );
+        assert_eq!(cpu.pc, 0x0601);
+
+        let mut cpu = run_program(&[0xea, 0x02]);
+        assert_eq!(cpu.run_until_pc(0x1234).unwrap(), StopReason::Halted);
+        assert_eq!(cpu.run_for_cycles(100).unwrap(), StopReason::Halted);
+    }
+
+    #[test]
+    fn test_bus_fault() {
+        // STA $8000; JAM
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        load_program(&mut cpu, &[0x8d, 0x00, 0x80, 0x02]);
+        assert_eq!(
+            cpu.run(),
+            Err(EmuError::BusFault {
+                addr: 0x8000,
+                write: true,
+                reason: "attempt to write to cartridge ROM space"
+            })
+        );
+        assert_eq!(cpu.pc, 0x0603);
+    }
+
+    #[test]
+    fn test_ppu_open_bus() {
+        // LDA #$2a; STA $2000; LDX $2006; STA $2002; JAM
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        load_program(
+            &mut cpu,
+            &[
+                0xa9, 0x2a, 0x8d, 0x00, 0x20, 0xae, 0x06, 0x20, 0x8d, 0x02, 0x20, 0x02,
+            ],
+        );
+        assert_eq!(cpu.run(), Ok(()));
+        assert_eq!(cpu.x, 0x2a);
+    }
+
+    #[test]
+    fn test_read_side_effects() {
+        // LDA $2002; JAM
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        load_program(&mut cpu, &[0xad, 0x02, 0x20, 0x02]);
+        // run the PPU into VBlank
+        for _ in 0..28_000 / 100 {
+            cpu.bus.tick(100);
+        }
+        assert_eq!(cpu.peek(0x2002) & 0x80, 0x80);
+
+        // tracing only peeks
+        assert!(trace::trace(&cpu).unwrap().contains("LDA $2002 = 80"));
+        assert_eq!(cpu.peek(0x2002) & 0x80, 0x80);
+
+        cpu.run().unwrap();
+        assert_eq!(cpu.a & 0x80, 0x80);
+        assert_eq!(cpu.peek(0x2002) & 0x80, 0);
+    }
+
+    #[test]
+    fn test_joypad_read_shifts() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.bus
+            .joypad1()
+            .set_button_pressed_status(crate::joypad::JoypadButton::B, true);
+        cpu.mem_write(0x4016, 1);
+        cpu.mem_write(0x4016, 0);
+        assert_eq!(cpu.peek(0x4016), 0);
+        assert_eq!(cpu.mem_read(0x4016), 0);
+        assert_eq!(cpu.peek(0x4016), 1);
+        assert_eq!(cpu.mem_read(0x4016), 1);
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        let mut rom = test_rom();
+        rom.mapper = 3;
+        assert_eq!(Cpu::new(rom).err(), Some(EmuError::UnsupportedMapper(3)));
+    }
+
+    #[test]
+    fn test_flat_ram() {
+        let mut ram = FlatRam::new();
+        // LDA #$42; STA $8000; BRK; padding; JAM
+        ram.load(0xc000, &[0xa9, 0x42, 0x8d, 0x00, 0x80, 0x00, 0x00, 0x02]);
+        // INX; RTI
+        ram.load(0xd000, &[0xe8, 0x40]);
+        ram.load(0xfffc, &[0x00, 0xc0, 0x00, 0xd0]);
+
+        let mut cpu = Cpu::with_bus(ram);
+        cpu.reset();
+        assert_eq!(cpu.pc, 0xc000);
+        cpu.run().unwrap();
+        assert_eq!(cpu.mem_read(0x8000), 0x42);
+        assert_eq!(cpu.x, 1);
+        assert_eq!(cpu.pc, 0xc007);
+    }
+
+    fn exec_mode_cpu(
+        variant: CpuVariant,
+        mode: ExecMode,
+        code: u8,
+        index: u8,
+        flags: u8,
+    ) -> Cpu<FlatRam> {
+        let mut ram = FlatRam::new();
+        // every zero page pointer points at $1111
+        ram.load(0x0000, &[0x11; 0x100]);
+        ram.load(0x0600, &[code, 0xf0, 0x10]);
+        let mut cpu = Cpu::with_bus(ram);
+        cpu.exec_mode = mode;
+        cpu.variant = variant;
+        cpu.pc = 0x0600;
+        cpu.a = 0x5a;
+        cpu.x = index;
+        cpu.y = index;
+        cpu.ps = flags.into();
+        cpu
+    }
+
+    #[test]
+    fn test_exec_modes_agree() {
+        let variants = [CpuVariant::Ricoh2A03, CpuVariant::Cmos65C02];
+        for (variant, code) in variants
+            .iter()
+            .flat_map(|v| (0..=0xffu8).map(move |c| (*v, c)))
+        {
+            for (index, flags) in [(0x00, 0x00), (0x20, 0xc3), (0xff, 0x0c)] {
+                let mut fast = exec_mode_cpu(variant, ExecMode::Instruction, code, index, flags);
+                let mut exact = exec_mode_cpu(variant, ExecMode::Cycle, code, index, flags);
+                let fast_step = fast.step().unwrap();
+                let exact_step = exact.step().unwrap();
+
+                let context = format!("{:?} opcode {:02x}, index {:02x}", variant, code, index);
+                assert_eq!(fast_step.cycles, exact_step.cycles, "{}", context);
+                assert_eq!(fast.cycles, exact.cycles, "{}", context);
+                assert_eq!(
+                    (fast.a, fast.x, fast.y, fast.sp, fast.pc),
+                    (exact.a, exact.x, exact.y, exact.sp, exact.pc),
+                    "{}",
+                    context
+                );
+                assert_eq!(u8::from(&fast.ps), u8::from(&exact.ps), "{}", context);
+                for addr in 0..0x2000 {
+                    assert_eq!(fast.mem_read(addr), exact.mem_read(addr), "{}", context);
                 }
-                // CPX
-                0xE0 | 0xE4 | 0xEC => self.cmp(&opcode.mode, self.x),
-                // CPY
-                0xC0 | 0xC4 | 0xCC => self.cmp(&opcode.mode, self.y),
-                // DEC
-                0xC6 | 0xD6 | 0xCE | 0xDE => self.dec(&opcode.mode),
-                // DEX
-                0xCA => self.dex(),
-                // DEY
-                0x88 => self.dey(),
-                // INC
-                0xE6 | 0xF6 | 0xEE | 0xFE => self.inc(&opcode.mode),
-                // INX
-                0xE8 => self.inx(),
-                // INY
-                0xC8 => self.iny(),
-                // JMP
-                0x4C => self.jmp_abs(),
-                0x6C => self.jmp_indirect(),
-                // JSR
-                0x20 => self.jsr(),
-                // RTI
-                0x40 => self.rti(),
-                // RTS
-                0x60 => self.rts(),
-                _ => todo!(),
-            }
-
-            if pc == self.pc {
-                self.pc += (opcode.len - 1) as u16;
             }
         }
     }
+
+    // Records every access the CPU makes, as (address, Some(data)) for writes.
+    struct LogBus {
+        ram: FlatRam,
+        log: Vec<(u16, Option<u8>)>,
+    }
+
+    impl Mem for LogBus {
+        fn mem_read(&mut self, addr: u16) -> u8 {
+            self.log.push((addr, None));
+            self.ram.peek(addr)
+        }
+
+        fn peek(&self, addr: u16) -> u8 {
+            self.ram.peek(addr)
+        }
+
+        fn mem_write(&mut self, addr: u16, data: u8) {
+            self.log.push((addr, Some(data)));
+            self.ram.mem_write(addr, data);
+        }
+    }
+
+    impl CpuBus for LogBus {}
+
+    fn logged_step(program: &[u8], x: u8) -> Vec<(u16, Option<u8>)> {
+        let mut ram = FlatRam::new();
+        ram.load(0x0600, program);
+        ram.load(0x0010, &[0x41]);
+        let mut cpu = Cpu::with_bus(LogBus {
+            ram,
+            log: Default::default(),
+        });
+        cpu.exec_mode = ExecMode::Cycle;
+        cpu.pc = 0x0600;
+        cpu.x = x;
+        let step = cpu.step().unwrap();
+        let log = std::mem::take(&mut cpu.bus.log);
+        assert_eq!(step.cycles as usize, log.len());
+        log
+    }
+
+    #[test]
+    fn test_cycle_mode_bus_accesses() {
+        // INC $10: read-modify-write writes the old value back first
+        assert_eq!(
+            logged_step(&[0xe6, 0x10], 0),
+            vec![
+                (0x0600, None),
+                (0x0601, None),
+                (0x0010, None),
+                (0x0010, Some(0x41)),
+                (0x0010, Some(0x42)),
+            ]
+        );
+
+        // LDA $10f0,X crossing a page reads the unfixed address first
+        assert_eq!(
+            logged_step(&[0xbd, 0xf0, 0x10], 0x20),
+            vec![
+                (0x0600, None),
+                (0x0601, None),
+                (0x0602, None),
+                (0x1010, None),
+                (0x1110, None),
+            ]
+        );
+
+        // INX reads the next byte and throws it away
+        assert_eq!(
+            logged_step(&[0xe8], 0),
+            vec![(0x0600, None), (0x0601, None)]
+        );
+    }
+
+    fn run_variant(variant: CpuVariant, program: &[u8]) -> Cpu<FlatRam> {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.variant = variant;
+        load_program(&mut cpu, program);
+        cpu.run_until_pc(0x0600 + program.len() as u16).unwrap();
+        cpu
+    }
+
+    #[test]
+    fn test_decimal_mode() {
+        // SED; CLC; LDA #$58; ADC #$46
+        let program = [0xf8, 0x18, 0xa9, 0x58, 0x69, 0x46];
+        let cpu = run_variant(CpuVariant::Ricoh2A03, &program);
+        assert_eq!(cpu.a, 0x9e);
+        assert!(!cpu.ps.carry);
+        let cpu = run_variant(CpuVariant::Nmos6502, &program);
+        assert_eq!(cpu.a, 0x04);
+        assert!(cpu.ps.carry);
+
+        // SED; SEC; LDA #$12; SBC #$21
+        let program = [0xf8, 0x38, 0xa9, 0x12, 0xe9, 0x21];
+        let cpu = run_variant(CpuVariant::Nmos6502, &program);
+        assert_eq!(cpu.a, 0x91);
+        assert!(!cpu.ps.carry);
+        let cpu = run_variant(CpuVariant::Cmos65C02, &program);
+        assert_eq!(cpu.a, 0x91);
+        assert!(cpu.ps.negative);
+
+        // SED; CLC; LDA #$99; ADC #$01: Z is only valid on the 65C02
+        let program = [0xf8, 0x18, 0xa9, 0x99, 0x69, 0x01];
+        let cpu = run_variant(CpuVariant::Nmos6502, &program);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.ps.carry);
+        assert!(!cpu.ps.zero);
+        assert!(cpu.ps.negative);
+        let cpu = run_variant(CpuVariant::Cmos65C02, &program);
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.ps.zero);
+        assert!(!cpu.ps.negative);
+        // one more cycle for ADC in decimal mode
+        assert_eq!(cpu.cycles, 2 + 2 + 2 + 3);
+    }
+
+    #[test]
+    fn test_cmos_instructions() {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.variant = CpuVariant::Cmos65C02;
+        cpu.mem_write(0x10, 0x00);
+        cpu.mem_write(0x11, 0x03);
+        cpu.mem_write(0x0300, 0x55);
+        cpu.mem_write(0x20, 0x0f);
+        // JMP ($06ff) reads its high byte from $0700, not $0600
+        cpu.mem_write(0x06ff, 0x20);
+        cpu.mem_write(0x0700, 0x06);
+        // JMP ($06fa,X) with X = 7
+        cpu.mem_write(0x0620, 0x7c);
+        cpu.mem_write(0x0621, 0xfa);
+        cpu.mem_write(0x0622, 0x06);
+        cpu.mem_write(0x0701, 0x03);
+        cpu.mem_write(0x0702, 0x07);
+        load_program(
+            &mut cpu,
+            &[
+                0xb2, 0x10, // LDA ($10)
+                0x1a, // INC A
+                0x92, 0x10, // STA ($10)
+                0x64, 0x11, // STZ $11
+                0xa2, 0x07, // LDX #$07
+                0xda, // PHX
+                0x7a, // PLY
+                0x04, 0x20, // TSB $20
+                0x89, 0x80, // BIT #$80
+                0x80, 0x01, // BRA +1
+                0xe8, // INX (skipped)
+                0x6c, 0xff, 0x06, // JMP ($06ff)
+            ],
+        );
+        cpu.run_until_pc(0x0703).unwrap();
+
+        assert_eq!(cpu.mem_read(0x0300), 0x56);
+        assert_eq!(cpu.mem_read(0x11), 0x00);
+        assert_eq!((cpu.x, cpu.y), (0x07, 0x07));
+        assert_eq!(cpu.mem_read(0x20), 0x5f);
+        assert!(cpu.ps.zero);
+        assert!(!cpu.ps.negative);
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        // INX; INX; INX; JAM
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        load_program(&mut cpu, &[0xe8, 0xe8, 0xe8, 0x02]);
+        cpu.debugger.add_breakpoint(0x0602);
+        assert_eq!(
+            cpu.run_until_break(),
+            Ok(StopReason::Break(BreakReason::Breakpoint(0x0602)))
+        );
+        assert_eq!(cpu.x, 2);
+        // resuming does not hit the breakpoint we are stopped at
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+        assert_eq!(cpu.x, 3);
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        // LDA $10; STA $0200; INX; JAM
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.mem_write(0x10, 0x42);
+        load_program(&mut cpu, &[0xa5, 0x10, 0x8d, 0x00, 0x02, 0xe8, 0x02]);
+        cpu.debugger
+            .add_watchpoint(0x0200..=0x02ff, WatchKind::Write);
+        cpu.debugger
+            .add_watchpoint(0x0605..=0x0605, WatchKind::Execute);
+        cpu.debugger.add_watchpoint(0x10..=0x10, WatchKind::Read);
+
+        let watch = |kind, addr, value| {
+            Ok(StopReason::Break(BreakReason::Watchpoint {
+                kind,
+                addr,
+                value,
+            }))
+        };
+        assert_eq!(cpu.run_until_break(), watch(WatchKind::Read, 0x10, 0x42));
+        assert_eq!(cpu.pc, 0x0602);
+        assert_eq!(cpu.run_until_break(), watch(WatchKind::Write, 0x0200, 0x42));
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+        assert_eq!(cpu.x, 1);
+
+        cpu.reset();
+        cpu.pc = 0x0602;
+        assert_eq!(
+            cpu.debugger.remove_watchpoint(0),
+            Some(Watchpoint {
+                range: 0x0200..=0x02ff,
+                kind: WatchKind::Write
+            })
+        );
+        assert_eq!(
+            cpu.run_until_break(),
+            watch(WatchKind::Execute, 0x0605, 0xe8)
+        );
+        assert_eq!(cpu.x, 0);
+    }
+
+    #[test]
+    fn test_read_watchpoints_skip_fetches() {
+        // LDA #$01; LDX $0600; JAM
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        load_program(&mut cpu, &[0xa9, 0x01, 0xae, 0x00, 0x06, 0x02]);
+        cpu.debugger
+            .add_watchpoint(0x0600..=0x06ff, WatchKind::Read);
+        assert_eq!(
+            cpu.run_until_break(),
+            Ok(StopReason::Break(BreakReason::Watchpoint {
+                kind: WatchKind::Read,
+                addr: 0x0600,
+                value: 0xa9
+            }))
+        );
+        assert_eq!(cpu.pc, 0x0605);
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+    }
+
+    #[test]
+    fn test_break_on_opcode_and_interrupt() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        install_handler(&mut cpu);
+        // INY; BRK; padding; INY; JAM
+        load_program(&mut cpu, &[0xc8, 0x00, 0xff, 0xc8, 0x02]);
+        cpu.debugger.break_on_opcode(0x00, true);
+        cpu.debugger.break_on_interrupt(Interrupt::Brk, true);
+
+        assert_eq!(
+            cpu.run_until_break(),
+            Ok(StopReason::Break(BreakReason::Opcode(0x00)))
+        );
+        assert_eq!(cpu.pc, 0x0601);
+        assert_eq!(
+            cpu.run_until_break(),
+            Ok(StopReason::Break(BreakReason::Interrupt(Interrupt::Brk)))
+        );
+        assert_eq!(cpu.pc, 0x0101);
+
+        cpu.debugger.clear();
+        assert!(cpu.debugger.is_empty());
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+        assert_eq!((cpu.x, cpu.y), (1, 2));
+    }
+
+    #[test]
+    fn test_history_step_back() {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.load_assembly(
+            "
+                   LDX #$05
+            loop:  TXA
+                   STA $0200,X
+                   JSR count
+                   DEX
+                   BNE loop
+                   .byte $02 ; JAM
+            count: INC $10
+                   RTS
+            ",
+        )
+        .unwrap();
+        let initial: Vec<u8> = (0..0x0800).map(|addr| cpu.peek(addr)).collect();
+        let (pc, sp) = (cpu.pc, cpu.sp);
+
+        cpu.history.set_capacity(10);
+        assert_eq!(cpu.run_until_pc(0x0609), Ok(StopReason::PcReached));
+        let steps = cpu.history.len();
+        assert_eq!(steps, 6);
+        let dump: Vec<String> = cpu.history.records().map(|r| r.to_string()).collect();
+        assert_eq!(
+            dump[2],
+            "0603  9D 00 02  A:05 X:05 Y:00 P:24 SP:FD CYC:4 0205:00->05"
+        );
+        assert_eq!(
+            dump[3],
+            "0606  20 0D 06  A:05 X:05 Y:00 P:24 SP:FD CYC:9 01FD:00->06 01FC:00->08"
+        );
+
+        for _ in 0..steps {
+            assert!(cpu.step_back().is_ok());
+        }
+        assert_eq!(cpu.step_back(), Err(StepBackError::NoHistory));
+        let memory: Vec<u8> = (0..0x0800).map(|addr| cpu.peek(addr)).collect();
+        assert!(memory == initial);
+        assert_eq!((cpu.pc, cpu.sp, cpu.x, cpu.cycles), (pc, sp, 0, 0));
+
+        // the buffer keeps the last instructions
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+        assert_eq!(cpu.history.len(), 10);
+        assert_eq!(cpu.history.records().last().unwrap().bytes[0], 0x02);
+        while cpu.step_back().is_ok() {}
+        assert_eq!((cpu.pc, cpu.x), (0x0609, 2));
+        assert_eq!(cpu.peek(0x10), 4);
+    }
+
+    #[test]
+    fn test_step_back_over_register_write() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.load_assembly(
+            "
+            LDA #$21
+            STA $2006
+            STA $2006
+            STA $10
+            .byte $02
+            ",
+        )
+        .unwrap();
+        cpu.history.set_capacity(10);
+        assert_eq!(cpu.run_until_pc(0x060a), Ok(StopReason::PcReached));
+        assert!(cpu.step_back().is_ok());
+        assert_eq!(cpu.peek(0x10), 0);
+        assert_eq!(
+            cpu.step_back(),
+            Err(StepBackError::RegisterWrite {
+                pc: 0x0605,
+                addr: 0x2006
+            })
+        );
+        // nothing was undone
+        assert_eq!(cpu.pc, 0x0608);
+        assert_eq!(cpu.history.len(), 3);
+    }
 }
@@ -1,11 +1,28 @@
-use lazy_static::lazy_static;
-use std::collections::HashMap;
-
 use crate::cpu::AddressingMode;
+use crate::cpu::CpuVariant;
+
+/// The operation an opcode performs, which selects its handler in the CPU.
+/// Some mnemonics are split when their handler depends on more than the
+/// addressing mode (accumulator shifts and increments, indirect JMP).
+#[rustfmt::skip]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Adc, And, Asl, AslA, Bcc, Bcs, Beq, Bit, Bmi, Bne, Bpl, Brk, Bvc, Bvs,
+    Clc, Cld, Cli, Clv, Cmp, Cpx, Cpy, Dec, Dex, Dey, Eor, Inc, Inx, Iny,
+    Jmp, JmpIndirect, Jsr, Lda, Ldx, Ldy, Lsr, LsrA, Nop, Ora, Pha, Php,
+    Pla, Plp, Rol, RolA, Ror, RorA, Rti, Rts, Sbc, Sec, Sed, Sei, Sta, Stx,
+    Sty, Tax, Tay, Tsx, Txa, Txs, Tya,
+    // Unofficial
+    Lax, Sax, Dcp, Isb, Slo, Rla, Sre, Rra, Anc, Alr, Arr, Axs, Xaa, Lxa,
+    Las, Tas, Sha, Shx, Shy, Jam,
+    // 65C02
+    Bra, DecA, IncA, JmpIndexedIndirect, Phx, Phy, Plx, Ply, Stz, Trb, Tsb,
+}
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy)]
 pub struct OpCode {
     pub code: u8,
+    pub op: Op,
     pub mnemonic: &'static str,
     pub len: u8,
     pub cycles: u8,
@@ -13,9 +30,17 @@ pub struct OpCode {
 }
 
 impl OpCode {
-    fn new(code: u8, mnemonic: &'static str, len: u8, cycles: u8, mode: AddressingMode) -> Self {
+    const fn new(
+        code: u8,
+        op: Op,
+        mnemonic: &'static str,
+        len: u8,
+        cycles: u8,
+        mode: AddressingMode,
+    ) -> Self {
         OpCode {
             code,
+            op,
             mnemonic,
             len,
             cycles,
@@ -24,196 +49,426 @@ impl OpCode {
     }
 }
 
-lazy_static! {
-    pub static ref CPU_OPS_CODES: Vec<OpCode> = vec![
-        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
-        OpCode::new(0xAA, "TAX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xA8, "TAY", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xBA, "TSX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x8A, "TXA", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x9A, "TXS", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x98, "TYA", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xE6, "INC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xF6, "INC", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0xEE, "INC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xFE, "INC", 3, 7, AddressingMode::AbsoluteX),
-        OpCode::new(0xE8, "INX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xC8, "INY", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xC6, "DEC", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0xD6, "DEC", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0xCE, "DEC", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0xDE, "DEC", 3, 7, AddressingMode::AbsoluteX),
-        OpCode::new(0xCA, "DEX", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x88, "DEY", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xEA, "NOP", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xA9, "LDA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA5, "LDA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB5, "LDA", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0xAD, "LDA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBD, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
-        OpCode::new(0xB9, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
-        OpCode::new(0xA1, "LDA", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0xB1, "LDA", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
-
-        OpCode::new(0xA2, "LDX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA6, "LDX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB6, "LDX", 2, 4, AddressingMode::ZeroPageY),
-        OpCode::new(0xAE, "LDX", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBE, "LDX", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
-
-        OpCode::new(0xA0, "LDY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xA4, "LDY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xB4, "LDY", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0xAC, "LDY", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xBC, "LDY", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
-
-        OpCode::new(0x86, "STX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x96, "STX", 2, 4, AddressingMode::ZeroPageY),
-        OpCode::new(0x8E, "STX", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0x84, "STY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x94, "STY", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x8C, "STY", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x9D, "STA", 3, 5, AddressingMode::AbsoluteX),
-        OpCode::new(0x99, "STA", 3, 5, AddressingMode::AbsoluteY),
-        OpCode::new(0x81, "STA", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x91, "STA", 2, 6, AddressingMode::IndirectY),
-
-        OpCode::new(0x29, "AND", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x25, "AND", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x35, "AND", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x2D, "AND", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x3D, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
-        OpCode::new(0x39, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
-        OpCode::new(0x21, "AND", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x31, "AND", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
-
-        OpCode::new(0x49, "EOR", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x45, "EOR", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x55, "EOR", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x4D, "EOR", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x5D, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
-        OpCode::new(0x59, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
-        OpCode::new(0x41, "EOR", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x51, "EOR", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
-
-        OpCode::new(0x09, "ORA", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x05, "ORA", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x15, "ORA", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x0D, "ORA", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x1D, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
-        OpCode::new(0x19, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
-        OpCode::new(0x01, "ORA", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x11, "ORA", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
-
-        OpCode::new(0x0A, "ASL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x06, "ASL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x16, "ASL", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0x0E, "ASL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x1E, "ASL", 3, 7, AddressingMode::AbsoluteX),
-
-        OpCode::new(0x2A, "ROL", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x26, "ROL", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x36, "ROL", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0x2E, "ROL", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x3E, "ROL", 3, 7, AddressingMode::AbsoluteX),
-
-        OpCode::new(0x4A, "LSR", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x46, "LSR", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x56, "LSR", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0x4E, "LSR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x5E, "LSR", 3, 7, AddressingMode::AbsoluteX),
-
-        OpCode::new(0x6A, "ROR", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x66, "ROR", 2, 5, AddressingMode::ZeroPage),
-        OpCode::new(0x76, "ROR", 2, 6, AddressingMode::ZeroPageX),
-        OpCode::new(0x6E, "ROR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x7E, "ROR", 3, 7, AddressingMode::AbsoluteX),
-
-        OpCode::new(0x24, "BIT", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x2C, "BIT", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x58, "CLI", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xB8, "CLV", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing),
-        OpCode::new(0x78, "SEI", 1, 2, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0x75, "ADC", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0x6D, "ADC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0x7D, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
-        OpCode::new(0x79, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
-        OpCode::new(0x61, "ADC", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0x71, "ADC", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
-
-        OpCode::new(0xE9, "SBC", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xE5, "SBC", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xF5, "SBC", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0xED, "SBC", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xFD, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
-        OpCode::new(0xF9, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
-        OpCode::new(0xE1, "SBC", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0xF1, "SBC", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
-
-        OpCode::new(0x48, "PHA", 1, 3, AddressingMode::NoneAddressing),
-        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
-        OpCode::new(0x68, "PLA", 1, 4, AddressingMode::NoneAddressing),
-        OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xD0, "BNE", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x70, "BVS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x50, "BVC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x30, "BMI", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xF0, "BEQ", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0xB0, "BCS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x90, "BCC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-        OpCode::new(0x10, "BPL", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
-
-        OpCode::new(0xC9, "CMP", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xC5, "CMP", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xD5, "CMP", 2, 4, AddressingMode::ZeroPageX),
-        OpCode::new(0xCD, "CMP", 3, 4, AddressingMode::Absolute),
-        OpCode::new(0xDD, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
-        OpCode::new(0xD9, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
-        OpCode::new(0xC1, "CMP", 2, 6, AddressingMode::IndirectX),
-        OpCode::new(0xD1, "CMP", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
-
-        OpCode::new(0xE0, "CPX", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xE4, "CPX", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xEC, "CPX", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0xC0, "CPY", 2, 2, AddressingMode::Immediate),
-        OpCode::new(0xC4, "CPY", 2, 3, AddressingMode::ZeroPage),
-        OpCode::new(0xCC, "CPY", 3, 4, AddressingMode::Absolute),
-
-        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
-
-        OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::NoneAddressing),
-        OpCode::new(0x6C, "JMP", 3, 5, AddressingMode::NoneAddressing),
-        OpCode::new(0x20, "JSR", 3, 6, AddressingMode::Absolute),
-        OpCode::new(0x40, "RTI", 1, 6, AddressingMode::NoneAddressing),
-        OpCode::new(0x60, "RTS", 1, 6, AddressingMode::NoneAddressing),
-    ];
-
-
-    pub static ref OPCODES_MAP: HashMap<u8, &'static OpCode> = {
-        let mut map = HashMap::new();
-        for cpuop in &*CPU_OPS_CODES {
-            map.insert(cpuop.code, cpuop);
-        }
-        map
+#[rustfmt::skip]
+pub const CPU_OPS_CODES: &[OpCode] = &[
+    OpCode::new(0x00, Op::Brk, "BRK", 1, 7, AddressingMode::NoneAddressing),
+    OpCode::new(0xAA, Op::Tax, "TAX", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xA8, Op::Tay, "TAY", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xBA, Op::Tsx, "TSX", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x8A, Op::Txa, "TXA", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x9A, Op::Txs, "TXS", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x98, Op::Tya, "TYA", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xE6, Op::Inc, "INC", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xF6, Op::Inc, "INC", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0xEE, Op::Inc, "INC", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xFE, Op::Inc, "INC", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0xE8, Op::Inx, "INX", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xC8, Op::Iny, "INY", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xC6, Op::Dec, "DEC", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xD6, Op::Dec, "DEC", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0xCE, Op::Dec, "DEC", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xDE, Op::Dec, "DEC", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0xCA, Op::Dex, "DEX", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x88, Op::Dey, "DEY", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xEA, Op::Nop, "NOP", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xA9, Op::Lda, "LDA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA5, Op::Lda, "LDA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB5, Op::Lda, "LDA", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xAD, Op::Lda, "LDA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBD, Op::Lda, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0xB9, Op::Lda, "LDA", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
+    OpCode::new(0xA1, Op::Lda, "LDA", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xB1, Op::Lda, "LDA", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
+
+    OpCode::new(0xA2, Op::Ldx, "LDX", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA6, Op::Ldx, "LDX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB6, Op::Ldx, "LDX", 2, 4, AddressingMode::ZeroPageY),
+    OpCode::new(0xAE, Op::Ldx, "LDX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBE, Op::Ldx, "LDX", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
+
+    OpCode::new(0xA0, Op::Ldy, "LDY", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xA4, Op::Ldy, "LDY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB4, Op::Ldy, "LDY", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xAC, Op::Ldy, "LDY", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBC, Op::Ldy, "LDY", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+
+    OpCode::new(0x86, Op::Stx, "STX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x96, Op::Stx, "STX", 2, 4, AddressingMode::ZeroPageY),
+    OpCode::new(0x8E, Op::Stx, "STX", 3, 4, AddressingMode::Absolute),
+
+    OpCode::new(0x84, Op::Sty, "STY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x94, Op::Sty, "STY", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x8C, Op::Sty, "STY", 3, 4, AddressingMode::Absolute),
+
+    OpCode::new(0x85, Op::Sta, "STA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x95, Op::Sta, "STA", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x8D, Op::Sta, "STA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x9D, Op::Sta, "STA", 3, 5, AddressingMode::AbsoluteX),
+    OpCode::new(0x99, Op::Sta, "STA", 3, 5, AddressingMode::AbsoluteY),
+    OpCode::new(0x81, Op::Sta, "STA", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x91, Op::Sta, "STA", 2, 6, AddressingMode::IndirectY),
+
+    OpCode::new(0x29, Op::And, "AND", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x25, Op::And, "AND", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x35, Op::And, "AND", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x2D, Op::And, "AND", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x3D, Op::And, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0x39, Op::And, "AND", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
+    OpCode::new(0x21, Op::And, "AND", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x31, Op::And, "AND", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
+
+    OpCode::new(0x49, Op::Eor, "EOR", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x45, Op::Eor, "EOR", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x55, Op::Eor, "EOR", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x4D, Op::Eor, "EOR", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x5D, Op::Eor, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0x59, Op::Eor, "EOR", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
+    OpCode::new(0x41, Op::Eor, "EOR", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x51, Op::Eor, "EOR", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
+
+    OpCode::new(0x09, Op::Ora, "ORA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x05, Op::Ora, "ORA", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x15, Op::Ora, "ORA", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x0D, Op::Ora, "ORA", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x1D, Op::Ora, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0x19, Op::Ora, "ORA", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
+    OpCode::new(0x01, Op::Ora, "ORA", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x11, Op::Ora, "ORA", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
+
+    OpCode::new(0x0A, Op::AslA, "ASL", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x06, Op::Asl, "ASL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x16, Op::Asl, "ASL", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x0E, Op::Asl, "ASL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x1E, Op::Asl, "ASL", 3, 7, AddressingMode::AbsoluteX),
+
+    OpCode::new(0x2A, Op::RolA, "ROL", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x26, Op::Rol, "ROL", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x36, Op::Rol, "ROL", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x2E, Op::Rol, "ROL", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x3E, Op::Rol, "ROL", 3, 7, AddressingMode::AbsoluteX),
+
+    OpCode::new(0x4A, Op::LsrA, "LSR", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x46, Op::Lsr, "LSR", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x56, Op::Lsr, "LSR", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x4E, Op::Lsr, "LSR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x5E, Op::Lsr, "LSR", 3, 7, AddressingMode::AbsoluteX),
+
+    OpCode::new(0x6A, Op::RorA, "ROR", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x66, Op::Ror, "ROR", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x76, Op::Ror, "ROR", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x6E, Op::Ror, "ROR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x7E, Op::Ror, "ROR", 3, 7, AddressingMode::AbsoluteX),
+
+    OpCode::new(0x24, Op::Bit, "BIT", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x2C, Op::Bit, "BIT", 3, 4, AddressingMode::Absolute),
+
+    OpCode::new(0x18, Op::Clc, "CLC", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xD8, Op::Cld, "CLD", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x58, Op::Cli, "CLI", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xB8, Op::Clv, "CLV", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0x38, Op::Sec, "SEC", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xF8, Op::Sed, "SED", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x78, Op::Sei, "SEI", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0x69, Op::Adc, "ADC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x65, Op::Adc, "ADC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x75, Op::Adc, "ADC", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x6D, Op::Adc, "ADC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x7D, Op::Adc, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0x79, Op::Adc, "ADC", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
+    OpCode::new(0x61, Op::Adc, "ADC", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0x71, Op::Adc, "ADC", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
+
+    OpCode::new(0xE9, Op::Sbc, "SBC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xE5, Op::Sbc, "SBC", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xF5, Op::Sbc, "SBC", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xED, Op::Sbc, "SBC", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xFD, Op::Sbc, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0xF9, Op::Sbc, "SBC", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
+    OpCode::new(0xE1, Op::Sbc, "SBC", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xF1, Op::Sbc, "SBC", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
+
+    OpCode::new(0x48, Op::Pha, "PHA", 1, 3, AddressingMode::NoneAddressing),
+    OpCode::new(0x08, Op::Php, "PHP", 1, 3, AddressingMode::NoneAddressing),
+    OpCode::new(0x68, Op::Pla, "PLA", 1, 4, AddressingMode::NoneAddressing),
+    OpCode::new(0x28, Op::Plp, "PLP", 1, 4, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xD0, Op::Bne, "BNE", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
+    OpCode::new(0x70, Op::Bvs, "BVS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
+    OpCode::new(0x50, Op::Bvc, "BVC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
+    OpCode::new(0x30, Op::Bmi, "BMI", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
+    OpCode::new(0xF0, Op::Beq, "BEQ", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
+    OpCode::new(0xB0, Op::Bcs, "BCS", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
+    OpCode::new(0x90, Op::Bcc, "BCC", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
+    OpCode::new(0x10, Op::Bpl, "BPL", 2, 2 /*(+1 if branch succeeds +2 if to a new page)*/, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xC9, Op::Cmp, "CMP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xC5, Op::Cmp, "CMP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xD5, Op::Cmp, "CMP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xCD, Op::Cmp, "CMP", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xDD, Op::Cmp, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0xD9, Op::Cmp, "CMP", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
+    OpCode::new(0xC1, Op::Cmp, "CMP", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xD1, Op::Cmp, "CMP", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
+
+    OpCode::new(0xE0, Op::Cpx, "CPX", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xE4, Op::Cpx, "CPX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xEC, Op::Cpx, "CPX", 3, 4, AddressingMode::Absolute),
+
+    OpCode::new(0xC0, Op::Cpy, "CPY", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xC4, Op::Cpy, "CPY", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xCC, Op::Cpy, "CPY", 3, 4, AddressingMode::Absolute),
+
+    OpCode::new(0x4C, Op::Jmp, "JMP", 3, 3, AddressingMode::NoneAddressing),
+    OpCode::new(0x6C, Op::JmpIndirect, "JMP", 3, 5, AddressingMode::NoneAddressing),
+    OpCode::new(0x20, Op::Jsr, "JSR", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x40, Op::Rti, "RTI", 1, 6, AddressingMode::NoneAddressing),
+    OpCode::new(0x60, Op::Rts, "RTS", 1, 6, AddressingMode::NoneAddressing),
+
+    // Unofficial opcodes
+    // https://www.nesdev.org/wiki/CPU_unofficial_opcodes
+
+    OpCode::new(0x1A, Op::Nop, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x3A, Op::Nop, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x5A, Op::Nop, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x7A, Op::Nop, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xDA, Op::Nop, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xFA, Op::Nop, "*NOP", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0x80, Op::Nop, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x82, Op::Nop, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x89, Op::Nop, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xC2, Op::Nop, "*NOP", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xE2, Op::Nop, "*NOP", 2, 2, AddressingMode::Immediate),
+
+    OpCode::new(0x04, Op::Nop, "*NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x44, Op::Nop, "*NOP", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x64, Op::Nop, "*NOP", 2, 3, AddressingMode::ZeroPage),
+
+    OpCode::new(0x14, Op::Nop, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x34, Op::Nop, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x54, Op::Nop, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x74, Op::Nop, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xD4, Op::Nop, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0xF4, Op::Nop, "*NOP", 2, 4, AddressingMode::ZeroPageX),
+
+    OpCode::new(0x0C, Op::Nop, "*NOP", 3, 4, AddressingMode::Absolute),
+
+    OpCode::new(0x1C, Op::Nop, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0x3C, Op::Nop, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0x5C, Op::Nop, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0x7C, Op::Nop, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0xDC, Op::Nop, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0xFC, Op::Nop, "*NOP", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+
+    OpCode::new(0xA7, Op::Lax, "*LAX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0xB7, Op::Lax, "*LAX", 2, 4, AddressingMode::ZeroPageY),
+    OpCode::new(0xAF, Op::Lax, "*LAX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0xBF, Op::Lax, "*LAX", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
+    OpCode::new(0xA3, Op::Lax, "*LAX", 2, 6, AddressingMode::IndirectX),
+    OpCode::new(0xB3, Op::Lax, "*LAX", 2, 5/*+1 if page crossed*/, AddressingMode::IndirectY),
+
+    OpCode::new(0x87, Op::Sax, "*SAX", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x97, Op::Sax, "*SAX", 2, 4, AddressingMode::ZeroPageY),
+    OpCode::new(0x8F, Op::Sax, "*SAX", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x83, Op::Sax, "*SAX", 2, 6, AddressingMode::IndirectX),
+
+    OpCode::new(0xEB, Op::Sbc, "*SBC", 2, 2, AddressingMode::Immediate),
+
+    OpCode::new(0xC7, Op::Dcp, "*DCP", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xD7, Op::Dcp, "*DCP", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0xCF, Op::Dcp, "*DCP", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xDF, Op::Dcp, "*DCP", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0xDB, Op::Dcp, "*DCP", 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new(0xC3, Op::Dcp, "*DCP", 2, 8, AddressingMode::IndirectX),
+    OpCode::new(0xD3, Op::Dcp, "*DCP", 2, 8, AddressingMode::IndirectY),
+
+    OpCode::new(0xE7, Op::Isb, "*ISB", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0xF7, Op::Isb, "*ISB", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0xEF, Op::Isb, "*ISB", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0xFF, Op::Isb, "*ISB", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0xFB, Op::Isb, "*ISB", 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new(0xE3, Op::Isb, "*ISB", 2, 8, AddressingMode::IndirectX),
+    OpCode::new(0xF3, Op::Isb, "*ISB", 2, 8, AddressingMode::IndirectY),
+
+    OpCode::new(0x07, Op::Slo, "*SLO", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x17, Op::Slo, "*SLO", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x0F, Op::Slo, "*SLO", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x1F, Op::Slo, "*SLO", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0x1B, Op::Slo, "*SLO", 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new(0x03, Op::Slo, "*SLO", 2, 8, AddressingMode::IndirectX),
+    OpCode::new(0x13, Op::Slo, "*SLO", 2, 8, AddressingMode::IndirectY),
+
+    OpCode::new(0x27, Op::Rla, "*RLA", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x37, Op::Rla, "*RLA", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x2F, Op::Rla, "*RLA", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x3F, Op::Rla, "*RLA", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0x3B, Op::Rla, "*RLA", 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new(0x23, Op::Rla, "*RLA", 2, 8, AddressingMode::IndirectX),
+    OpCode::new(0x33, Op::Rla, "*RLA", 2, 8, AddressingMode::IndirectY),
+
+    OpCode::new(0x47, Op::Sre, "*SRE", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x57, Op::Sre, "*SRE", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x4F, Op::Sre, "*SRE", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x5F, Op::Sre, "*SRE", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0x5B, Op::Sre, "*SRE", 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new(0x43, Op::Sre, "*SRE", 2, 8, AddressingMode::IndirectX),
+    OpCode::new(0x53, Op::Sre, "*SRE", 2, 8, AddressingMode::IndirectY),
+
+    OpCode::new(0x67, Op::Rra, "*RRA", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x77, Op::Rra, "*RRA", 2, 6, AddressingMode::ZeroPageX),
+    OpCode::new(0x6F, Op::Rra, "*RRA", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x7F, Op::Rra, "*RRA", 3, 7, AddressingMode::AbsoluteX),
+    OpCode::new(0x7B, Op::Rra, "*RRA", 3, 7, AddressingMode::AbsoluteY),
+    OpCode::new(0x63, Op::Rra, "*RRA", 2, 8, AddressingMode::IndirectX),
+    OpCode::new(0x73, Op::Rra, "*RRA", 2, 8, AddressingMode::IndirectY),
+
+    OpCode::new(0x0B, Op::Anc, "*ANC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x2B, Op::Anc, "*ANC", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x4B, Op::Alr, "*ALR", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x6B, Op::Arr, "*ARR", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xCB, Op::Axs, "*AXS", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x8B, Op::Xaa, "*XAA", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0xAB, Op::Lxa, "*LXA", 2, 2, AddressingMode::Immediate),
+
+    OpCode::new(0xBB, Op::Las, "*LAS", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteY),
+    OpCode::new(0x9B, Op::Tas, "*TAS", 3, 5, AddressingMode::AbsoluteY),
+    OpCode::new(0x9F, Op::Sha, "*SHA", 3, 5, AddressingMode::AbsoluteY),
+    OpCode::new(0x93, Op::Sha, "*SHA", 2, 6, AddressingMode::IndirectY),
+    OpCode::new(0x9E, Op::Shx, "*SHX", 3, 5, AddressingMode::AbsoluteY),
+    OpCode::new(0x9C, Op::Shy, "*SHY", 3, 5, AddressingMode::AbsoluteX),
+
+    OpCode::new(0x02, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x12, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x22, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x32, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x42, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x52, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x62, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x72, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x92, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xB2, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xD2, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0xF2, Op::Jam, "*JAM", 1, 2, AddressingMode::NoneAddressing),
+];
+
+// http://6502.org/tutorials/65c02opcodes.html
+#[rustfmt::skip]
+pub const CMOS_OPS_CODES: &[OpCode] = &[
+    OpCode::new(0x80, Op::Bra, "BRA", 2, 2/*+1 if branch succeeds +2 if to a new page*/, AddressingMode::NoneAddressing),
+
+    OpCode::new(0xDA, Op::Phx, "PHX", 1, 3, AddressingMode::NoneAddressing),
+    OpCode::new(0x5A, Op::Phy, "PHY", 1, 3, AddressingMode::NoneAddressing),
+    OpCode::new(0xFA, Op::Plx, "PLX", 1, 4, AddressingMode::NoneAddressing),
+    OpCode::new(0x7A, Op::Ply, "PLY", 1, 4, AddressingMode::NoneAddressing),
+
+    OpCode::new(0x64, Op::Stz, "STZ", 2, 3, AddressingMode::ZeroPage),
+    OpCode::new(0x74, Op::Stz, "STZ", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x9C, Op::Stz, "STZ", 3, 4, AddressingMode::Absolute),
+    OpCode::new(0x9E, Op::Stz, "STZ", 3, 5, AddressingMode::AbsoluteX),
+
+    OpCode::new(0x14, Op::Trb, "TRB", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x1C, Op::Trb, "TRB", 3, 6, AddressingMode::Absolute),
+    OpCode::new(0x04, Op::Tsb, "TSB", 2, 5, AddressingMode::ZeroPage),
+    OpCode::new(0x0C, Op::Tsb, "TSB", 3, 6, AddressingMode::Absolute),
+
+    OpCode::new(0x1A, Op::IncA, "INC", 1, 2, AddressingMode::NoneAddressing),
+    OpCode::new(0x3A, Op::DecA, "DEC", 1, 2, AddressingMode::NoneAddressing),
+
+    OpCode::new(0x89, Op::Bit, "BIT", 2, 2, AddressingMode::Immediate),
+    OpCode::new(0x34, Op::Bit, "BIT", 2, 4, AddressingMode::ZeroPageX),
+    OpCode::new(0x3C, Op::Bit, "BIT", 3, 4/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+
+    OpCode::new(0x12, Op::Ora, "ORA", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x32, Op::And, "AND", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x52, Op::Eor, "EOR", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x72, Op::Adc, "ADC", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0x92, Op::Sta, "STA", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0xB2, Op::Lda, "LDA", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0xD2, Op::Cmp, "CMP", 2, 5, AddressingMode::ZeroPageIndirect),
+    OpCode::new(0xF2, Op::Sbc, "SBC", 2, 5, AddressingMode::ZeroPageIndirect),
+
+    OpCode::new(0x7C, Op::JmpIndexedIndirect, "JMP", 3, 6, AddressingMode::NoneAddressing),
+
+    // Fixed indirect JMP takes one more cycle
+    OpCode::new(0x6C, Op::JmpIndirect, "JMP", 3, 6, AddressingMode::NoneAddressing),
+    // Shifts only pay for the index when it crosses a page
+    OpCode::new(0x1E, Op::Asl, "ASL", 3, 6/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0x5E, Op::Lsr, "LSR", 3, 6/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0x3E, Op::Rol, "ROL", 3, 6/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+    OpCode::new(0x7E, Op::Ror, "ROR", 3, 6/*+1 if page crossed*/, AddressingMode::AbsoluteX),
+];
+
+const fn decode_table() -> [Option<OpCode>; 256] {
+    let mut table = [None; 256];
+    let mut i = 0;
+    while i < CPU_OPS_CODES.len() {
+        let opcode = CPU_OPS_CODES[i];
+        table[opcode.code as usize] = Some(opcode);
+        i += 1;
+    }
+    table
+}
+
+// Every opcode the 65C02 does not define is a NOP, of a length and duration
+// that depend on its column.
+// http://www.6502.org/tutorials/65c02opcodes.html#7
+const fn cmos_nop(code: u8) -> OpCode {
+    let (len, cycles, mode) = match code {
+        0x44 => (2, 3, AddressingMode::ZeroPage),
+        0x54 | 0xD4 | 0xF4 => (2, 4, AddressingMode::ZeroPageX),
+        0x5C => (3, 8, AddressingMode::Absolute),
+        0xDC | 0xFC => (3, 4, AddressingMode::Absolute),
+        _ if code & 0x0F == 0x02 => (2, 2, AddressingMode::Immediate),
+        _ if code & 0x03 == 0x03 => (1, 1, AddressingMode::NoneAddressing),
+        _ => panic!("opcode is defined on the 65C02"),
     };
+    OpCode::new(code, Op::Nop, "*NOP", len, cycles, mode)
+}
+
+const fn cmos_decode_table() -> [Option<OpCode>; 256] {
+    let mut table = decode_table();
+    let mut code = 0;
+    while code < table.len() {
+        if let Some(opcode) = table[code] {
+            if opcode.mnemonic.as_bytes()[0] == b'*' {
+                table[code] = None;
+            }
+        }
+        code += 1;
+    }
+
+    let mut i = 0;
+    while i < CMOS_OPS_CODES.len() {
+        let opcode = CMOS_OPS_CODES[i];
+        table[opcode.code as usize] = Some(opcode);
+        i += 1;
+    }
+
+    let mut code = 0;
+    while code < table.len() {
+        if table[code].is_none() {
+            table[code] = Some(cmos_nop(code as u8));
+        }
+        code += 1;
+    }
+    table
+}
+
+/// Opcode decoding table, indexed by opcode and built at compile time.
+pub static OPCODES: [Option<OpCode>; 256] = decode_table();
+
+/// Same as `OPCODES`, for the 65C02.
+pub static CMOS_OPCODES: [Option<OpCode>; 256] = cmos_decode_table();
+
+pub fn decode(variant: CpuVariant, code: u8) -> Option<&'static OpCode> {
+    match variant {
+        CpuVariant::Ricoh2A03 | CpuVariant::Nmos6502 => OPCODES[code as usize].as_ref(),
+        CpuVariant::Cmos65C02 => CMOS_OPCODES[code as usize].as_ref(),
+    }
 }
@@ -0,0 +1,308 @@
+// Cycle profiler: attributes every instruction's cycles to the chain of
+// subroutines (JSR) and interrupt handlers it runs in.
+
+use std::fmt::Write;
+
+use crate::cpu::callstack::{CallEvent, Frame, FrameKind};
+use crate::cpu::StepResult;
+
+// A routine in one calling context: the same routine called from two
+// places has two nodes.
+struct Node {
+    // None for the root, the code running before any call
+    routine: Option<u16>,
+    parent: usize,
+    children: Vec<usize>,
+    calls: u64,
+    self_cycles: u64,
+}
+
+/// Time spent in a routine, over all its calling contexts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RoutineStats {
+    pub addr: u16,
+    pub calls: u64,
+    /// Cycles spent in the routine itself.
+    pub self_cycles: u64,
+    /// Cycles spent in the routine and the ones it calls.
+    pub total_cycles: u64,
+}
+
+/// Off by default, see [`Profiler::start`].
+#[derive(Default)]
+pub struct Profiler {
+    enabled: bool,
+    nodes: Vec<Node>,
+    // the nodes of the frames of the call stack
+    stack: Vec<usize>,
+}
+
+#[allow(dead_code)]
+impl Profiler {
+    /// Starts (or resumes) attributing cycles, with the code running now as
+    /// the root of the call tree.
+    pub fn start(&mut self) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node {
+                routine: None,
+                parent: 0,
+                children: vec![],
+                calls: 0,
+                self_cycles: 0,
+            });
+        }
+        self.enabled = true;
+    }
+
+    pub fn stop(&mut self) {
+        self.enabled = false;
+    }
+
+    /// Forgets everything profiled so far.
+    pub fn reset(&mut self) {
+        *self = Profiler {
+            enabled: self.enabled,
+            ..Default::default()
+        };
+        if self.enabled {
+            self.start();
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.nodes.iter().map(|node| node.self_cycles).sum()
+    }
+
+    // Called after each step, with how it changed the call stack.
+    pub(crate) fn on_step(&mut self, result: &StepResult, call: Option<CallEvent>) {
+        let current = self.stack.last().copied().unwrap_or(0);
+        let cycles = result.cycles as u64;
+        match call {
+            Some(CallEvent::Call(frame)) => {
+                let node = self.enter(current, frame.target);
+                self.stack.push(node);
+                // the interrupt sequence counts for the handler, JSR for
+                // the caller
+                match frame.kind {
+                    FrameKind::Interrupt(_) => self.nodes[node].self_cycles += cycles,
+                    FrameKind::Jsr => self.nodes[current].self_cycles += cycles,
+                }
+            }
+            Some(CallEvent::Return(frames)) => {
+                self.nodes[current].self_cycles += cycles;
+                let depth = self.stack.len().saturating_sub(frames);
+                self.stack.truncate(depth);
+            }
+            None => self.nodes[current].self_cycles += cycles,
+        }
+    }
+
+    // The call stack was dropped, by a reset.
+    pub(crate) fn unwind(&mut self) {
+        self.stack.clear();
+    }
+
+    // A step was undone: leaves the routine it called, or goes back into
+    // the ones it returned from. The cycles it counted stay counted.
+    pub(crate) fn step_back(&mut self, called: bool, returned: &[Frame]) {
+        if called {
+            self.stack.pop();
+        }
+        for frame in returned.iter().rev() {
+            let parent = self.stack.last().copied().unwrap_or(0);
+            let node = self.node(parent, frame.target);
+            self.stack.push(node);
+        }
+    }
+
+    fn enter(&mut self, parent: usize, routine: u16) -> usize {
+        let node = self.node(parent, routine);
+        self.nodes[node].calls += 1;
+        node
+    }
+
+    // The node of `routine` called from `parent`, created on the first call
+    fn node(&mut self, parent: usize, routine: u16) -> usize {
+        let found = self.nodes[parent]
+            .children
+            .iter()
+            .copied()
+            .find(|child| self.nodes[*child].routine == Some(routine));
+        found.unwrap_or_else(|| {
+            self.nodes.push(Node {
+                routine: Some(routine),
+                parent,
+                children: vec![],
+                calls: 0,
+                self_cycles: 0,
+            });
+            let node = self.nodes.len() - 1;
+            self.nodes[parent].children.push(node);
+            node
+        })
+    }
+
+    // Cycles of each node including its callees. Children are always
+    // created after their parent, so one backward pass is enough.
+    fn inclusive_cycles(&self) -> Vec<u64> {
+        let mut cycles: Vec<u64> = self.nodes.iter().map(|node| node.self_cycles).collect();
+        for node in (1..self.nodes.len()).rev() {
+            cycles[self.nodes[node].parent] += cycles[node];
+        }
+        cycles
+    }
+
+    fn path(&self, mut node: usize) -> Vec<u16> {
+        let mut path = vec![];
+        while node != 0 {
+            path.push(self.nodes[node].routine.unwrap());
+            node = self.nodes[node].parent;
+        }
+        path.reverse();
+        path
+    }
+
+    /// Per routine statistics, the most expensive first.
+    pub fn routines(&self) -> Vec<RoutineStats> {
+        let inclusive = self.inclusive_cycles();
+        let mut routines: Vec<RoutineStats> = vec![];
+        for (index, node) in self.nodes.iter().enumerate().skip(1) {
+            let addr = node.routine.unwrap();
+            let i = match routines.iter().position(|r| r.addr == addr) {
+                Some(i) => i,
+                None => {
+                    routines.push(RoutineStats {
+                        addr,
+                        calls: 0,
+                        self_cycles: 0,
+                        total_cycles: 0,
+                    });
+                    routines.len() - 1
+                }
+            };
+            let stats = &mut routines[i];
+            stats.calls += node.calls;
+            stats.self_cycles += node.self_cycles;
+            // recursive calls are already counted by the outer call
+            let path = self.path(node.parent);
+            if !path.contains(&addr) {
+                stats.total_cycles += inclusive[index];
+            }
+        }
+        routines.sort_by(|a, b| {
+            b.total_cycles
+                .cmp(&a.total_cycles)
+                .then(a.addr.cmp(&b.addr))
+        });
+        routines
+    }
+
+    /// The per routine table, as text.
+    pub fn flat_table(&self) -> String {
+        let total = self.total_cycles().max(1) as f64;
+        let mut table = format!(
+            "{:<8} {:>8} {:>12} {:>7} {:>12} {:>7}\n",
+            "routine", "calls", "self", "self%", "total", "total%"
+        );
+        for r in self.routines() {
+            writeln!(
+                table,
+                "${:04X}    {:>8} {:>12} {:>6.2}% {:>12} {:>6.2}%",
+                r.addr,
+                r.calls,
+                r.self_cycles,
+                100.0 * r.self_cycles as f64 / total,
+                r.total_cycles,
+                100.0 * r.total_cycles as f64 / total,
+            )
+            .unwrap();
+        }
+        table
+    }
+
+    /// Self cycles per call stack in the collapsed format of
+    /// flamegraph.pl and inferno: `root;$C000;$C123 1234` per line.
+    pub fn collapsed(&self) -> String {
+        let mut out = String::new();
+        for (index, node) in self.nodes.iter().enumerate() {
+            if node.self_cycles == 0 {
+                continue;
+            }
+            let mut frames = vec![String::from("root")];
+            frames.extend(self.path(index).iter().map(|addr| format!("${:04X}", addr)));
+            writeln!(out, "{} {}", frames.join(";"), node.self_cycles).unwrap();
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::cpu::ram::FlatRam;
+    use crate::cpu::{Cpu, StopReason};
+
+    #[test]
+    fn test_profile_calls_and_interrupts() {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.load_assembly(
+            "
+                     JSR outer      ; $0600
+                     JSR inner
+                     .byte $02      ; JAM
+            outer:   JSR inner      ; $0607
+                     BRK
+                     NOP            ; padding, skipped by RTI
+                     RTS
+            inner:   LDX #$03       ; $060D
+            loop:    DEX
+                     BNE loop
+                     RTS
+            handler: NOP            ; $0613
+                     RTI
+
+                     .org $fffe
+                     .word handler
+            ",
+        )
+        .unwrap();
+        cpu.profiler.start();
+        assert_eq!(cpu.run_until_break(), Ok(StopReason::Halted));
+
+        let routines = cpu.profiler.routines();
+        let stats = |addr: u16| *routines.iter().find(|r| r.addr == addr).unwrap();
+        // twice LDX + 3 DEX + 2 taken BNE + BNE + RTS
+        assert_eq!(stats(0x060d).self_cycles, 2 * (2 + 6 + 6 + 2 + 6));
+        assert_eq!(stats(0x060d).calls, 2);
+        // BRK, NOP, RTI
+        assert_eq!(stats(0x0613).self_cycles, 7 + 2 + 6);
+        assert_eq!(stats(0x0607).self_cycles, 6 + 6);
+        assert_eq!(stats(0x0607).total_cycles, 12 + 22 + 15);
+        assert_eq!(routines[0].addr, 0x0607);
+
+        let collapsed = cpu.profiler.collapsed();
+        let lines: Vec<&str> = collapsed.lines().collect();
+        assert_eq!(
+            lines[1..],
+            [
+                "root;$0607 12",
+                "root;$0607;$060D 22",
+                "root;$0607;$0613 15",
+                "root;$060D 22",
+            ]
+        );
+        // two JSR and the JAM
+        assert_eq!(lines[0], "root 14");
+        assert_eq!(cpu.profiler.total_cycles(), cpu.cycles);
+        assert!(cpu
+            .profiler
+            .flat_table()
+            .lines()
+            .nth(1)
+            .unwrap()
+            .starts_with("$0607"));
+    }
+}
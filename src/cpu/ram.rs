@@ -0,0 +1,42 @@
+use crate::cpu::CpuBus;
+use crate::cpu::Mem;
+
+/// 64 KiB of RAM and nothing else: the simplest bus to run a bare 6502
+/// program against.
+#[allow(dead_code)]
+pub struct FlatRam {
+    memory: Box<[u8; 0x10000]>,
+}
+
+#[allow(dead_code)]
+impl FlatRam {
+    pub fn new() -> Self {
+        FlatRam {
+            memory: Box::new([0; 0x10000]),
+        }
+    }
+
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        let start = addr as usize;
+        let end = (start + data.len()).min(self.memory.len());
+        self.memory[start..end].copy_from_slice(&data[..end - start]);
+    }
+}
+
+impl Default for FlatRam {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mem for FlatRam {
+    fn peek(&self, addr: u16) -> u8 {
+        self.memory[addr as usize]
+    }
+
+    fn mem_write(&mut self, addr: u16, data: u8) {
+        self.memory[addr as usize] = data;
+    }
+}
+
+impl CpuBus for FlatRam {}
@@ -91,7 +91,16 @@ impl From<&Status> for u8 {
 
 impl From<u8> for Status {
     fn from(status: u8) -> Self {
-        let mut res = Status::new();
+        let mut res = Status {
+            carry: false,
+            zero: false,
+            interrupt: false,
+            decimal: false,
+            break0: false,
+            break1: false,
+            overflow: false,
+            negative: false,
+        };
         if (status & (1 << 0)) != 0 {
             res.set(Carry, true)
         }
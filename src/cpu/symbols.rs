@@ -0,0 +1,276 @@
+// Symbol tables, from the debug files of ca65, the name lists of FCEUX or
+// plain `addr=name` lines.
+// https://cc65.github.io/doc/debugging.html
+// https://fceux.com/web/help/NLFilesFormat.html
+
+use std::collections::BTreeMap;
+use std::io;
+use std::path::Path;
+
+// iNES header before the PRG ROM in the output file of ld65
+const HEADER_SIZE: usize = 16;
+const BANK_SIZE: usize = 0x4000;
+
+/// Names of addresses. A name can be limited to one 16k PRG ROM bank, so
+/// that the same CPU address in different banks gets different names.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    names: BTreeMap<(u16, Option<usize>), String>,
+}
+
+fn invalid(line: usize, message: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {}: {}", line + 1, message),
+    )
+}
+
+fn parse_addr(text: &str) -> Option<u16> {
+    let text = text.trim();
+    let digits = text
+        .strip_prefix('$')
+        .or_else(|| text.strip_prefix("0x"))
+        .unwrap_or(text);
+    u16::from_str_radix(digits, 16).ok()
+}
+
+#[allow(dead_code)]
+impl SymbolTable {
+    /// Adds the symbols of a file: a ca65 debug file (`.dbg`), an FCEUX
+    /// name list (`.nl`, its bank taken from names like `game.nes.1.nl`)
+    /// or `addr=name` lines.
+    pub fn load(&mut self, path: impl AsRef<Path>) -> io::Result<()> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("dbg") => self.parse_ca65_dbg(&text),
+            Some("nl") => {
+                // game.nes.1.nl is bank 1, game.nes.1A.nl bank 26 (the
+                // bank is hex), game.nes.ram.nl is RAM
+                let stem = path.file_stem().and_then(|stem| stem.to_str());
+                let bank = stem
+                    .and_then(|stem| stem.rsplit('.').next())
+                    .and_then(|bank| usize::from_str_radix(bank, 16).ok());
+                self.parse_fceux_nl(&text, bank)
+            }
+            _ => self.parse_names(&text),
+        }
+    }
+
+    pub fn insert(&mut self, addr: u16, bank: Option<usize>, name: &str) {
+        self.names.insert((addr, bank), name.to_string());
+    }
+
+    /// The name of `addr` in `bank`, or the name it has in any bank.
+    pub fn get(&self, addr: u16, bank: Option<usize>) -> Option<&str> {
+        let name = match bank {
+            Some(_) => self.names.get(&(addr, bank)),
+            None => None,
+        };
+        name.or_else(|| self.names.get(&(addr, None)))
+            .map(String::as_str)
+    }
+
+    /// Address, bank and name of every symbol, in address order.
+    pub fn iter(&self) -> impl Iterator<Item = (u16, Option<usize>, &str)> {
+        self.names
+            .iter()
+            .map(|((addr, bank), name)| (*addr, *bank, name.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.names.is_empty()
+    }
+
+    /// `addr=name` lines, with an optional bank: `1:C5F5=UpdatePlayer`.
+    /// Lines starting with `#` or `;` are comments.
+    pub fn parse_names(&mut self, text: &str) -> io::Result<()> {
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let (addr, name) = line
+                .split_once('=')
+                .ok_or_else(|| invalid(number, "expected addr=name"))?;
+            let (bank, addr) = match addr.split_once(':') {
+                Some((bank, addr)) => {
+                    let bank = bank.trim().parse();
+                    (
+                        Some(bank.map_err(|_| invalid(number, "invalid bank"))?),
+                        addr,
+                    )
+                }
+                None => (None, addr),
+            };
+            let addr = parse_addr(addr).ok_or_else(|| invalid(number, "invalid address"))?;
+            self.insert(addr, bank, name.trim());
+        }
+        Ok(())
+    }
+
+    /// An FCEUX name list, `$C5F5#UpdatePlayer#comment` lines, for `bank`
+    /// or for RAM.
+    pub fn parse_fceux_nl(&mut self, text: &str, bank: Option<usize>) -> io::Result<()> {
+        for (number, line) in text.lines().enumerate() {
+            // lines starting with \ continue the comment of the previous one
+            if !line.starts_with('$') {
+                continue;
+            }
+            let mut fields = line.splitn(3, '#');
+            let addr = fields.next().unwrap_or("");
+            // an array: $0300/10
+            let addr = addr.split('/').next().unwrap_or(addr);
+            let addr = parse_addr(addr).ok_or_else(|| invalid(number, "invalid address"))?;
+            match fields.next().map(str::trim) {
+                Some(name) if !name.is_empty() => self.insert(addr, bank, name),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// The labels of a ca65 debug file, as written by `ld65 --dbgfile`.
+    /// Labels in segments written to the ROM get the bank of their offset
+    /// in it.
+    pub fn parse_ca65_dbg(&mut self, text: &str) -> io::Result<()> {
+        // segment id -> (start, offset in the output file)
+        let mut segments: BTreeMap<usize, (usize, Option<usize>)> = BTreeMap::new();
+        let mut symbols = vec![];
+        for (number, line) in text.lines().enumerate() {
+            let Some((kind, attributes)) = line.split_once(char::is_whitespace) else {
+                continue;
+            };
+            let attributes = dbg_attributes(attributes);
+            let attribute = |key: &str| {
+                attributes
+                    .iter()
+                    .find(|(k, _)| *k == key)
+                    .map(|(_, value)| *value)
+            };
+            let number_of = |key: &str| -> io::Result<Option<usize>> {
+                attribute(key)
+                    .map(|value| {
+                        let parsed = match value.strip_prefix("0x") {
+                            Some(hex) => usize::from_str_radix(hex, 16),
+                            None => value.parse(),
+                        };
+                        parsed.map_err(|_| invalid(number, &format!("invalid {}", key)))
+                    })
+                    .transpose()
+            };
+            match kind {
+                "seg" => {
+                    let id = number_of("id")?.ok_or_else(|| invalid(number, "missing id"))?;
+                    let start = number_of("start")?.unwrap_or(0);
+                    segments.insert(id, (start, number_of("ooffs")?));
+                }
+                "sym" if attribute("type") == Some("lab") => {
+                    let (Some(name), Some(value)) = (attribute("name"), number_of("val")?) else {
+                        continue;
+                    };
+                    symbols.push((name.to_string(), value, number_of("seg")?));
+                }
+                _ => {}
+            }
+        }
+
+        for (name, value, segment) in symbols {
+            let bank = segment
+                .and_then(|id| segments.get(&id))
+                .and_then(|(start, offset)| {
+                    let offset = (*offset)? + value.checked_sub(*start)?;
+                    Some(offset.checked_sub(HEADER_SIZE)? / BANK_SIZE)
+                })
+                .filter(|_| value >= 0x8000);
+            self.insert(value as u16, bank, &name);
+        }
+        Ok(())
+    }
+}
+
+// The `key=value` attributes of a debug file line, without the quotes of
+// string values.
+fn dbg_attributes(text: &str) -> Vec<(&str, &str)> {
+    let mut attributes = vec![];
+    let mut rest = text.trim();
+    while let Some((key, value)) = rest.split_once('=') {
+        let (value, next) = match value.strip_prefix('"') {
+            Some(quoted) => {
+                let end = quoted.find('"').unwrap_or(quoted.len());
+                let next = quoted.get(end + 1..).unwrap_or("");
+                (&quoted[..end], next)
+            }
+            None => value.split_once(',').map_or((value, ""), |(v, n)| (v, n)),
+        };
+        attributes.push((key.trim(), value));
+        rest = next.trim_start_matches(',');
+    }
+    attributes
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_formats() {
+        let mut symbols = SymbolTable::default();
+        symbols
+            .parse_names("# comment\n0010=player_x\n$C000 = Reset\n1:C000=BankOneEntry\n")
+            .unwrap();
+        symbols
+            .parse_fceux_nl(
+                "$8000#NmiHandler#handles NMI\n\\continued comment\n$8010/4#Table#\n$8020##\n",
+                Some(2),
+            )
+            .unwrap();
+        assert_eq!(symbols.get(0x0010, None), Some("player_x"));
+        assert_eq!(symbols.get(0xc000, Some(0)), Some("Reset"));
+        assert_eq!(symbols.get(0xc000, Some(1)), Some("BankOneEntry"));
+        assert_eq!(symbols.get(0x8000, Some(2)), Some("NmiHandler"));
+        assert_eq!(symbols.get(0x8000, Some(0)), None);
+        assert_eq!(symbols.get(0x8010, Some(2)), Some("Table"));
+        assert_eq!(symbols.len(), 5);
+        assert!(symbols.parse_names("C000 Reset").is_err());
+    }
+
+    #[test]
+    fn test_load_nl_bank_in_hex() {
+        let dir = std::env::temp_dir().join(format!("yane-nl-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut symbols = SymbolTable::default();
+        for (file, name) in [("game.nes.1A.nl", "Banked"), ("game.nes.ram.nl", "Ram")] {
+            std::fs::write(dir.join(file), format!("$8000#{}#\n", name)).unwrap();
+            symbols.load(dir.join(file)).unwrap();
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(symbols.get(0x8000, Some(0x1a)), Some("Banked"));
+        assert_eq!(symbols.get(0x8000, None), Some("Ram"));
+    }
+
+    #[test]
+    fn test_parse_ca65_dbg() {
+        let dbg = r#"version	major=2,minor=0
+info	csym=0,file=1,lib=0,line=10,mod=1,scope=1,seg=3,span=10,sym=4,type=2
+seg	id=0,name="ZEROPAGE",start=0x000000,size=0x0002,addrsize=zeropage,type=rw
+seg	id=1,name="BANK0",start=0x008000,size=0x4000,addrsize=absolute,type=ro,oname="game, final.nes",ooffs=16
+seg	id=2,name="BANK1",start=0x008000,size=0x4000,addrsize=absolute,type=ro,oname="game, final.nes",ooffs=16400
+sym	id=0,name="frame",addrsize=zeropage,scope=0,def=1,val=0x1,seg=0,type=lab
+sym	id=1,name="UpdatePlayer",addrsize=absolute,scope=0,def=2,val=0x8123,seg=1,type=lab
+sym	id=2,name="DrawMap",addrsize=absolute,scope=0,def=3,val=0x8123,seg=2,type=lab
+sym	id=3,name="SPEED",addrsize=zeropage,scope=0,def=4,val=0x3,type=equ
+"#;
+        let mut symbols = SymbolTable::default();
+        symbols.parse_ca65_dbg(dbg).unwrap();
+        assert_eq!(symbols.get(0x0001, None), Some("frame"));
+        assert_eq!(symbols.get(0x8123, Some(0)), Some("UpdatePlayer"));
+        assert_eq!(symbols.get(0x8123, Some(1)), Some("DrawMap"));
+        assert_eq!(symbols.get(0x0003, None), None);
+        assert_eq!(symbols.len(), 3);
+    }
+}
@@ -1,16 +1,29 @@
 use sdl2::sys::SDL_HINT_WINDOWS_ENABLE_MESSAGELOOP;
 
-use crate::cpu::opcodes;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::cpu::disasm::disassemble;
+use crate::cpu::opcodes::{self, Op, OpCode};
 use crate::cpu::AddressingMode;
 use crate::cpu::Cpu;
+use crate::cpu::CpuBus;
+use crate::cpu::CpuVariant;
+use crate::cpu::EmuError;
+use crate::cpu::Interrupt;
 use crate::cpu::Mem;
-use std::collections::HashMap;
+use crate::cpu::{StepEvent, StepResult};
 
-pub fn trace(cpu: &Cpu) -> String {
-    let opcodes: &HashMap<u8, &'static opcodes::OpCode> = &(*opcodes::OPCODES_MAP);
+// PPU, APU and I/O registers, for `TraceConfig::register_writes`
+const REGISTERS: RangeInclusive<u16> = 0x2000..=0x401f;
 
-    let code = cpu.mem_read(cpu.pc);
-    let ops = opcodes.get(&code).unwrap();
+pub fn trace<B: CpuBus>(cpu: &Cpu<B>) -> Result<String, EmuError> {
+    let code = cpu.peek(cpu.pc);
+    let ops =
+        opcodes::decode(cpu.variant, code).ok_or(EmuError::IllegalOpcode { code, pc: cpu.pc })?;
 
     let begin = cpu.pc;
     let mut hex_dump = vec![];
@@ -19,51 +32,66 @@ pub fn trace(cpu: &Cpu) -> String {
     let (mem_addr, stored_value) = match ops.mode {
         AddressingMode::Immediate | AddressingMode::NoneAddressing => (0, 0),
         _ => {
-            let addr = cpu.get_absolute_address(&ops.mode, begin + 1);
-            (addr, cpu.mem_read(addr))
+            let (addr, _) = cpu.get_absolute_address(&ops.mode, begin.wrapping_add(1));
+            (addr, cpu.peek(addr))
         }
     };
 
+    // the name of an operand address, if it has one
+    let name = |addr: u16, hex: String| cpu.label(addr).map_or(hex, String::from);
+
     let tmp = match ops.len {
         1 => match ops.code {
             0x0a | 0x4a | 0x2a | 0x6a => format!("A "),
+            0x1a | 0x3a if cpu.variant == CpuVariant::Cmos65C02 => String::from("A "),
             _ => String::from(""),
         },
         2 => {
-            let address: u8 = cpu.mem_read(begin + 1);
-            // let value = cpu.mem_read(address));
+            let address: u8 = cpu.peek(begin.wrapping_add(1));
+            // let value = cpu.peek(address));
             hex_dump.push(address);
 
             match ops.mode {
-                AddressingMode::Immediate => format!("#${:02x}", address),
-                AddressingMode::ZeroPage => format!("${:02x} = {:02x}", mem_addr, stored_value),
+                AddressingMode::Immediate => format!("#${:02X}", address),
+                AddressingMode::ZeroPage => format!(
+                    "{} = {:02X}",
+                    name(mem_addr, format!("${:02X}", mem_addr)),
+                    stored_value
+                ),
                 AddressingMode::ZeroPageX => format!(
-                    "${:02x},X @ {:02x} = {:02x}",
-                    address, mem_addr, stored_value
+                    "{},X @ {:02X} = {:02X}",
+                    name(address as u16, format!("${:02X}", address)),
+                    mem_addr,
+                    stored_value
                 ),
                 AddressingMode::ZeroPageY => format!(
-                    "${:02x},Y @ {:02x} = {:02x}",
-                    address, mem_addr, stored_value
+                    "{},Y @ {:02X} = {:02X}",
+                    name(address as u16, format!("${:02X}", address)),
+                    mem_addr,
+                    stored_value
                 ),
                 AddressingMode::IndirectX => format!(
-                    "(${:02x},X) @ {:02x} = {:04x} = {:02x}",
+                    "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
                     address,
                     (address.wrapping_add(cpu.x)),
                     mem_addr,
                     stored_value
                 ),
                 AddressingMode::IndirectY => format!(
-                    "(${:02x}),Y = {:04x} @ {:04x} = {:02x}",
+                    "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
                     address,
                     (mem_addr.wrapping_sub(cpu.y as u16)),
                     mem_addr,
                     stored_value
                 ),
+                AddressingMode::ZeroPageIndirect => format!(
+                    "(${:02X}) = {:04X} = {:02X}",
+                    address, mem_addr, stored_value
+                ),
                 AddressingMode::NoneAddressing => {
                     // assuming local jumps: BNE, BVS, etc....
-                    let address: usize =
-                        (begin as usize + 2).wrapping_add((address as i8) as usize);
-                    format!("${:04x}", address)
+                    let address = (begin.wrapping_add(2)).wrapping_add((address as i8) as u16);
+                    name(address, format!("${:04X}", address))
                 }
 
                 _ => panic!(
@@ -73,45 +101,61 @@ pub fn trace(cpu: &Cpu) -> String {
             }
         }
         3 => {
-            let address_low = cpu.mem_read(begin + 1);
-            let address_high = cpu.mem_read(begin + 2);
+            let address_low = cpu.peek(begin.wrapping_add(1));
+            let address_high = cpu.peek(begin.wrapping_add(2));
             hex_dump.push(address_low);
             hex_dump.push(address_high);
 
-            let address = cpu.mem_read_u16(begin + 1);
+            let address = cpu.peek_u16(begin.wrapping_add(1));
 
             match ops.mode {
                 AddressingMode::NoneAddressing => {
                     if ops.code == 0x6c {
                         //jmp indirect
-                        let jmp_addr = if address & 0x00FF == 0x00FF {
-                            let lo = cpu.mem_read(address);
-                            let hi = cpu.mem_read(address & 0xFF00);
-                            (hi as u16) << 8 | (lo as u16)
-                        } else {
-                            cpu.mem_read_u16(address)
-                        };
-
-                        // let jmp_addr = cpu.mem_read_u16(address);
-                        format!("(${:04x}) = {:04x}", address, jmp_addr)
+                        let jmp_addr =
+                            if address & 0x00FF == 0x00FF && cpu.variant != CpuVariant::Cmos65C02 {
+                                let lo = cpu.peek(address);
+                                let hi = cpu.peek(address & 0xFF00);
+                                (hi as u16) << 8 | (lo as u16)
+                            } else {
+                                cpu.peek_u16(address)
+                            };
+
+                        // let jmp_addr = cpu.peek_u16(address);
+                        format!(
+                            "({}) = {:04X}",
+                            name(address, format!("${:04X}", address)),
+                            jmp_addr
+                        )
+                    } else if ops.code == 0x7c && cpu.variant == CpuVariant::Cmos65C02 {
+                        let jmp_addr = cpu.peek_u16(address.wrapping_add(cpu.x as u16));
+                        format!("(${:04X},X) = {:04X}", address, jmp_addr)
                     } else {
-                        format!("${:04x}", address)
+                        name(address, format!("${:04X}", address))
                     }
                 }
                 AddressingMode::Absolute => {
                     if ops.code != /*JSR*/ 0x20 {
-                        format!("${:04x} = {:02x}", mem_addr, stored_value)
+                        format!(
+                            "{} = {:02X}",
+                            name(mem_addr, format!("${:04X}", mem_addr)),
+                            stored_value
+                        )
                     } else {
-                        format!("${:04x}         ", mem_addr)
+                        name(mem_addr, format!("${:04X}", mem_addr))
                     }
                 }
                 AddressingMode::AbsoluteX => format!(
-                    "${:04x},X @ {:04x} = {:02x}",
-                    address, mem_addr, stored_value
+                    "{},X @ {:04X} = {:02X}",
+                    name(address, format!("${:04X}", address)),
+                    mem_addr,
+                    stored_value
                 ),
                 AddressingMode::AbsoluteY => format!(
-                    "${:04x},Y @ {:04x} = {:02x}",
-                    address, mem_addr, stored_value
+                    "{},Y @ {:04X} = {:02X}",
+                    name(address, format!("${:04X}", address)),
+                    mem_addr,
+                    stored_value
                 ),
                 _ => panic!(
                     "unexpected addressing mode {:?} has ops-len 3. code {:02x}",
@@ -124,42 +168,313 @@ pub fn trace(cpu: &Cpu) -> String {
 
     let hex_str = hex_dump
         .iter()
-        .map(|z| format!("{:02x}", z))
+        .map(|z| format!("{:02X}", z))
         .collect::<Vec<String>>()
         .join(" ");
-    let asm_str = format!("{:04x}  {:8} {: >4} {}", begin, hex_str, ops.mnemonic, tmp)
+    let asm_str = format!("{:04X}  {:8} {: >4} {}", begin, hex_str, ops.mnemonic, tmp)
         .trim()
         .to_string();
 
     let ps: u8 = (&cpu.ps).into();
-    format!(
-        "{:47} A:{:02x} X:{:02x} Y:{:02x} P:{:02x} SP:{:02x}",
+    Ok(format!(
+        "{:47} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
         asm_str, cpu.a, cpu.x, cpu.y, ps, cpu.sp,
-    )
-    .to_ascii_uppercase()
+    ))
+}
+
+/// Layout of the lines written by a [`Tracer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TraceFormat {
+    /// The nestest.log layout of [`trace`], followed by the cycle count.
+    #[default]
+    Nestest,
+    /// The default layout of the Mesen trace logger, without the PPU
+    /// columns.
+    Mesen,
+    /// The layout of the FCEUX trace logger.
+    Fceux,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name.to_ascii_lowercase().as_str() {
+            "nestest" => Ok(TraceFormat::Nestest),
+            "mesen" => Ok(TraceFormat::Mesen),
+            "fceux" => Ok(TraceFormat::Fceux),
+            _ => Err(format!("unknown trace format {}", name)),
+        }
+    }
+}
+
+/// What a [`Tracer`] writes. By default every instruction, and nothing
+/// else.
+#[derive(Debug, Clone, Default)]
+pub struct TraceConfig {
+    pub format: TraceFormat,
+    /// Only trace instructions in this range.
+    pub pc_range: Option<RangeInclusive<u16>>,
+    /// Only trace instructions in this 16k PRG ROM bank.
+    pub bank: Option<usize>,
+    /// Start tracing when PC reaches this address.
+    pub start_at: Option<u16>,
+    /// Stop tracing when PC reaches this address, until `start_at` is
+    /// reached again.
+    pub stop_at: Option<u16>,
+    /// Also log NMIs and IRQs.
+    pub interrupts: bool,
+    /// Also log the writes to the PPU, APU and I/O registers.
+    pub register_writes: bool,
+    /// End the run after the instruction at this address, see
+    /// [`Tracer::ended`].
+    pub end_after: Option<u16>,
+    /// End the run after this many instructions.
+    pub limit: Option<u64>,
+}
+
+/// Steps a CPU and logs what it executes. Write errors stop the logging
+/// and are reported by [`Tracer::finish`], so that tracing never stops the
+/// emulation.
+pub struct Tracer<W: Write> {
+    config: TraceConfig,
+    out: W,
+    active: bool,
+    executed: u64,
+    ended: bool,
+    error: Option<io::Error>,
+}
+
+#[allow(dead_code)]
+impl Tracer<BufWriter<File>> {
+    pub fn to_file(path: impl AsRef<Path>, config: TraceConfig) -> io::Result<Self> {
+        Ok(Tracer::new(config, BufWriter::new(File::create(path)?)))
+    }
+}
+
+impl<W: Write> Tracer<W> {
+    pub fn new(config: TraceConfig, out: W) -> Self {
+        Tracer {
+            active: config.start_at.is_none(),
+            config,
+            out,
+            executed: 0,
+            ended: false,
+            error: None,
+        }
+    }
+
+    /// Executes one step of `cpu` and logs it. Register writes are taken
+    /// from `cpu.history`, which then records at least one instruction.
+    pub fn step<B: CpuBus>(&mut self, cpu: &mut Cpu<B>) -> Result<StepResult, EmuError> {
+        let pc = cpu.pc;
+        if self.config.start_at == Some(pc) {
+            self.active = true;
+        }
+        if self.config.stop_at == Some(pc) {
+            self.active = false;
+        }
+        // formatted before the step changes the registers
+        let line = if self.active && self.traced(cpu, pc) {
+            self.format(cpu)
+        } else {
+            None
+        };
+        if self.config.register_writes && cpu.history.capacity() == 0 {
+            cpu.history.set_capacity(1);
+        }
+
+        let result = cpu.step()?;
+        if !matches!(result.event, Some(StepEvent::Interrupt(interrupt)) if interrupt != Interrupt::Brk)
+        {
+            self.executed += 1;
+            self.ended |= self.config.end_after == Some(pc)
+                || self
+                    .config
+                    .limit
+                    .is_some_and(|limit| self.executed >= limit);
+        }
+        if !self.active {
+            return Ok(result);
+        }
+        match result.event {
+            // NMI and IRQ run instead of the instruction at PC
+            Some(StepEvent::Interrupt(interrupt)) if interrupt != Interrupt::Brk => {
+                if self.config.interrupts {
+                    let name = format!("{:?}", interrupt).to_ascii_uppercase();
+                    self.write(format!("-- {} at ${:04X} -> ${:04X}", name, pc, cpu.pc));
+                }
+            }
+            _ => {
+                if let Some(line) = line {
+                    self.write(line);
+                }
+            }
+        }
+        if self.config.register_writes {
+            let record = cpu.history.records().last();
+            let writes = record.into_iter().flat_map(|record| &record.writes);
+            for write in writes.filter(|write| REGISTERS.contains(&write.addr)) {
+                self.write(format!("-- ${:04X} <- ${:02X}", write.addr, write.new));
+            }
+        }
+        Ok(result)
+    }
+
+    /// Whether the run reached `end_after` or `limit`. Programs rarely
+    /// halt, so this is how a traced run ends.
+    pub fn ended(&self) -> bool {
+        self.ended
+    }
+
+    /// Flushes the output, and reports the first write error.
+    pub fn finish(mut self) -> io::Result<()> {
+        match self.error.take() {
+            Some(err) => Err(err),
+            None => self.out.flush(),
+        }
+    }
+
+    fn traced<B: CpuBus>(&self, cpu: &Cpu<B>, pc: u16) -> bool {
+        let in_range = self
+            .config
+            .pc_range
+            .as_ref()
+            .is_none_or(|range| range.contains(&pc));
+        let in_bank = self.config.bank.is_none_or(|bank| {
+            // only a NES bus has PRG ROM banks
+            cpu.bus.as_nes().and_then(|bus| bus.prg_bank(pc)) == Some(bank)
+        });
+        in_range && in_bank
+    }
+
+    fn format<B: CpuBus>(&self, cpu: &Cpu<B>) -> Option<String> {
+        let opcode = opcodes::decode(cpu.variant, cpu.peek(cpu.pc))?;
+        let ps: u8 = (&cpu.ps).into();
+        let bytes: Vec<u8> = (0..opcode.len as u16)
+            .map(|i| cpu.peek(cpu.pc.wrapping_add(i)))
+            .collect();
+        let text = uppercase_hex(&disassemble(cpu, cpu.pc).0);
+        let access = operand_access(cpu, opcode);
+        let line = match self.config.format {
+            TraceFormat::Nestest => format!("{} CYC:{}", trace(cpu).ok()?, cpu.cycles),
+            TraceFormat::Mesen => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("${:02X}", b)).collect();
+                let operand = match access {
+                    Some((addr, false)) => format!(" = ${:02X}", cpu.peek(addr)),
+                    Some((addr, true)) => format!(" [${:04X}] = ${:02X}", addr, cpu.peek(addr)),
+                    None => String::new(),
+                };
+                let code = format!("{:04X}  {:15} {}{}", cpu.pc, bytes.join(" "), text, operand);
+                format!(
+                    "{:49} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CPU Cycle:{}",
+                    code, cpu.a, cpu.x, cpu.y, ps, cpu.sp, cpu.cycles
+                )
+            }
+            TraceFormat::Fceux => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                let operand = match access {
+                    Some((addr, false)) => format!(" = #${:02X}", cpu.peek(addr)),
+                    Some((addr, true)) => format!(" @ ${:04X} = #${:02X}", addr, cpu.peek(addr)),
+                    None => String::new(),
+                };
+                format!(
+                    "A:{:02X} X:{:02X} Y:{:02X} S:{:02X} P:{}  ${:04X}:{:9} {}{}",
+                    cpu.a,
+                    cpu.x,
+                    cpu.y,
+                    cpu.sp,
+                    flags(ps),
+                    cpu.pc,
+                    bytes.join(" "),
+                    text,
+                    operand
+                )
+            }
+        };
+        Some(line)
+    }
+
+    fn write(&mut self, line: String) {
+        if self.error.is_none() {
+            if let Err(err) = writeln!(self.out, "{}", line) {
+                self.error = Some(err);
+            }
+        }
+    }
+}
+
+// The memory operand of the instruction at PC, and whether it is indexed
+// or indirect.
+fn operand_access<B: CpuBus>(cpu: &Cpu<B>, opcode: &OpCode) -> Option<(u16, bool)> {
+    match opcode.mode {
+        AddressingMode::Immediate | AddressingMode::NoneAddressing => None,
+        _ if opcode.op == Op::Jsr => None,
+        ref mode => {
+            let (addr, _) = cpu.get_absolute_address(mode, cpu.pc.wrapping_add(1));
+            let indexed = !matches!(mode, AddressingMode::ZeroPage | AddressingMode::Absolute);
+            Some((addr, indexed))
+        }
+    }
+}
+
+// The status flags like FCEUX shows them: NV-BDIZC, lowercase when clear.
+fn flags(ps: u8) -> String {
+    "nvubdizc"
+        .chars()
+        .enumerate()
+        .map(|(i, flag)| {
+            if ps & (0x80 >> i) != 0 {
+                flag.to_ascii_uppercase()
+            } else {
+                flag
+            }
+        })
+        .collect()
+}
+
+// Hex digits in uppercase, leaving the case of labels
+fn uppercase_hex(text: &str) -> String {
+    let mut hex = false;
+    text.chars()
+        .map(|c| {
+            hex = c == '$' || (hex && c.is_ascii_hexdigit());
+            if hex {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect()
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::cartridge::test::test_rom;
+    use crate::cpu::ram::FlatRam;
 
     #[test]
     fn test_format_trace() {
-        let mut cpu = Cpu::new(test_rom());
-        cpu.bus.mem_write(100, 0xa2);
-        cpu.bus.mem_write(101, 0x01);
-        cpu.bus.mem_write(102, 0xca);
-        cpu.bus.mem_write(103, 0x88);
-        cpu.bus.mem_write(104, 0x00);
-        cpu.pc = 0x64;
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.load_assembly(
+            "
+            .org $64
+            LDX #$01
+            DEX
+            DEY
+            .byte $02 ; *JAM
+            ",
+        )
+        .unwrap();
         cpu.a = 1;
         cpu.x = 2;
         cpu.y = 3;
         let mut result: Vec<String> = vec![];
         cpu.run_with_callback(|cpu| {
-            result.push(trace(cpu));
-        });
+            result.push(trace(cpu).unwrap());
+        })
+        .unwrap();
         assert_eq!(
             "0064  A2 01     LDX #$01                        A:01 X:02 Y:03 P:24 SP:FD",
             result[0]
@@ -176,27 +491,263 @@ mod test {
 
     #[test]
     fn test_format_mem_access() {
-        let mut cpu = Cpu::new(test_rom());
-        // ORA ($33), Y
-        cpu.bus.mem_write(100, 0x11);
-        cpu.bus.mem_write(101, 0x33);
-
-        //data
-        cpu.bus.mem_write(0x33, 00);
-        cpu.bus.mem_write(0x34, 04);
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        cpu.load_assembly(
+            "
+            .org $64
+            ORA ($33),Y
+            .byte $02 ; *JAM
 
-        //target cell
-        cpu.bus.mem_write(0x400, 0xAA);
+            .org $33  ; data
+            .word $0400
 
-        cpu.pc = 0x64;
+            .org $400 ; target cell
+            .byte $AA
+            ",
+        )
+        .unwrap();
         cpu.y = 0;
         let mut result: Vec<String> = vec![];
         cpu.run_with_callback(|cpu| {
-            result.push(trace(cpu));
-        });
+            result.push(trace(cpu).unwrap());
+        })
+        .unwrap();
         assert_eq!(
             "0064  11 33     ORA ($33),Y = 0400 @ 0400 = AA  A:00 X:00 Y:00 P:24 SP:FD",
             result[0]
         );
     }
+
+    #[test]
+    fn test_format_unofficial() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        // unofficial opcodes are not assembled
+        cpu.load_assembly(
+            "
+            .org $64
+            .byte $04, $a9 ; *NOP $a9
+            .byte $b3, $10 ; *LAX ($10),Y
+            .byte $02      ; *JAM
+
+            .org $10
+            .word $0300
+            .org $300
+            .byte $55
+            ",
+        )
+        .unwrap();
+        let mut result: Vec<String> = vec![];
+        cpu.run_with_callback(|cpu| {
+            result.push(trace(cpu).unwrap());
+        })
+        .unwrap();
+        assert_eq!(
+            "0064  04 A9    *NOP $A9 = 00                    A:00 X:00 Y:00 P:24 SP:FD",
+            result[0]
+        );
+        assert_eq!(
+            "0066  B3 10    *LAX ($10),Y = 0300 @ 0300 = 55  A:00 X:00 Y:00 P:24 SP:FD",
+            result[1]
+        );
+        assert_eq!(
+            "0068  02       *JAM                             A:55 X:55 Y:00 P:24 SP:FD",
+            result[2]
+        );
+    }
+
+    #[test]
+    fn test_format_symbols() {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.load_assembly(
+            "
+                    JSR sub         ; $0600
+                    .byte $02
+            sub:    LDA $0300,X     ; $0604
+                    STA $10
+                    BNE sub
+            ",
+        )
+        .unwrap();
+        cpu.symbols.insert(0x0604, None, "UpdatePlayer");
+        cpu.symbols.insert(0x0300, None, "table");
+        cpu.symbols.insert(0x0010, None, "player_x");
+        let mut lines = vec![];
+        for _ in 0..3 {
+            lines.push(trace(&cpu).unwrap());
+            cpu.step().unwrap();
+        }
+        lines.push(trace(&cpu).unwrap());
+        let code: Vec<&str> = lines.iter().map(|line| line[16..47].trim()).collect();
+        assert_eq!(
+            code,
+            [
+                "JSR UpdatePlayer",
+                "LDA table,X @ 0300 = 00",
+                "STA player_x = 00",
+                "BNE UpdatePlayer",
+            ]
+        );
+
+        let config = TraceConfig {
+            format: TraceFormat::Fceux,
+            ..Default::default()
+        };
+        let tracer = Tracer::new(config, vec![]);
+        assert!(tracer.format(&cpu).unwrap().ends_with("BNE UpdatePlayer"));
+    }
+
+    fn trace_all<B: CpuBus>(cpu: &mut Cpu<B>, config: TraceConfig) -> Vec<String> {
+        let mut out = vec![];
+        let mut tracer = Tracer::new(config, &mut out);
+        while tracer.step(cpu).unwrap().event != Some(StepEvent::Halted) {}
+        tracer.finish().unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn test_tracer_formats() {
+        let source = "
+            LDX #$05
+            LDA $0300,X
+            STA $10
+            .byte $02 ; *JAM
+        ";
+        let run = |format| {
+            let mut cpu = Cpu::with_bus(FlatRam::new());
+            cpu.load_assembly(source).unwrap();
+            cpu.bus.mem_write(0x0305, 0x77);
+            trace_all(
+                &mut cpu,
+                TraceConfig {
+                    format,
+                    ..Default::default()
+                },
+            )
+        };
+
+        let lines = run(TraceFormat::Nestest);
+        assert_eq!(
+            lines[1],
+            "0602  BD 00 03  LDA $0300,X @ 0305 = 77         A:00 X:05 Y:00 P:24 SP:FD CYC:2"
+        );
+        let lines = run(TraceFormat::Mesen);
+        assert_eq!(
+            lines[1],
+            "0602  $BD $00 $03     LDA $0300,X [$0305] = $77   A:00 X:05 Y:00 P:24 SP:FD CPU Cycle:2"
+        );
+        assert_eq!(
+            lines[2],
+            "0605  $85 $10         STA $10 = $00               A:77 X:05 Y:00 P:24 SP:FD CPU Cycle:6"
+        );
+        let lines = run(TraceFormat::Fceux);
+        assert_eq!(
+            lines[0],
+            "A:00 X:00 Y:00 S:FD P:nvUbdIzc  $0600:A2 05     LDX #$05"
+        );
+        assert_eq!(
+            lines[1],
+            "A:00 X:05 Y:00 S:FD P:nvUbdIzc  $0602:BD 00 03  LDA $0300,X @ $0305 = #$77"
+        );
+        assert_eq!("mesen".parse(), Ok(TraceFormat::Mesen));
+        assert!("bizhawk".parse::<TraceFormat>().is_err());
+    }
+
+    #[test]
+    fn test_tracer_filters_and_events() {
+        let mut cpu = Cpu::new(test_rom()).unwrap();
+        // the NMI vector of the test ROM is $0101
+        cpu.load_assembly(
+            "
+            .org $64
+            LDX #$21
+            STX $2006
+            INX
+            DEX
+            .byte $02 ; *JAM
+
+            .org $0101
+            RTI
+            ",
+        )
+        .unwrap();
+        let mut out = vec![];
+        let mut tracer = Tracer::new(
+            TraceConfig {
+                format: TraceFormat::Fceux,
+                start_at: Some(0x66),
+                stop_at: Some(0x6a),
+                interrupts: true,
+                register_writes: true,
+                ..Default::default()
+            },
+            &mut out,
+        );
+        while cpu.pc != 0x69 {
+            tracer.step(&mut cpu).unwrap();
+        }
+        cpu.bus.set_nmi(true);
+        while tracer.step(&mut cpu).unwrap().event != Some(StepEvent::Halted) {}
+        assert!(!tracer.ended());
+        tracer.finish().unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines.len(), 5);
+        assert!(lines[0].ends_with("$0066:8E 06 20  STX $2006 = #$00"));
+        assert_eq!(lines[1], "-- $2006 <- $21");
+        assert_eq!(lines[2], "-- NMI at $0069 -> $0101");
+        assert!(lines[3].ends_with("$0101:40        RTI"));
+        assert!(lines[4].ends_with("$0069:E8        INX"));
+
+        // the RAM is not in a PRG ROM bank
+        cpu.reset();
+        cpu.pc = 0x64;
+        let config = TraceConfig {
+            bank: Some(0),
+            ..Default::default()
+        };
+        assert!(trace_all(&mut cpu, config).is_empty());
+        cpu.reset();
+        cpu.pc = 0x64;
+        let config = TraceConfig {
+            pc_range: Some(0x66..=0x69),
+            ..Default::default()
+        };
+        assert_eq!(trace_all(&mut cpu, config).len(), 2);
+    }
+
+    #[test]
+    fn test_tracer_end() {
+        let mut cpu = Cpu::with_bus(FlatRam::new());
+        cpu.load_assembly("loop: INX\nJMP loop").unwrap();
+        for (config, lines) in [
+            (
+                TraceConfig {
+                    end_after: Some(0x0601),
+                    ..Default::default()
+                },
+                2,
+            ),
+            (
+                TraceConfig {
+                    limit: Some(5),
+                    ..Default::default()
+                },
+                5,
+            ),
+        ] {
+            cpu.pc = 0x0600;
+            let mut out = vec![];
+            let mut tracer = Tracer::new(config, &mut out);
+            while !tracer.ended() {
+                tracer.step(&mut cpu).unwrap();
+            }
+            tracer.finish().unwrap();
+            assert_eq!(String::from_utf8(out).unwrap().lines().count(), lines);
+        }
+    }
 }
@@ -0,0 +1,401 @@
+// GDB remote serial protocol stub
+// https://sourceware.org/gdb/current/onlinedocs/gdb.html/Remote-Protocol.html
+//
+// There is no 6502 target description in GDB, registers are sent in this
+// order: A, X, Y, P and SP as one byte each, then PC as two bytes, little
+// endian like every multi-byte value on the 6502.
+
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use crate::cpu::{Cpu, CpuBus, EmuError, Mem, StopReason, WatchKind};
+
+// Registers in `g` packet order, PC being the only 16-bit one
+const REGISTERS: usize = 6;
+// How long the CPU runs between two checks for a break request (^C)
+const RESUME_CYCLES: u64 = 10_000;
+
+// Stop replies, as signal numbers
+const SIGINT: &str = "S02";
+const SIGILL: &str = "S04";
+const SIGTRAP: &str = "S05";
+const SIGSEGV: &str = "S0b";
+
+/// Waits for a debugger on `addr` and serves it until it detaches or
+/// disconnects.
+pub fn listen<B: CpuBus, A: ToSocketAddrs>(cpu: &mut Cpu<B>, addr: A) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    eprintln!("waiting for gdb on {}", listener.local_addr()?);
+    let (stream, peer) = listener.accept()?;
+    eprintln!("gdb connected from {}", peer);
+    serve(cpu, stream)
+}
+
+/// Serves one debugger session over `stream`.
+pub fn serve<B: CpuBus>(cpu: &mut Cpu<B>, stream: TcpStream) -> io::Result<()> {
+    stream.set_nodelay(true)?;
+    let mut stub = GdbStub { cpu, stream };
+    while let Some(packet) = stub.receive()? {
+        let reply = match packet.as_bytes().first() {
+            Some(b'D') => {
+                stub.send("OK")?;
+                return Ok(());
+            }
+            Some(b'k') => return Ok(()),
+            _ => stub.handle(&packet)?,
+        };
+        stub.send(&reply)?;
+    }
+    Ok(())
+}
+
+struct GdbStub<'a, B: CpuBus> {
+    cpu: &'a mut Cpu<B>,
+    stream: TcpStream,
+}
+
+impl<'a, B: CpuBus> GdbStub<'a, B> {
+    fn handle(&mut self, packet: &str) -> io::Result<String> {
+        // an empty packet, or one starting with a non-ASCII character
+        let Some((command, args)) = packet.split_at_checked(1) else {
+            return Ok(String::new());
+        };
+        let reply = match command {
+            "?" => String::from(SIGTRAP),
+            "g" => to_hex(&self.registers()),
+            "G" => match from_hex(args) {
+                Some(bytes) if bytes.len() == REGISTERS + 1 => {
+                    self.set_registers(&bytes);
+                    String::from("OK")
+                }
+                _ => String::from("E01"),
+            },
+            "p" => match parse_hex(args) {
+                Some(n) if n < REGISTERS as u16 => self.register(n as usize),
+                _ => String::from("E01"),
+            },
+            "P" => self
+                .write_register(args)
+                .unwrap_or_else(|| String::from("E01")),
+            "m" => self
+                .read_memory(args)
+                .unwrap_or_else(|| String::from("E01")),
+            "M" => self
+                .write_memory(args)
+                .unwrap_or_else(|| String::from("E01")),
+            "Z" | "z" => self
+                .breakpoint(args, command == "Z")
+                .unwrap_or_else(|| String::from("E01")),
+            "c" => self.resume(false)?,
+            "s" => self.resume(true)?,
+            "H" => String::from("OK"),
+            "q" if args.starts_with("Supported") => String::from("PacketSize=4000"),
+            "q" if args == "Attached" => String::from("1"),
+            // anything else is not supported
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn registers(&self) -> Vec<u8> {
+        let cpu = &self.cpu;
+        let [pc_lo, pc_hi] = cpu.pc.to_le_bytes();
+        vec![cpu.a, cpu.x, cpu.y, (&cpu.ps).into(), cpu.sp, pc_lo, pc_hi]
+    }
+
+    fn set_registers(&mut self, bytes: &[u8]) {
+        let cpu = &mut self.cpu;
+        cpu.a = bytes[0];
+        cpu.x = bytes[1];
+        cpu.y = bytes[2];
+        cpu.ps = bytes[3].into();
+        cpu.sp = bytes[4];
+        cpu.pc = u16::from_le_bytes([bytes[5], bytes[6]]);
+    }
+
+    fn register(&self, n: usize) -> String {
+        let registers = self.registers();
+        match n {
+            5 => to_hex(&registers[5..7]),
+            _ => to_hex(&registers[n..n + 1]),
+        }
+    }
+
+    // P n=value
+    fn write_register(&mut self, args: &str) -> Option<String> {
+        let (n, value) = args.split_once('=')?;
+        let n = parse_hex(n)? as usize;
+        let value = from_hex(value)?;
+        let mut registers = self.registers();
+        let len = if n == 5 { 2 } else { 1 };
+        if n >= REGISTERS || value.len() != len {
+            return None;
+        }
+        registers[n..n + len].copy_from_slice(&value);
+        self.set_registers(&registers);
+        Some(String::from("OK"))
+    }
+
+    // m addr,length
+    fn read_memory(&self, args: &str) -> Option<String> {
+        let (addr, len) = args.split_once(',')?;
+        let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+        let bytes: Vec<u8> = (0..len)
+            .map(|i| self.cpu.peek(addr.wrapping_add(i)))
+            .collect();
+        Some(to_hex(&bytes))
+    }
+
+    // M addr,length:XX...
+    fn write_memory(&mut self, args: &str) -> Option<String> {
+        let (range, data) = args.split_once(':')?;
+        let (addr, len) = range.split_once(',')?;
+        let (addr, len) = (parse_hex(addr)?, parse_hex(len)?);
+        let data = from_hex(data)?;
+        if data.len() != len as usize {
+            return None;
+        }
+        for (i, byte) in data.iter().enumerate() {
+            self.cpu.mem_write(addr.wrapping_add(i as u16), *byte);
+        }
+        // a debugger poking at ROM is not an emulation failure
+        match self.cpu.bus.take_fault() {
+            Some(_) => Some(String::from("E0e")),
+            None => Some(String::from("OK")),
+        }
+    }
+
+    // Z type,addr,kind / z type,addr,kind
+    fn breakpoint(&mut self, args: &str, insert: bool) -> Option<String> {
+        let mut fields = args.split(',');
+        let kind = fields.next()?;
+        let addr = parse_hex(fields.next()?)?;
+        let debugger = &mut self.cpu.debugger;
+        let watches: &[WatchKind] = match kind {
+            // software and hardware breakpoints are the same thing here
+            "0" | "1" => {
+                if insert {
+                    debugger.add_breakpoint(addr);
+                } else {
+                    debugger.remove_breakpoint(addr);
+                }
+                return Some(String::from("OK"));
+            }
+            "2" => &[WatchKind::Write],
+            "3" => &[WatchKind::Read],
+            "4" => &[WatchKind::Read, WatchKind::Write],
+            _ => return Some(String::new()),
+        };
+        let len = parse_hex(fields.next()?)?.max(1);
+        let range = addr..=addr.wrapping_add(len - 1);
+        for &kind in watches {
+            if insert {
+                debugger.add_watchpoint(range.clone(), kind);
+            } else if let Some(index) = debugger
+                .watchpoints()
+                .iter()
+                .position(|w| w.kind == kind && w.range == range)
+            {
+                debugger.remove_watchpoint(index);
+            }
+        }
+        Some(String::from("OK"))
+    }
+
+    fn resume(&mut self, step: bool) -> io::Result<String> {
+        let result = if step {
+            self.cpu.step().map(|_| StopReason::Predicate)
+        } else {
+            loop {
+                match self.cpu.run_for_cycles(RESUME_CYCLES) {
+                    Ok(StopReason::CyclesElapsed) if self.interrupted()? => {
+                        break Ok(StopReason::CyclesElapsed);
+                    }
+                    Ok(StopReason::CyclesElapsed) => continue,
+                    result => break result,
+                }
+            }
+        };
+        let signal = match result {
+            Ok(StopReason::CyclesElapsed) => SIGINT,
+            Ok(StopReason::Halted) | Err(EmuError::IllegalOpcode { .. }) => SIGILL,
+            Ok(_) => SIGTRAP,
+            Err(_) => SIGSEGV,
+        };
+        Ok(String::from(signal))
+    }
+
+    // Checks, without waiting, whether the debugger sent a break request.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(1) => Ok(byte[0] == 0x03),
+            Ok(_) => Err(io::ErrorKind::UnexpectedEof.into()),
+            Err(err) if err.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // Returns the next valid packet, acknowledging it, or None once the
+    // debugger has disconnected.
+    fn receive(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // skip acks and stray break requests until the packet start
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => continue,
+                    None => return Ok(None),
+                }
+            }
+
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            if expected == Some(checksum_of(&data)) {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        loop {
+            self.stream.write_all(packet.as_bytes())?;
+            match self.read_byte()? {
+                Some(b'-') => continue,
+                _ => return Ok(()),
+            }
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn parse_hex(hex: &str) -> Option<u16> {
+    u16::from_str_radix(hex, 16).ok()
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::ram::FlatRam;
+    use std::thread;
+
+    // A minimal client, acknowledging every reply.
+    struct Client {
+        stream: TcpStream,
+    }
+
+    impl Client {
+        fn request(&mut self, data: &str) -> String {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+            self.stream.write_all(packet.as_bytes()).unwrap();
+
+            let mut ack = [0];
+            self.stream.read_exact(&mut ack).unwrap();
+            assert_eq!(ack[0], b'+');
+
+            let mut reply = vec![];
+            let mut byte = [0];
+            loop {
+                self.stream.read_exact(&mut byte).unwrap();
+                if byte[0] == b'#' {
+                    break;
+                }
+                reply.push(byte[0]);
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum).unwrap();
+            self.stream.write_all(b"+").unwrap();
+            assert_eq!(reply[0], b'$');
+            String::from_utf8(reply[1..].to_vec()).unwrap()
+        }
+    }
+
+    fn start(program: &[u8]) -> (Client, thread::JoinHandle<Cpu<FlatRam>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let mut ram = FlatRam::new();
+        ram.load(0x0600, program);
+        let server = thread::spawn(move || {
+            let mut cpu = Cpu::with_bus(ram);
+            cpu.pc = 0x0600;
+            let (stream, _) = listener.accept().unwrap();
+            serve(&mut cpu, stream).unwrap();
+            cpu
+        });
+        let stream = TcpStream::connect(addr).unwrap();
+        (Client { stream }, server)
+    }
+
+    #[test]
+    fn test_session() {
+        // INX; INX; STA $10; INX; JAM
+        let (mut client, server) = start(&[0xe8, 0xe8, 0x85, 0x10, 0xe8, 0x02]);
+        assert_eq!(client.request("qSupported:swbreak+"), "PacketSize=4000");
+        assert_eq!(client.request("?"), "S05");
+        assert_eq!(client.request(""), "");
+        assert_eq!(client.request("\u{e9}"), "");
+        assert_eq!(client.request("g"), "00000024fd0006");
+        assert_eq!(client.request("P0=2a"), "OK");
+        assert_eq!(client.request("p0"), "2a");
+        assert_eq!(client.request("p5"), "0006");
+
+        assert_eq!(client.request("s"), "S05");
+        assert_eq!(client.request("Z0,602,1"), "OK");
+        assert_eq!(client.request("Z2,10,1"), "OK");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("p5"), "0206");
+        assert_eq!(client.request("c"), "S05");
+        assert_eq!(client.request("m10,2"), "2a00");
+        assert_eq!(client.request("z2,10,1"), "OK");
+        assert_eq!(client.request("M11,2:beef"), "OK");
+        assert_eq!(client.request("m10,3"), "2abeef");
+        assert_eq!(client.request("c"), "S04");
+        assert_eq!(client.request("D"), "OK");
+
+        let cpu = server.join().unwrap();
+        assert_eq!(cpu.x, 3);
+    }
+}
@@ -0,0 +1,102 @@
+// https://www.nesdev.org/wiki/Standard_controller
+
+/// Buttons of the standard controller, as bits of the report it shifts out
+/// (A first).
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum JoypadButton {
+    A = 0b0000_0001,
+    B = 0b0000_0010,
+    Select = 0b0000_0100,
+    Start = 0b0000_1000,
+    Up = 0b0001_0000,
+    Down = 0b0010_0000,
+    Left = 0b0100_0000,
+    Right = 0b1000_0000,
+}
+
+pub struct Joypad {
+    strobe: bool,
+    button_index: u8,
+    button_status: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Self {
+        Joypad {
+            strobe: false,
+            button_index: 0,
+            button_status: 0,
+        }
+    }
+
+    /// While strobe (bit 0) is set the shift register keeps reloading, so
+    /// reads keep returning button A.
+    pub fn write(&mut self, data: u8) {
+        self.strobe = data & 1 == 1;
+        if self.strobe {
+            self.button_index = 0;
+        }
+    }
+
+    /// Returns the next button and shifts the report. Once all eight are
+    /// read, an official controller returns 1.
+    pub fn read(&mut self) -> u8 {
+        let response = self.peek();
+        if !self.strobe && self.button_index <= 7 {
+            self.button_index += 1;
+        }
+        response
+    }
+
+    pub fn peek(&self) -> u8 {
+        if self.button_index > 7 {
+            return 1;
+        }
+        (self.button_status >> self.button_index) & 1
+    }
+
+    #[allow(dead_code)]
+    pub fn set_button_pressed_status(&mut self, button: JoypadButton, pressed: bool) {
+        if pressed {
+            self.button_status |= button as u8;
+        } else {
+            self.button_status &= !(button as u8);
+        }
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_shifts_buttons() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::A, true);
+        joypad.set_button_pressed_status(JoypadButton::Start, true);
+        joypad.write(1);
+        joypad.write(0);
+
+        let report: Vec<u8> = (0..10).map(|_| joypad.read()).collect();
+        assert_eq!(report, vec![1, 0, 0, 1, 0, 0, 0, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_strobe_keeps_returning_a() {
+        let mut joypad = Joypad::new();
+        joypad.set_button_pressed_status(JoypadButton::A, true);
+        joypad.write(1);
+        for _ in 0..3 {
+            assert_eq!(joypad.peek(), 1);
+            assert_eq!(joypad.read(), 1);
+        }
+    }
+}
@@ -5,8 +5,16 @@ use sdl2::pixels::PixelFormatEnum;
 use sdl2::EventPump;
 
 use rand::Rng;
+use std::io::{BufWriter, Write};
+use std::path::Path;
 
 mod cpu;
+mod gdb;
+mod joypad;
+mod ppu;
+mod repl;
+use cpu::symbols::SymbolTable;
+use cpu::trace::{TraceConfig, Tracer};
 use cpu::*;
 
 fn handle_user_input(cpu: &mut Cpu, event_pump: &mut EventPump) {
@@ -64,7 +72,7 @@ fn read_screen_state(cpu: &Cpu, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     let mut frame_idx = 0;
     let mut update = false;
     for i in 0x0200..0x600 {
-        let color_idx = cpu.mem_read(i as u16);
+        let color_idx = cpu.peek(i as u16);
         let (b1, b2, b3) = color(color_idx).rgb();
         if frame[frame_idx] != b1 || frame[frame_idx + 1] != b2 || frame[frame_idx + 2] != b3 {
             frame[frame_idx] = b1;
@@ -77,7 +85,335 @@ fn read_screen_state(cpu: &Cpu, frame: &mut [u8; 32 * 3 * 32]) -> bool {
     update
 }
 
+const USAGE: &str = "\
+usage: yane [trace [options]] [rom.nes]
+                                run nestest.nes (or rom.nes) and trace every instruction,
+                                from $C000 for nestest.nes, from the reset vector otherwise
+          --format f            nestest (the default), mesen or fceux
+          --out file            write the trace to file instead of the standard output
+          --pc start-end        only trace instructions in this address range
+          --bank n              only trace instructions in this 16k PRG ROM bank
+          --start addr          start tracing when PC reaches addr
+          --stop addr           stop tracing when PC reaches addr
+          --end addr            end the run after the instruction at addr
+                                (for nestest.nes, $C66E by default)
+          --limit n             end the run after n instructions
+          --interrupts          also log NMIs and IRQs
+          --writes              also log the writes to the PPU, APU and I/O registers
+          --symbols file        name addresses after a symbol file
+       yane dbg rom.nes [symbols...]
+                                debug rom.nes interactively
+       yane dbg prog.asm [symbols...]
+                                debug a 6502 program (Easy6502 syntax) in flat RAM
+       yane gdb rom.nes [port]  serve rom.nes to gdb on localhost (port 6502)
+       yane disasm rom.nes [symbols...]
+                                print a labelled listing of the PRG ROM of rom.nes
+       yane ca65 rom.nes out.s  export rom.nes as ca65 source, with its ld65 config in out.cfg
+       yane cdl rom.nes out.cdl [frames]
+                                run rom.nes (600 frames) and log code and data to out.cdl
+       yane profile rom.nes out.folded [frames]
+                                run rom.nes (600 frames), print the cycles spent per routine
+                                and write the call stacks to out.folded for flamegraph tools
+       yane diff rom.nes golden.log
+                                run rom.nes from the first PC of golden.log, a nestest.log
+                                style trace, and stop at the first line that differs
+symbol files are ca65 debug files (.dbg), FCEUX name lists (.nl, game.nes.N.nl for
+bank N) or addr=name lines, the address optionally prefixed by a bank: 1:C000=Reset
+the cheats of rom.nes are loaded from rom.cht, one code per line followed by an optional
+name: 6 or 8 letter Game Genie codes, or ADDR:VAL RAM freezes (! before a disabled one)";
+
+fn load_rom(rom_path: &str) -> cpu::cartridge::Rom {
+    let rom_data = std::fs::read(rom_path).unwrap_or_else(|err| {
+        eprintln!("failure to load file {}: {}", rom_path, err);
+        std::process::exit(1)
+    });
+    cpu::cartridge::Rom::new(&rom_data).unwrap_or_else(|err| {
+        eprintln!("failure to load {}: {}", rom_path, err);
+        std::process::exit(1)
+    })
+}
+
+fn load(rom_path: &str) -> Cpu {
+    let rom = load_rom(rom_path);
+
+    let mut cpu = Cpu::new(rom).unwrap_or_else(|err| {
+        eprintln!("failure to load {}: {}", rom_path, err);
+        std::process::exit(1)
+    });
+    // the cheats of game.nes are in game.cht
+    let cheat_path = std::path::Path::new(rom_path).with_extension("cht");
+    if cheat_path.exists() {
+        let cheats = cpu.bus.cheats();
+        if let Err(err) = cheats.load(&cheat_path) {
+            eprintln!("failure to load {}: {}", cheat_path.display(), err);
+            std::process::exit(1)
+        }
+        eprintln!(
+            "{} cheats loaded from {}",
+            cheats.list().len(),
+            cheat_path.display()
+        );
+    }
+    cpu.reset();
+    cpu
+}
+
+fn load_symbols(paths: &[&str]) -> SymbolTable {
+    let mut symbols = SymbolTable::default();
+    for path in paths {
+        if let Err(err) = symbols.load(path) {
+            eprintln!("failure to load {}: {}", path, err);
+            std::process::exit(1)
+        }
+    }
+    symbols
+}
+
+fn debug(path: &str, symbol_paths: &[&str]) {
+    let stdin = std::io::stdin();
+    let result = if path.ends_with(".asm") || path.ends_with(".s") {
+        let source = std::fs::read_to_string(path).unwrap_or_else(|err| {
+            eprintln!("failure to load file {}: {}", path, err);
+            std::process::exit(1)
+        });
+        let mut cpu = Cpu::with_bus(cpu::ram::FlatRam::new());
+        if let Err(err) = cpu.load_assembly(&source) {
+            eprintln!("{}: {}", path, err);
+            std::process::exit(1)
+        }
+        cpu.symbols = load_symbols(symbol_paths);
+        repl::run(&mut cpu, stdin.lock(), std::io::stdout())
+    } else {
+        let mut cpu = load(path);
+        cpu.symbols = load_symbols(symbol_paths);
+        repl::run(&mut cpu, stdin.lock(), std::io::stdout())
+    };
+    if let Err(err) = result {
+        eprintln!("{}", err);
+        std::process::exit(1)
+    }
+}
+
+fn gdb_server(rom_path: &str, port: &str) {
+    let port: u16 = port.parse().unwrap_or_else(|_| {
+        eprintln!("invalid port {}", port);
+        std::process::exit(2)
+    });
+    let mut cpu = load(rom_path);
+    if let Err(err) = gdb::listen(&mut cpu, ("127.0.0.1", port)) {
+        eprintln!("{}", err);
+        std::process::exit(1)
+    }
+}
+
+fn disasm(rom_path: &str, symbol_paths: &[&str]) {
+    let rom = load_rom(rom_path);
+    let mut disassembly = cpu::disasm::disassemble_rom(&rom, CpuVariant::default());
+    disassembly.add_symbols(&load_symbols(symbol_paths), |addr| {
+        cpu::disasm::rom_bank(&rom, addr)
+    });
+    print!("{}", disassembly);
+}
+
+fn export_ca65(rom_path: &str, out_path: &str) {
+    let rom_data = std::fs::read(rom_path).unwrap_or_else(|err| {
+        eprintln!("failure to load file {}: {}", rom_path, err);
+        std::process::exit(1)
+    });
+    let export = cpu::ca65::export(&rom_data, CpuVariant::default()).unwrap_or_else(|err| {
+        eprintln!("failure to load {}: {}", rom_path, err);
+        std::process::exit(1)
+    });
+    let out_path = std::path::Path::new(out_path);
+    let result = std::fs::write(out_path, export.source)
+        .and_then(|_| std::fs::write(out_path.with_extension("cfg"), export.config));
+    if let Err(err) = result {
+        eprintln!("failure to write {}: {}", out_path.display(), err);
+        std::process::exit(1)
+    }
+}
+
+// NTSC CPU cycles per frame
+const FRAME_CYCLES: u64 = 29_781;
+
+fn parse_frames(frames: &str) -> u64 {
+    frames.parse().unwrap_or_else(|_| {
+        eprintln!("invalid frame count {}", frames);
+        std::process::exit(2)
+    })
+}
+
+fn code_data_log(rom_path: &str, cdl_path: &str, frames: &str) {
+    let frames = parse_frames(frames);
+    let mut cpu = load(rom_path);
+    // go on with an existing log
+    let result = match std::fs::read(cdl_path) {
+        Ok(bytes) => cpu.bus.load_cdl(&bytes),
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+            cpu.bus.enable_cdl();
+            Ok(())
+        }
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        eprintln!("failure to load {}: {}", cdl_path, err);
+        std::process::exit(1)
+    }
+
+    if let Err(err) = cpu.run_for_cycles(frames * FRAME_CYCLES) {
+        eprintln!("emulation stopped: {}", err);
+    }
+    let cdl = cpu.bus.cdl().unwrap();
+    if let Err(err) = std::fs::write(cdl_path, cdl.to_bytes()) {
+        eprintln!("failure to write {}: {}", cdl_path, err);
+        std::process::exit(1)
+    }
+    let unused: usize = cdl.unused_prg().iter().map(|range| range.len()).sum();
+    println!("{} of {} PRG ROM bytes unused", unused, cdl.prg().len());
+}
+
+fn profile(rom_path: &str, folded_path: &str, frames: &str) {
+    let frames = parse_frames(frames);
+    let mut cpu = load(rom_path);
+    cpu.profiler.start();
+    if let Err(err) = cpu.run_for_cycles(frames * FRAME_CYCLES) {
+        eprintln!("emulation stopped: {}", err);
+    }
+    print!("{}", cpu.profiler.flat_table());
+    if let Err(err) = std::fs::write(folded_path, cpu.profiler.collapsed()) {
+        eprintln!("failure to write {}: {}", folded_path, err);
+        std::process::exit(1)
+    }
+}
+
+fn diff_trace(rom_path: &str, log_path: &str) {
+    let mut cpu = load(rom_path);
+    let log = std::fs::File::open(log_path).unwrap_or_else(|err| {
+        eprintln!("failure to open {}: {}", log_path, err);
+        std::process::exit(1)
+    });
+    match cpu::golden::run(&mut cpu, std::io::BufReader::new(log)) {
+        Ok(outcome) => match outcome.divergence {
+            Some(divergence) => {
+                print!("{}", divergence);
+                std::process::exit(1)
+            }
+            None => println!("{} lines match", outcome.matched),
+        },
+        Err(err) => {
+            eprintln!("failure to read {}: {}", log_path, err);
+            std::process::exit(1)
+        }
+    }
+}
+
+// The trace configuration, symbol files, output file and ROM in the
+// arguments of `yane trace`.
+type TraceOptions<'a> = (TraceConfig, Vec<&'a str>, Option<&'a str>, &'a str);
+
+fn trace_options<'a>(args: &[&'a str]) -> Result<TraceOptions<'a>, String> {
+    let mut config = TraceConfig::default();
+    let mut symbol_paths = vec![];
+    let mut out_path = None;
+    let mut rom_path = "nestest.nes";
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or(format!("{} needs a value", arg));
+        match *arg {
+            "--format" => config.format = value()?.parse()?,
+            "--out" => out_path = Some(*value()?),
+            "--pc" => {
+                let range = value()?;
+                let (start, end) = range
+                    .split_once('-')
+                    .ok_or(format!("invalid range {}", range))?;
+                config.pc_range = Some(repl::parse(start)?..=repl::parse(end)?);
+            }
+            "--bank" => {
+                let bank = value()?;
+                config.bank = Some(bank.parse().map_err(|_| format!("invalid bank {}", bank))?);
+            }
+            "--start" => config.start_at = Some(repl::parse(value()?)?),
+            "--stop" => config.stop_at = Some(repl::parse(value()?)?),
+            "--end" => config.end_after = Some(repl::parse(value()?)?),
+            "--limit" => {
+                let limit = value()?;
+                config.limit = Some(
+                    limit
+                        .parse()
+                        .map_err(|_| format!("invalid count {}", limit))?,
+                );
+            }
+            "--interrupts" => config.interrupts = true,
+            "--writes" => config.register_writes = true,
+            "--symbols" => symbol_paths.push(*value()?),
+            option if option.starts_with('-') => return Err(format!("unknown option {}", option)),
+            path => rom_path = path,
+        }
+    }
+    Ok((config, symbol_paths, out_path, rom_path))
+}
+
+fn trace_to<W: Write>(cpu: &mut Cpu, mut tracer: Tracer<W>) {
+    let result = loop {
+        match tracer.step(cpu) {
+            Ok(result) if result.event == Some(StepEvent::Halted) => break Ok(()),
+            Ok(_) if tracer.ended() => break Ok(()),
+            Ok(_) => {}
+            Err(err) => break Err(err),
+        }
+    };
+    if let Err(err) = tracer.finish() {
+        eprintln!("failure to write the trace: {}", err);
+    }
+    if let Err(err) = result {
+        eprintln!("emulation stopped: {}", err);
+        std::process::exit(1)
+    }
+}
+
 fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["dbg", path, symbol_paths @ ..] => debug(path, symbol_paths),
+        ["disasm", rom_path, symbol_paths @ ..] => disasm(rom_path, symbol_paths),
+        ["cdl", rom_path, cdl_path] => code_data_log(rom_path, cdl_path, "600"),
+        ["cdl", rom_path, cdl_path, frames] => code_data_log(rom_path, cdl_path, frames),
+        ["profile", rom_path, folded_path] => profile(rom_path, folded_path, "600"),
+        ["profile", rom_path, folded_path, frames] => profile(rom_path, folded_path, frames),
+        ["diff", rom_path, log_path] => diff_trace(rom_path, log_path),
+        ["ca65", rom_path, out_path] => export_ca65(rom_path, out_path),
+        ["gdb", rom_path] => gdb_server(rom_path, "6502"),
+        ["gdb", rom_path, port] => gdb_server(rom_path, port),
+        [] => run("nestest.nes", TraceConfig::default(), &[], None),
+        ["trace", options @ ..] => match trace_options(options) {
+            Ok((config, symbol_paths, out_path, rom_path)) => {
+                run(rom_path, config, &symbol_paths, out_path)
+            }
+            Err(err) => {
+                eprintln!("{}\n{}", err, USAGE);
+                std::process::exit(2)
+            }
+        },
+        [rom_path]
+            if !rom_path.starts_with('-')
+                && !["dbg", "gdb", "disasm", "ca65", "cdl", "profile", "diff"]
+                    .contains(rom_path) =>
+        {
+            run(rom_path, TraceConfig::default(), &[], None)
+        }
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2)
+        }
+    }
+}
+
+// The final RTS of nestest's automated run, the last line of nestest.log
+const NESTEST_END: u16 = 0xC66E;
+
+fn run(rom_path: &str, mut config: TraceConfig, symbol_paths: &[&str], out_path: Option<&str>) {
     // SDL2 init
     let sdl_context = sdl2::init().unwrap();
     let video_subsystem = sdl_context.video().unwrap();
@@ -96,21 +432,17 @@ fn main() {
         .create_texture_target(PixelFormatEnum::RGB24, 32, 32)
         .unwrap();
 
-    // TODO: Do CLI to pass in rom name
-    // let rom_path = "snake.nes";
-    let rom_path = "nestest.nes";
-    let rom_name = std::path::Path::new(rom_path);
-    let rom_data =
-        std::fs::read(rom_name).unwrap_or_else(|_| panic!("failure to load file:  {}", rom_path));
-    let rom = match cpu::cartridge::Rom::new(&rom_data) {
-        Ok(rom) => rom,
-        Err(msg) => panic!("{}", msg),
-    };
-
     // Load game
-    let mut cpu = Cpu::new(rom);
-    cpu.reset();
-    cpu.pc = 0xC000;
+    let mut cpu = load(rom_path);
+    cpu.symbols = load_symbols(symbol_paths);
+    // nestest automation mode, other ROMs start at their reset vector
+    let nestest = Path::new(rom_path).file_name() == Some("nestest.nes".as_ref());
+    if nestest {
+        cpu.pc = 0xC000;
+    }
+    if nestest && config.end_after.is_none() && config.limit.is_none() {
+        config.end_after = Some(NESTEST_END);
+    }
 
     let mut screen_state = [0_u8; 32 * 3 * 32];
     let mut rng = rand::thread_rng();
@@ -132,7 +464,17 @@ fn main() {
     });
     */
     // TRACE
-    cpu.run_with_callback(move |cpu| {
-        println!("{}", cpu::trace::trace(cpu));
-    });
+    match out_path {
+        Some(path) => match Tracer::to_file(path, config) {
+            Ok(tracer) => trace_to(&mut cpu, tracer),
+            Err(err) => {
+                eprintln!("failure to create {}: {}", path, err);
+                std::process::exit(1)
+            }
+        },
+        None => trace_to(
+            &mut cpu,
+            Tracer::new(config, BufWriter::new(std::io::stdout())),
+        ),
+    }
 }
@@ -0,0 +1,297 @@
+use crate::cpu::cartridge::Mirroring;
+
+// https://www.nesdev.org/wiki/PPU_registers
+
+// PPUCTRL
+const CTRL_VRAM_ADD_INCREMENT: u8 = 0b0000_0100;
+const CTRL_GENERATE_NMI: u8 = 0b1000_0000;
+
+// PPUSTATUS
+const STATUS_VBLANK_STARTED: u8 = 0b1000_0000;
+
+// https://www.nesdev.org/wiki/PPU_rendering
+const CYCLES_PER_SCANLINE: usize = 341;
+const VBLANK_SCANLINE: u16 = 241;
+const SCANLINES_PER_FRAME: u16 = 262;
+
+/// The PPU as seen from the CPU: its registers and memory, and the VBlank
+/// timing that drives NMI. Nothing is rendered yet.
+pub struct NesPPU {
+    pub chr_rom: Vec<u8>,
+    pub palette_table: [u8; 32],
+    pub vram: [u8; 2048],
+    pub oam_data: [u8; 256],
+    pub mirroring: Mirroring,
+
+    ctrl: u8,
+    mask: u8,
+    status: u8,
+    oam_addr: u8,
+    scroll: (u8, u8),
+    addr: u16,
+    // shared by PPUSCROLL and PPUADDR: false for the first write of a pair
+    w: bool,
+    internal_data_buf: u8,
+    // the last value on the PPU's data bus, what write-only registers read
+    // as (it does not decay here)
+    io_latch: u8,
+
+    scanline: u16,
+    cycles: usize,
+}
+
+impl NesPPU {
+    pub fn new(chr_rom: Vec<u8>, mirroring: Mirroring) -> Self {
+        NesPPU {
+            chr_rom,
+            palette_table: [0; 32],
+            vram: [0; 2048],
+            oam_data: [0; 256],
+            mirroring,
+            ctrl: 0,
+            mask: 0,
+            status: 0,
+            oam_addr: 0,
+            scroll: (0, 0),
+            addr: 0,
+            w: false,
+            internal_data_buf: 0,
+            io_latch: 0,
+            scanline: 0,
+            cycles: 0,
+        }
+    }
+
+    /// Level of the PPU's /NMI output: asserted during VBlank when PPUCTRL
+    /// asks for it.
+    pub fn nmi_line(&self) -> bool {
+        self.status & STATUS_VBLANK_STARTED != 0 && self.ctrl & CTRL_GENERATE_NMI != 0
+    }
+
+    /// Advances the PPU by `cycles` PPU cycles. Returns true when a frame
+    /// is complete.
+    pub fn tick(&mut self, cycles: u8) -> bool {
+        self.cycles += cycles as usize;
+        if self.cycles < CYCLES_PER_SCANLINE {
+            return false;
+        }
+
+        self.cycles -= CYCLES_PER_SCANLINE;
+        self.scanline += 1;
+        if self.scanline == VBLANK_SCANLINE {
+            self.status |= STATUS_VBLANK_STARTED;
+        }
+        if self.scanline >= SCANLINES_PER_FRAME {
+            self.scanline = 0;
+            self.status &= !STATUS_VBLANK_STARTED;
+            return true;
+        }
+        false
+    }
+
+    // Registers
+
+    pub fn write_to_ctrl(&mut self, value: u8) {
+        self.io_latch = value;
+        self.ctrl = value;
+    }
+
+    pub fn write_to_mask(&mut self, value: u8) {
+        self.io_latch = value;
+        self.mask = value;
+    }
+
+    pub fn write_to_oam_addr(&mut self, value: u8) {
+        self.io_latch = value;
+        self.oam_addr = value;
+    }
+
+    pub fn write_to_oam_data(&mut self, value: u8) {
+        self.io_latch = value;
+        self.oam_data[self.oam_addr as usize] = value;
+        self.oam_addr = self.oam_addr.wrapping_add(1);
+    }
+
+    pub fn write_to_scroll(&mut self, value: u8) {
+        self.io_latch = value;
+        if self.w {
+            self.scroll.1 = value;
+        } else {
+            self.scroll.0 = value;
+        }
+        self.w = !self.w;
+    }
+
+    pub fn write_to_ppu_addr(&mut self, value: u8) {
+        self.io_latch = value;
+        if self.w {
+            self.addr = (self.addr & 0xFF00) | value as u16;
+        } else {
+            self.addr = ((value as u16) << 8) | (self.addr & 0x00FF);
+        }
+        self.addr &= 0x3FFF;
+        self.w = !self.w;
+    }
+
+    pub fn write_to_data(&mut self, value: u8) {
+        self.io_latch = value;
+        let addr = self.addr;
+        match addr {
+            0x0000..=0x1FFF => { /* CHR ROM */ }
+            0x2000..=0x3EFF => self.vram[self.mirror_vram_addr(addr) as usize] = value,
+            _ => self.palette_table[Self::palette_index(addr)] = value,
+        }
+        self.increment_vram_addr();
+    }
+
+    /// PPUSTATUS is read-only, writing it only sets the I/O latch.
+    pub fn write_to_status(&mut self, value: u8) {
+        self.io_latch = value;
+    }
+
+    /// Reading PPUSTATUS clears the VBlank flag and the write latch.
+    pub fn read_status(&mut self) -> u8 {
+        let data = self.peek_status();
+        self.status &= !STATUS_VBLANK_STARTED;
+        self.w = false;
+        self.io_latch = data;
+        data
+    }
+
+    pub fn read_oam_data(&self) -> u8 {
+        self.oam_data[self.oam_addr as usize]
+    }
+
+    /// Reading PPUDATA returns the byte buffered by the previous read
+    /// (except for the palette) and moves on to the next address.
+    pub fn read_data(&mut self) -> u8 {
+        let data = self.peek_data();
+        self.io_latch = data;
+        self.internal_data_buf = self.read_vram(self.addr);
+        self.increment_vram_addr();
+        data
+    }
+
+    /// The VRAM address the next PPUDATA access uses.
+    pub fn vram_addr(&self) -> u16 {
+        self.addr
+    }
+
+    /// The flags are the top 3 bits, the others are open bus.
+    pub fn peek_status(&self) -> u8 {
+        (self.status & 0xE0) | (self.io_latch & 0x1F)
+    }
+
+    /// What reading a write-only register gives: the last value written to
+    /// or read from a register.
+    pub fn open_bus(&self) -> u8 {
+        self.io_latch
+    }
+
+    pub fn peek_data(&self) -> u8 {
+        match self.addr {
+            0x3F00..=0x3FFF => self.read_vram(self.addr),
+            _ => self.internal_data_buf,
+        }
+    }
+
+    fn increment_vram_addr(&mut self) {
+        let inc = if self.ctrl & CTRL_VRAM_ADD_INCREMENT != 0 {
+            32
+        } else {
+            1
+        };
+        self.addr = self.addr.wrapping_add(inc) & 0x3FFF;
+    }
+
+    fn read_vram(&self, addr: u16) -> u8 {
+        match addr {
+            0x0000..=0x1FFF => self.chr_rom.get(addr as usize).copied().unwrap_or(0),
+            0x2000..=0x3EFF => self.vram[self.mirror_vram_addr(addr) as usize],
+            _ => self.palette_table[Self::palette_index(addr)],
+        }
+    }
+
+    // https://www.nesdev.org/wiki/Mirroring#Nametable_Mirroring
+    fn mirror_vram_addr(&self, addr: u16) -> u16 {
+        let vram_index = (addr & 0x2FFF) - 0x2000;
+        let name_table = vram_index / 0x400;
+        match (&self.mirroring, name_table) {
+            (Mirroring::Vertical, 2) | (Mirroring::Vertical, 3) => vram_index - 0x800,
+            (Mirroring::Horizontal, 1) | (Mirroring::Horizontal, 2) => vram_index - 0x400,
+            (Mirroring::Horizontal, 3) => vram_index - 0x800,
+            _ => vram_index & 0x7FF,
+        }
+    }
+
+    // $3F10/$3F14/$3F18/$3F1C mirror the backdrop entries below them
+    fn palette_index(addr: u16) -> usize {
+        let index = (addr & 0x1F) as usize;
+        match index {
+            0x10 | 0x14 | 0x18 | 0x1C => index - 0x10,
+            _ => index,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_read_status_resets_vblank_and_latch() {
+        let mut ppu = NesPPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.status |= STATUS_VBLANK_STARTED;
+        ppu.write_to_ppu_addr(0x21);
+
+        assert_eq!(ppu.peek_status() >> 7, 1);
+        assert_eq!(ppu.read_status() >> 7, 1);
+        assert_eq!(ppu.read_status() >> 7, 0);
+
+        // the latch was reset, so this is a high byte again
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        assert_eq!(ppu.addr, 0x2305);
+    }
+
+    #[test]
+    fn test_read_data_is_buffered() {
+        let mut ppu = NesPPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.vram[0x0305] = 0x66;
+        ppu.vram[0x0306] = 0x77;
+
+        ppu.write_to_ppu_addr(0x23);
+        ppu.write_to_ppu_addr(0x05);
+        ppu.read_data(); // load into the buffer
+        assert_eq!(ppu.addr, 0x2306);
+        assert_eq!(ppu.peek_data(), 0x66);
+        assert_eq!(ppu.read_data(), 0x66);
+        assert_eq!(ppu.read_data(), 0x77);
+    }
+
+    #[test]
+    fn test_open_bus() {
+        let mut ppu = NesPPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.status |= STATUS_VBLANK_STARTED;
+        ppu.write_to_mask(0x1e);
+        assert_eq!(ppu.open_bus(), 0x1e);
+        assert_eq!(ppu.read_status(), 0x9e);
+        assert_eq!(ppu.open_bus(), 0x9e);
+        ppu.write_to_status(0x42);
+        assert_eq!(ppu.peek_status(), 0x02);
+    }
+
+    #[test]
+    fn test_vblank_nmi() {
+        let mut ppu = NesPPU::new(vec![0; 0x2000], Mirroring::Horizontal);
+        ppu.write_to_ctrl(CTRL_GENERATE_NMI);
+        while ppu.scanline < VBLANK_SCANLINE {
+            assert!(!ppu.nmi_line());
+            ppu.tick(3);
+        }
+        assert!(ppu.nmi_line());
+
+        ppu.read_status();
+        assert!(!ppu.nmi_line());
+    }
+}
@@ -0,0 +1,431 @@
+use std::io::{self, BufRead, Write};
+
+use crate::cpu::disasm::disassemble;
+use crate::cpu::trace::trace;
+use crate::cpu::{BreakReason, Cpu, CpuBus, Mem, StepEvent, StopReason};
+
+// Instructions kept for `back` and `history`, unless the CPU already keeps some
+const HISTORY: usize = 10_000;
+
+const HELP: &str = "\
+commands (numbers are hex, with an optional $ or 0x prefix):
+  s, step [count]        execute instructions
+  bs, back [count]       undo instructions
+  n, next                step over JSR
+  c, continue            run until a breakpoint or halt
+  u, until <addr>        run until PC reaches addr
+  b, break [addr]        set a breakpoint, or list them
+  d, delete <addr>       delete a breakpoint
+  r, regs [reg value]    show registers, or set a, x, y, p, sp or pc
+  m, mem <addr> [len]    hex dump memory
+  l, list [addr] [n]     disassemble around PC, or from addr
+  hi, history [count]    show the last executed instructions
+  bt, backtrace          show the subroutine calls and interrupts PC is in
+  ch, cheat [code [name]]
+                         list cheats, or add a Game Genie or ADDR:VAL RAM freeze code
+  ch, cheat on|off <n>   enable or disable cheat n
+  q, quit
+an empty line repeats the last command";
+
+/// Interactive debugger reading commands from `input`, until `quit` or the
+/// end of the input.
+pub fn run<B: CpuBus, R: BufRead, W: Write>(
+    cpu: &mut Cpu<B>,
+    input: R,
+    mut out: W,
+) -> io::Result<()> {
+    if cpu.history.capacity() == 0 {
+        cpu.history.set_capacity(HISTORY);
+    }
+    writeln!(out, "{}", current(cpu))?;
+    let mut lines = input.lines();
+    let mut last = String::new();
+    loop {
+        write!(out, "(yane) ")?;
+        out.flush()?;
+        let line = match lines.next() {
+            Some(line) => line?,
+            None => return Ok(()),
+        };
+        let line = if line.trim().is_empty() {
+            last.clone()
+        } else {
+            line
+        };
+
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.split_first() {
+            Some((&"q", _)) | Some((&"quit", _)) => return Ok(()),
+            Some((command, args)) => {
+                if let Err(err) = execute(cpu, command, args, &mut out) {
+                    writeln!(out, "{}", err)?;
+                }
+            }
+            None => {}
+        }
+        last = line;
+    }
+}
+
+// Errors of a command are reported and the REPL goes on, I/O errors end it.
+fn execute<B: CpuBus, W: Write>(
+    cpu: &mut Cpu<B>,
+    command: &str,
+    args: &[&str],
+    out: &mut W,
+) -> Result<(), String> {
+    let io = |err: io::Error| err.to_string();
+    match command {
+        "s" | "step" => {
+            let count = args.first().map(|arg| parse(arg)).transpose()?;
+            for _ in 0..count.unwrap_or(1) {
+                let result = cpu.step().map_err(|err| err.to_string())?;
+                if result.event == Some(StepEvent::Halted) {
+                    return report(cpu, StopReason::Halted, out).map_err(io);
+                }
+            }
+            warn_mismatches(cpu, out).map_err(io)?;
+            writeln!(out, "{}", current(cpu)).map_err(io)?;
+        }
+        "bs" | "back" => {
+            let count = args.first().map(|arg| parse(arg)).transpose()?;
+            for _ in 0..count.unwrap_or(1) {
+                if let Err(err) = cpu.step_back() {
+                    writeln!(out, "{}", err).map_err(io)?;
+                    break;
+                }
+            }
+            writeln!(out, "{}", current(cpu)).map_err(io)?;
+        }
+        "n" | "next" => {
+            // JSR: run until it returns to the next instruction, on this stack level
+            let reason = if cpu.peek(cpu.pc) == 0x20 {
+                let (ret, sp) = (cpu.pc.wrapping_add(3), cpu.sp);
+                cpu.run_until(|cpu| cpu.pc == ret && cpu.sp == sp)
+            } else {
+                cpu.run_for_cycles(1)
+            };
+            report(cpu, reason.map_err(|err| err.to_string())?, out).map_err(io)?;
+        }
+        "c" | "continue" => {
+            let reason = cpu.run_until_break().map_err(|err| err.to_string())?;
+            report(cpu, reason, out).map_err(io)?;
+        }
+        "u" | "until" => {
+            let addr = parse(args.first().ok_or("usage: until <addr>")?)?;
+            let reason = cpu.run_until_pc(addr).map_err(|err| err.to_string())?;
+            report(cpu, reason, out).map_err(io)?;
+        }
+        "b" | "break" => match args.first() {
+            Some(arg) => {
+                let addr = parse(arg)?;
+                cpu.debugger.add_breakpoint(addr);
+                writeln!(out, "breakpoint at ${:04X}", addr).map_err(io)?;
+            }
+            None => {
+                for addr in cpu.debugger.breakpoints() {
+                    writeln!(out, "${:04X}", addr).map_err(io)?;
+                }
+            }
+        },
+        "d" | "delete" => {
+            let addr = parse(args.first().ok_or("usage: delete <addr>")?)?;
+            if !cpu.debugger.remove_breakpoint(addr) {
+                return Err(format!("no breakpoint at ${:04X}", addr));
+            }
+        }
+        "r" | "regs" => {
+            if let [reg, value] = args {
+                let value = parse(value)?;
+                let byte = u8::try_from(value).map_err(|_| format!("{} is a byte", reg))?;
+                match reg.to_ascii_lowercase().as_str() {
+                    "a" => cpu.a = byte,
+                    "x" => cpu.x = byte,
+                    "y" => cpu.y = byte,
+                    "p" => cpu.ps = byte.into(),
+                    "sp" => cpu.sp = byte,
+                    "pc" => cpu.pc = value,
+                    _ => return Err(format!("unknown register {}", reg)),
+                }
+            } else if !args.is_empty() {
+                return Err(String::from("usage: regs [reg value]"));
+            }
+            writeln!(out, "{}", registers(cpu)).map_err(io)?;
+        }
+        "m" | "mem" => {
+            let addr = parse(args.first().ok_or("usage: mem <addr> [len]")?)?;
+            let len = args.get(1).map(|arg| parse(arg)).transpose()?;
+            hex_dump(cpu, addr, len.unwrap_or(0x40), out).map_err(io)?;
+        }
+        "l" | "list" => {
+            let (start, count) = match args {
+                [] => (listing_start(cpu, cpu.pc, 4), 10),
+                [addr] => (parse(addr)?, 10),
+                [addr, count, ..] => (parse(addr)?, parse(count)?),
+            };
+            let mut addr = start;
+            for _ in 0..count {
+                let (text, len) = disassemble(cpu, addr);
+                let marker = if addr == cpu.pc { "=>" } else { "  " };
+                writeln!(out, "{} {:04X}  {}", marker, addr, text).map_err(io)?;
+                addr = addr.wrapping_add(len);
+            }
+        }
+        "hi" | "history" => {
+            let count = args.first().map(|arg| parse(arg)).transpose()?;
+            let count = count.map_or(10, usize::from);
+            let skip = cpu.history.len().saturating_sub(count);
+            for record in cpu.history.records().skip(skip) {
+                writeln!(out, "{}", record).map_err(io)?;
+            }
+        }
+        "bt" | "backtrace" => backtrace(cpu, out).map_err(io)?,
+        "ch" | "cheat" => {
+            let cheats = cpu
+                .bus
+                .as_nes_mut()
+                .ok_or("no cheats on this bus")?
+                .cheats();
+            match args {
+                [] => {
+                    for (index, cheat) in cheats.list().iter().enumerate() {
+                        writeln!(out, "{:2} {}", index, cheat).map_err(io)?;
+                    }
+                }
+                [state @ ("on" | "off"), index] => {
+                    let index = parse(index)? as usize;
+                    if !cheats.set_enabled(index, *state == "on") {
+                        return Err(format!("no cheat {}", index));
+                    }
+                }
+                [code, name @ ..] => {
+                    let index = cheats.add(code, &name.join(" "))?;
+                    writeln!(out, "{:2} {}", index, cheats.list()[index]).map_err(io)?;
+                }
+            }
+        }
+        "h" | "help" | "?" => writeln!(out, "{}", HELP).map_err(io)?,
+        _ => return Err(format!("unknown command {}, try help", command)),
+    }
+    Ok(())
+}
+
+pub(crate) fn parse(arg: &str) -> Result<u16, String> {
+    let digits = arg
+        .strip_prefix('$')
+        .or_else(|| arg.strip_prefix("0x"))
+        .unwrap_or(arg);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid number {}", arg))
+}
+
+fn current<B: CpuBus>(cpu: &Cpu<B>) -> String {
+    trace(cpu).unwrap_or_else(|err| err.to_string())
+}
+
+fn registers<B: CpuBus>(cpu: &Cpu<B>) -> String {
+    let ps: u8 = (&cpu.ps).into();
+    format!(
+        "A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PC:{:04X} CYC:{}",
+        cpu.a, cpu.x, cpu.y, ps, cpu.sp, cpu.pc, cpu.cycles
+    )
+}
+
+fn backtrace<B: CpuBus, W: Write>(cpu: &Cpu<B>, out: &mut W) -> io::Result<()> {
+    for line in cpu
+        .call_stack
+        .backtrace(cpu.pc, |addr| cpu.label(addr).map(String::from))
+    {
+        writeln!(out, "{}", line)?;
+    }
+    Ok(())
+}
+
+fn warn_mismatches<B: CpuBus, W: Write>(cpu: &mut Cpu<B>, out: &mut W) -> io::Result<()> {
+    for mismatch in cpu.call_stack.take_mismatches() {
+        writeln!(out, "warning: {}", mismatch)?;
+    }
+    Ok(())
+}
+
+fn report<B: CpuBus, W: Write>(
+    cpu: &mut Cpu<B>,
+    reason: StopReason,
+    out: &mut W,
+) -> io::Result<()> {
+    warn_mismatches(cpu, out)?;
+    match reason {
+        StopReason::Halted => writeln!(out, "halted")?,
+        StopReason::Break(BreakReason::Breakpoint(addr)) => {
+            writeln!(out, "breakpoint at ${:04X}", addr)?
+        }
+        StopReason::Break(BreakReason::Watchpoint { kind, addr, value }) => writeln!(
+            out,
+            "{:?} watchpoint at ${:04X} = {:02X}",
+            kind, addr, value
+        )?,
+        StopReason::Break(BreakReason::Opcode(code)) => writeln!(out, "opcode ${:02X}", code)?,
+        StopReason::Break(BreakReason::Interrupt(interrupt)) => {
+            writeln!(out, "interrupt {:?}", interrupt)?
+        }
+        StopReason::CyclesElapsed | StopReason::PcReached | StopReason::Predicate => {}
+    }
+    writeln!(out, "{}", current(cpu))?;
+    let stopped = matches!(
+        reason,
+        StopReason::Halted | StopReason::Break(BreakReason::Breakpoint(_))
+    );
+    if stopped && !cpu.call_stack.frames().is_empty() {
+        backtrace(cpu, out)?;
+    }
+    Ok(())
+}
+
+fn hex_dump<B: CpuBus, W: Write>(cpu: &Cpu<B>, addr: u16, len: u16, out: &mut W) -> io::Result<()> {
+    for row in (0..len).step_by(16) {
+        let start = addr.wrapping_add(row);
+        let bytes: Vec<u8> = (0..16.min(len - row))
+            .map(|i| cpu.peek(start.wrapping_add(i)))
+            .collect();
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() { b as char } else { '.' })
+            .collect();
+        writeln!(out, "{:04X}  {:47}  {}", start, hex.join(" "), ascii)?;
+    }
+    Ok(())
+}
+
+// Instructions have different lengths, so to list the ones before `pc`,
+// look for the closest address from which decoding lands on `pc`.
+fn listing_start<B: CpuBus>(cpu: &Cpu<B>, pc: u16, before: usize) -> u16 {
+    for back in before as u16..=before as u16 * 3 {
+        let start = pc.wrapping_sub(back);
+        let mut addr = start;
+        let mut count = 0;
+        while addr != pc && count <= back {
+            addr = addr.wrapping_add(disassemble(cpu, addr).1);
+            count += 1;
+        }
+        if addr == pc && count >= before as u16 {
+            return start;
+        }
+    }
+    pc
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::cpu::ram::FlatRam;
+
+    fn session(program: &[u8], commands: &str) -> String {
+        let mut ram = FlatRam::new();
+        ram.load(0x0600, program);
+        let mut cpu = Cpu::with_bus(ram);
+        cpu.pc = 0x0600;
+        let mut out = vec![];
+        run(&mut cpu, commands.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn test_step_over_and_breakpoints() {
+        // JSR $0608; INX; INX; JAM; ...; $0608: INY; RTS
+        let program = [0x20, 0x08, 0x06, 0xe8, 0xe8, 0x02, 0xea, 0xea, 0xc8, 0x60];
+        let out = session(&program, "n\nb 604\nc\nr\nc\n");
+        let lines: Vec<&str> = out.lines().collect();
+        assert!(lines[1].starts_with("(yane) 0603  E8        INX"));
+        assert_eq!(lines[2], "(yane) breakpoint at $0604");
+        assert_eq!(lines[3], "(yane) breakpoint at $0604");
+        assert_eq!(lines[5], "(yane) A:00 X:01 Y:01 P:24 SP:FD PC:0604 CYC:16");
+        assert_eq!(lines[6], "(yane) halted");
+    }
+
+    #[test]
+    fn test_registers_and_memory() {
+        let out = session(&[0xa9, 0x41], "r a 42\nr pc 0\nm 0 12\nr q 1\nl 600 1\n");
+        assert!(out.contains("A:42 X:00 Y:00 P:24 SP:FD PC:0600"));
+        assert!(out.contains("A:42 X:00 Y:00 P:24 SP:FD PC:0000"));
+        assert!(out.contains(&format!(
+            "0000  {:47}  {}",
+            ["00"; 16].join(" "),
+            ".".repeat(16)
+        )));
+        assert!(out.contains(&format!("0010  {:47}  ..", "00 00")));
+        assert!(out.contains("unknown register q"));
+        assert!(out.contains("   0600  LDA #$41"));
+    }
+
+    #[test]
+    fn test_history_and_back() {
+        // LDX #$01; STX $10; INX; JAM
+        let program = [0xa2, 0x01, 0x86, 0x10, 0xe8, 0x02];
+        let out = session(&program, "s 3\nhi 2\nbs 2\nm 10 1\nbs 2\n");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines[2],
+            "(yane) 0602  86 10     A:00 X:01 Y:00 P:24 SP:FD CYC:2 0010:00->01"
+        );
+        assert_eq!(lines[3], "0604  E8        A:00 X:01 Y:00 P:24 SP:FD CYC:5");
+        assert!(lines[4].starts_with("(yane) 0602  86 10     STX $10 = 00"));
+        assert_eq!(lines[5], format!("(yane) 0010  {:47}  .", "00"));
+        assert_eq!(lines[6], "(yane) no more history");
+    }
+
+    #[test]
+    fn test_cheats() {
+        let mut cpu = Cpu::new(crate::cartridge::test::test_rom()).unwrap();
+        let mut out = vec![];
+        let commands = "ch SXIOPO lives\nch 0075:09\nch off 0\nch\nch on 2\nch QQQQQQ\n";
+        run(&mut cpu, commands.as_bytes(), &mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[1], "(yane)  0 on  SXIOPO   lives ($91D9 = $AD)");
+        assert_eq!(lines[2], "(yane)  1 on  0075:09  ($0075 = $09)");
+        assert_eq!(
+            lines[3],
+            "(yane) (yane)  0 off SXIOPO   lives ($91D9 = $AD)"
+        );
+        assert_eq!(lines[5], "(yane) no cheat 2");
+        assert_eq!(lines[6], "(yane) invalid Game Genie code QQQQQQ");
+
+        let out = session(&[], "ch\n");
+        assert!(out.contains("no cheats on this bus"));
+    }
+
+    #[test]
+    fn test_backtrace() {
+        // JSR $0606; JAM; ...; $0606: LDA #$06; PHA; LDA #$0D; PHA; RTS; NOP; JAM
+        #[rustfmt::skip]
+        let program = [
+            0x20, 0x06, 0x06, 0x02, 0xea, 0xea,
+            0xa9, 0x06, 0x48, 0xa9, 0x0d, 0x48, 0x60, 0xea, 0x02,
+        ];
+        let out = session(&program, "b 608\nc\nbt\nc\n");
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[2], "(yane) breakpoint at $0608");
+        assert_eq!(lines[4], "#0  $0608 in $0606");
+        assert_eq!(lines[5], "#1  $0600");
+        assert_eq!(lines[6], "(yane) #0  $0608 in $0606");
+        assert_eq!(
+            lines[8],
+            "(yane) warning: return at $060C to $060E used as a jump"
+        );
+        assert_eq!(lines[9], "halted");
+        // the JAM is still in the subroutine
+        assert_eq!(lines[11], "#0  $060E in $0606");
+    }
+
+    #[test]
+    fn test_listing_around_pc() {
+        // 6 * INX, LDA $1234, 4 * NOP
+        let mut program = vec![0xe8; 6];
+        program.extend([0xad, 0x34, 0x12, 0xea, 0xea, 0xea, 0xea]);
+        let mut ram = FlatRam::new();
+        ram.load(0x0600, &program);
+        let mut cpu = Cpu::with_bus(ram);
+        cpu.pc = 0x0609;
+        assert_eq!(listing_start(&cpu, cpu.pc, 4), 0x0603);
+    }
+}